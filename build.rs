@@ -0,0 +1,93 @@
+// Packs the `initrd` directory into a ustar archive that the kernel embeds
+// with `include_bytes!` and unpacks into the root RamFs at boot.
+
+use std::env;
+use std::fs;
+use std::io;
+use std::path::Path;
+
+const BLOCK: usize = 512;
+
+fn main() {
+    let out = Path::new(&env::var("OUT_DIR").unwrap()).join("initrd.tar");
+    let root = Path::new("initrd");
+    println!("cargo:rerun-if-changed=initrd");
+
+    let mut archive = Vec::new();
+    if root.is_dir() {
+        pack_dir(root, "", &mut archive).expect("failed to pack initrd");
+    }
+    // End of archive: two zero blocks
+    archive.resize(archive.len() + 2 * BLOCK, 0);
+    fs::write(out, archive).expect("failed to write initrd.tar");
+}
+
+fn pack_dir(dir: &Path, prefix: &str, archive: &mut Vec<u8>) -> io::Result<()> {
+    let mut entries: Vec<_> = fs::read_dir(dir)?.collect::<Result<_, _>>()?;
+    entries.sort_by_key(|e| e.file_name());
+
+    for entry in entries {
+        let name = entry.file_name().into_string().expect("non UTF-8 file name in initrd");
+        let path = format!("{}{}", prefix, name);
+        let meta = fs::symlink_metadata(entry.path())?;
+
+        if meta.file_type().is_symlink() {
+            let target = fs::read_link(entry.path())?;
+            let target = target.to_str().expect("non UTF-8 symlink target in initrd");
+            push_header(archive, &path, b'2', 0, target);
+        } else if meta.is_dir() {
+            push_header(archive, &format!("{}/", path), b'5', 0, "");
+            pack_dir(&entry.path(), &format!("{}/", path), archive)?;
+        } else {
+            let data = fs::read(entry.path())?;
+            push_header(archive, &path, b'0', data.len(), "");
+            archive.extend_from_slice(&data);
+            let padding = (BLOCK - data.len() % BLOCK) % BLOCK;
+            archive.resize(archive.len() + padding, 0);
+        }
+    }
+    Ok(())
+}
+
+fn push_header(archive: &mut Vec<u8>, path: &str, typeflag: u8, size: usize, link: &str) {
+    let mut header = [0u8; BLOCK];
+
+    // Names longer than 100 bytes are split at a '/' into prefix and name
+    let (prefix, name) = if path.len() <= 100 {
+        ("", path)
+    } else {
+        let split = path[..path.len().min(156)]
+            .rfind('/')
+            .filter(|&i| path.len() - i - 1 <= 100)
+            .unwrap_or_else(|| panic!("initrd path too long for ustar: {}", path));
+        (&path[..split], &path[split + 1..])
+    };
+    assert!(link.len() <= 100, "initrd symlink target too long: {}", link);
+
+    let mode = if typeflag == b'5' { 0o755 } else { 0o644 };
+    header[..name.len()].copy_from_slice(name.as_bytes());
+    put_octal(&mut header[100..108], mode);
+    put_octal(&mut header[108..116], 0);
+    put_octal(&mut header[116..124], 0);
+    put_octal(&mut header[124..136], size as u64);
+    put_octal(&mut header[136..148], 0);
+    header[156] = typeflag;
+    header[157..157 + link.len()].copy_from_slice(link.as_bytes());
+    header[257..263].copy_from_slice(b"ustar\0");
+    header[263..265].copy_from_slice(b"00");
+    header[345..345 + prefix.len()].copy_from_slice(prefix.as_bytes());
+
+    header[148..156].copy_from_slice(b"        ");
+    let checksum: u64 = header.iter().map(|&b| b as u64).sum();
+    put_octal(&mut header[148..155], checksum);
+    header[155] = b' ';
+
+    archive.extend_from_slice(&header);
+}
+
+/// Writes `value` as zero padded octal followed by a NUL
+fn put_octal(field: &mut [u8], value: u64) {
+    let digits = format!("{:0width$o}", value, width = field.len() - 1);
+    field[..digits.len()].copy_from_slice(digits.as_bytes());
+    field[digits.len()] = 0;
+}
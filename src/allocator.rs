@@ -34,6 +34,25 @@ static ALLOCATOR: Locked<FixedSizeBlockAllocator> = Locked::new(
 pub const HEAP_START: usize = 0x_4444_4444_0000;
 pub const HEAP_SIZE: usize = 100 * 1024;//100 KiB
 
+/// Heap usage as shown in `/proc/meminfo`
+#[derive(Debug, Clone, Copy)]
+pub struct HeapStats {
+    pub size: usize,
+    /// Bytes handed out to live allocations
+    pub used: usize,
+    /// Bytes of freed blocks kept for reuse, not available to other sizes
+    pub cached: usize,
+}
+
+pub fn heap_stats() -> HeapStats {
+    let allocator = ALLOCATOR.lock();
+    HeapStats {
+        size: HEAP_SIZE,
+        used: allocator.used(),
+        cached: allocator.cached(),
+    }
+}
+
 pub struct Locked<A> {
     inner: spin::Mutex<A>,
 }
@@ -12,6 +12,14 @@ fn list_index(layout: &Layout) -> Option<usize> {
     BLOCK_SIZES.iter().position(|&s| s >= required_block_size)
 }
 
+/// How many bytes an allocation of `layout` really takes
+fn allocated_size(layout: &Layout) -> usize {
+    match list_index(layout) {
+        Some(index) => BLOCK_SIZES[index],
+        None => layout.size(),
+    }
+}
+
 struct ListNode {
     next: Option<&'static mut ListNode>,
 }
@@ -19,6 +27,8 @@ struct ListNode {
 pub struct FixedSizeBlockAllocator {
     list_heads: [Option<&'static mut ListNode>; BLOCK_SIZES.len()],
     fallback_allocator: linked_list_allocator::Heap,
+    /// Bytes currently handed out, counting whole blocks for small allocations
+    used: usize,
 }
 
 impl FixedSizeBlockAllocator {
@@ -27,6 +37,7 @@ impl FixedSizeBlockAllocator {
         FixedSizeBlockAllocator {
             list_heads: [EMPTY; BLOCK_SIZES.len()],
             fallback_allocator: linked_list_allocator::Heap::empty(),
+            used: 0,
         }
     }
 
@@ -37,6 +48,28 @@ impl FixedSizeBlockAllocator {
         }
     }
 
+    /// Bytes currently handed out
+    pub fn used(&self) -> usize {
+        self.used
+    }
+
+    /// Bytes of freed blocks sitting in the lists, waiting to be reused
+    pub fn cached(&self) -> usize {
+        self.list_heads
+            .iter()
+            .zip(BLOCK_SIZES)
+            .map(|(head, &size)| {
+                let mut count = 0;
+                let mut node = head.as_deref();
+                while let Some(n) = node {
+                    count += 1;
+                    node = n.next.as_deref();
+                }
+                count * size
+            })
+            .sum()
+    }
+
     fn fallback_alloc(&mut self, layout: Layout) -> *mut u8 {
         match self.fallback_allocator.allocate_first_fit(layout) {
             Ok(ptr) => ptr.as_ptr(),
@@ -48,7 +81,7 @@ impl FixedSizeBlockAllocator {
 unsafe impl GlobalAlloc for Locked<FixedSizeBlockAllocator> {
     unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
         let mut allocator = self.lock();
-        match list_index(&layout) {
+        let ptr = match list_index(&layout) {
             Some(index) => {
                 match allocator.list_heads[index].take() {
                     Some(node) => {
@@ -64,11 +97,16 @@ unsafe impl GlobalAlloc for Locked<FixedSizeBlockAllocator> {
                 }
             }
             None => allocator.fallback_alloc(layout),
+        };
+        if !ptr.is_null() {
+            allocator.used += allocated_size(&layout);
         }
+        ptr
     }
 
     unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
         let mut allocator = self.lock();
+        allocator.used -= allocated_size(&layout);
         match list_index(&layout) {
             Some(index) => {
                 let new_node = ListNode {
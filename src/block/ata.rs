@@ -0,0 +1,384 @@
+extern crate alloc;
+use alloc::{boxed::Box, format, string::String, sync::Arc};
+use core::{future::Future, sync::atomic::{AtomicBool, Ordering}, task::Poll};
+use futures_util::{future::poll_fn, task::AtomicWaker};
+use x86_64::instructions::port::Port;
+use crate::interrupts::{self, TIMER_HZ};
+use super::{check_request, BlockDevice, BlockError, BlockFuture, SECTOR_SIZE};
+
+// Registers relative to the I/O base of a channel
+const REG_DATA: u16 = 0;
+const REG_ERROR: u16 = 1;
+const REG_SECTOR_COUNT: u16 = 2;
+const REG_LBA_LOW: u16 = 3;
+const REG_LBA_MID: u16 = 4;
+const REG_LBA_HIGH: u16 = 5;
+const REG_DRIVE: u16 = 6;
+/// Status when read, command when written. Reading it acknowledges the IRQ.
+const REG_STATUS: u16 = 7;
+const REG_COMMAND: u16 = 7;
+
+const STATUS_ERR: u8 = 0x01;
+const STATUS_DRQ: u8 = 0x08;
+const STATUS_DF: u8 = 0x20;
+const STATUS_BSY: u8 = 0x80;
+
+/// Device control register bit that keeps the drive from raising IRQs
+const CONTROL_NIEN: u8 = 0x02;
+
+const CMD_READ_SECTORS: u8 = 0x20;
+const CMD_READ_SECTORS_EXT: u8 = 0x24;
+const CMD_WRITE_SECTORS: u8 = 0x30;
+const CMD_WRITE_SECTORS_EXT: u8 = 0x34;
+const CMD_FLUSH_CACHE: u8 = 0xE7;
+const CMD_FLUSH_CACHE_EXT: u8 = 0xEA;
+const CMD_IDENTIFY: u8 = 0xEC;
+
+/// Sectors moved by one command, requests are split into pieces this big
+const MAX_SECTORS_PER_COMMAND: usize = 128;
+
+/// Highest sector LBA28 commands can address
+const LBA28_LIMIT: u64 = 1 << 28;
+
+/// How long a drive may take to answer a command
+const IRQ_TIMEOUT_TICKS: u64 = 5 * TIMER_HZ;
+
+/// Status reads while polling before a drive counts as gone
+const POLL_LIMIT: usize = 1_000_000;
+
+/// One IDE channel, a master and a slave drive sharing registers and an IRQ
+struct Channel {
+    io_base: u16,
+    control_base: u16,
+    /// Set by the IRQ handler, cleared before every command
+    irq_fired: AtomicBool,
+    waker: AtomicWaker,
+    /// Taken while a request uses the channel
+    busy: AtomicBool,
+}
+
+static CHANNELS: [Channel; 2] = [Channel::new(0x1F0, 0x3F6), Channel::new(0x170, 0x376)];
+
+/// Releases the channel when the request is done or dropped
+struct ChannelGuard<'a> {
+    channel: &'a Channel,
+}
+
+impl Drop for ChannelGuard<'_> {
+    fn drop(&mut self) {
+        self.channel.busy.store(false, Ordering::Release);
+    }
+}
+
+impl Channel {
+    const fn new(io_base: u16, control_base: u16) -> Self {
+        Channel {
+            io_base,
+            control_base,
+            irq_fired: AtomicBool::new(false),
+            waker: AtomicWaker::new(),
+            busy: AtomicBool::new(false),
+        }
+    }
+
+    fn read_reg(&self, reg: u16) -> u8 {
+        unsafe { Port::new(self.io_base + reg).read() }
+    }
+
+    fn write_reg(&self, reg: u16, value: u8) {
+        unsafe { Port::new(self.io_base + reg).write(value) }
+    }
+
+    /// Status without acknowledging a pending IRQ
+    fn alt_status(&self) -> u8 {
+        unsafe { Port::new(self.control_base).read() }
+    }
+
+    fn set_control(&self, value: u8) {
+        unsafe { Port::new(self.control_base).write(value) }
+    }
+
+    /// Gives the drive the 400ns it needs after selection to update status
+    fn delay(&self) {
+        for _ in 0..4 {
+            self.alt_status();
+        }
+    }
+
+    /// Waits for the channel to be free, retrying on every poll like
+    /// `fs::FsFuture` does for busy locks
+    fn lock(&self) -> impl Future<Output = ChannelGuard<'_>> + '_ {
+        poll_fn(move |cx| {
+            match self.busy.compare_exchange(false, true, Ordering::Acquire, Ordering::Relaxed) {
+                Ok(_) => Poll::Ready(ChannelGuard { channel: self }),
+                Err(_) => {
+                    cx.waker().wake_by_ref();
+                    Poll::Pending
+                }
+            }
+        })
+    }
+
+    /// Turns a status byte into the outcome of the command
+    fn check(status: u8) -> Result<u8, BlockError> {
+        if status & (STATUS_ERR | STATUS_DF) != 0 {
+            Err(BlockError::DeviceError)
+        } else {
+            Ok(status)
+        }
+    }
+
+    /// Completes with the status once the drive raised its IRQ
+    fn wait_irq(&self) -> impl Future<Output = Result<u8, BlockError>> + '_ {
+        let start = interrupts::ticks();
+        poll_fn(move |cx| {
+            if self.irq_fired.swap(false, Ordering::Acquire) {
+                return Poll::Ready(Self::check(self.alt_status()));
+            }
+            self.waker.register(cx.waker());
+            if self.irq_fired.swap(false, Ordering::Acquire) {
+                self.waker.take();
+                return Poll::Ready(Self::check(self.alt_status()));
+            }
+            // Only noticed when something polls again, `block_on` does on
+            // every timer tick
+            if interrupts::ticks() - start > IRQ_TIMEOUT_TICKS {
+                return Poll::Ready(Err(BlockError::TimedOut));
+            }
+            Poll::Pending
+        })
+    }
+
+    /// Busy-waits until BSY clears and then for DRQ, used where the drive
+    /// raises no IRQ
+    fn poll_drq(&self) -> Result<(), BlockError> {
+        for _ in 0..POLL_LIMIT {
+            let status = self.alt_status();
+            if status & STATUS_BSY != 0 {
+                continue;
+            }
+            Self::check(status)?;
+            if status & STATUS_DRQ != 0 {
+                return Ok(());
+            }
+        }
+        Err(BlockError::TimedOut)
+    }
+
+    fn read_data(&self, sector: &mut [u8]) {
+        let mut data: Port<u16> = Port::new(self.io_base + REG_DATA);
+        for word in sector.chunks_exact_mut(2) {
+            word.copy_from_slice(&unsafe { data.read() }.to_le_bytes());
+        }
+    }
+
+    fn write_data(&self, sector: &[u8]) {
+        let mut data: Port<u16> = Port::new(self.io_base + REG_DATA);
+        for word in sector.chunks_exact(2) {
+            unsafe { data.write(u16::from_le_bytes([word[0], word[1]])) };
+        }
+    }
+}
+
+/// Called from the IRQ14 and IRQ15 handlers
+pub(crate) fn handle_irq(channel: usize) {
+    let channel = &CHANNELS[channel];
+    // Reading the status register tells the drive the IRQ was seen
+    channel.read_reg(REG_STATUS);
+    channel.irq_fired.store(true, Ordering::Release);
+    channel.waker.wake();
+}
+
+/// A hard disk on one of the two legacy IDE channels, driven with PIO
+pub struct AtaDrive {
+    name: String,
+    model: String,
+    channel: usize,
+    slave: bool,
+    sectors: u64,
+    lba48: bool,
+}
+
+impl AtaDrive {
+    fn channel(&self) -> &'static Channel {
+        &CHANNELS[self.channel]
+    }
+
+    /// Sends IDENTIFY and reads the drive's answer, `None` if nothing usable
+    /// is attached. Runs with the channel's IRQ disabled.
+    fn identify(channel_index: usize, slave: bool) -> Option<AtaDrive> {
+        let channel = &CHANNELS[channel_index];
+        channel.write_reg(REG_DRIVE, 0xA0 | (slave as u8) << 4);
+        channel.delay();
+        for reg in [REG_SECTOR_COUNT, REG_LBA_LOW, REG_LBA_MID, REG_LBA_HIGH] {
+            channel.write_reg(reg, 0);
+        }
+        channel.write_reg(REG_COMMAND, CMD_IDENTIFY);
+        if channel.read_reg(REG_STATUS) == 0 {
+            return None;
+        }
+
+        for _ in 0..POLL_LIMIT {
+            if channel.alt_status() & STATUS_BSY == 0 {
+                break;
+            }
+        }
+        // ATAPI and SATA devices put a signature here and need other commands
+        if channel.read_reg(REG_LBA_MID) != 0 || channel.read_reg(REG_LBA_HIGH) != 0 {
+            return None;
+        }
+        channel.poll_drq().ok()?;
+
+        let mut raw = [0u8; SECTOR_SIZE];
+        channel.read_data(&mut raw);
+        let word = |i: usize| u16::from_le_bytes([raw[2 * i], raw[2 * i + 1]]);
+
+        let lba48 = word(83) & (1 << 10) != 0;
+        let sectors = if lba48 {
+            (0..4).map(|i| (word(100 + i) as u64) << (16 * i)).sum()
+        } else {
+            word(60) as u64 | (word(61) as u64) << 16
+        };
+        if sectors == 0 {
+            return None;
+        }
+
+        // The model string is stored with the bytes of every word swapped
+        let model: String = (27..47)
+            .flat_map(|i| word(i).to_be_bytes())
+            .map(|b| b as char)
+            .collect();
+
+        let letter = (b'a' + (channel_index * 2 + slave as usize) as u8) as char;
+        Some(AtaDrive {
+            name: format!("hd{}", letter),
+            model: String::from(model.trim()),
+            channel: channel_index,
+            slave,
+            sectors,
+            lba48,
+        })
+    }
+
+    /// Selects the drive and sends `command` for `count` sectors at `lba`
+    fn issue(&self, lba: u64, count: usize, command: u8, command_ext: u8) {
+        let channel = self.channel();
+        channel.irq_fired.store(false, Ordering::Release);
+        let slave = (self.slave as u8) << 4;
+        let use_lba48 = self.lba48 && lba + count as u64 > LBA28_LIMIT;
+
+        if use_lba48 {
+            channel.write_reg(REG_DRIVE, 0x40 | slave);
+            channel.delay();
+            // High bytes first, the registers are two deep FIFOs
+            channel.write_reg(REG_SECTOR_COUNT, (count >> 8) as u8);
+            channel.write_reg(REG_LBA_LOW, (lba >> 24) as u8);
+            channel.write_reg(REG_LBA_MID, (lba >> 32) as u8);
+            channel.write_reg(REG_LBA_HIGH, (lba >> 40) as u8);
+        } else {
+            channel.write_reg(REG_DRIVE, 0xE0 | slave | (lba >> 24) as u8 & 0x0F);
+            channel.delay();
+        }
+        channel.write_reg(REG_SECTOR_COUNT, count as u8);
+        channel.write_reg(REG_LBA_LOW, lba as u8);
+        channel.write_reg(REG_LBA_MID, (lba >> 8) as u8);
+        channel.write_reg(REG_LBA_HIGH, (lba >> 16) as u8);
+        channel.write_reg(REG_COMMAND, if use_lba48 { command_ext } else { command });
+    }
+
+    async fn read(&self, lba: u64, buf: &mut [u8]) -> Result<(), BlockError> {
+        check_request(self, lba, buf.len())?;
+        let channel = self.channel();
+        let _guard = channel.lock().await;
+
+        let mut start = lba;
+        for chunk in buf.chunks_mut(MAX_SECTORS_PER_COMMAND * SECTOR_SIZE) {
+            let count = chunk.len() / SECTOR_SIZE;
+            self.issue(start, count, CMD_READ_SECTORS, CMD_READ_SECTORS_EXT);
+            // One IRQ per sector, each announcing its data
+            for sector in chunk.chunks_exact_mut(SECTOR_SIZE) {
+                channel.wait_irq().await?;
+                channel.read_data(sector);
+            }
+            start += count as u64;
+        }
+        Ok(())
+    }
+
+    async fn write(&self, lba: u64, data: &[u8]) -> Result<(), BlockError> {
+        check_request(self, lba, data.len())?;
+        let channel = self.channel();
+        let _guard = channel.lock().await;
+
+        let mut start = lba;
+        for chunk in data.chunks(MAX_SECTORS_PER_COMMAND * SECTOR_SIZE) {
+            let count = chunk.len() / SECTOR_SIZE;
+            self.issue(start, count, CMD_WRITE_SECTORS, CMD_WRITE_SECTORS_EXT);
+            // The first sector is requested without an IRQ, every later
+            // one and the end of the command raise one
+            channel.poll_drq()?;
+            for sector in chunk.chunks_exact(SECTOR_SIZE) {
+                channel.write_data(sector);
+                channel.wait_irq().await?;
+            }
+            start += count as u64;
+        }
+        Ok(())
+    }
+
+    async fn flush_cache(&self) -> Result<(), BlockError> {
+        let channel = self.channel();
+        let _guard = channel.lock().await;
+        self.issue(0, 0, CMD_FLUSH_CACHE, CMD_FLUSH_CACHE_EXT);
+        channel.wait_irq().await?;
+        Ok(())
+    }
+}
+
+impl BlockDevice for AtaDrive {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn sector_count(&self) -> u64 {
+        self.sectors
+    }
+
+    fn model(&self) -> &str {
+        &self.model
+    }
+
+    fn read_sectors<'a>(&'a self, lba: u64, buf: &'a mut [u8]) -> BlockFuture<'a, ()> {
+        Box::pin(self.read(lba, buf))
+    }
+
+    fn write_sectors<'a>(&'a self, lba: u64, data: &'a [u8]) -> BlockFuture<'a, ()> {
+        Box::pin(self.write(lba, data))
+    }
+
+    fn flush(&self) -> BlockFuture<'_, ()> {
+        Box::pin(self.flush_cache())
+    }
+}
+
+/// Finds the drives on both channels and registers them as hda to hdd
+pub fn init() {
+    interrupts::enable_ata_irqs();
+    for (index, channel) in CHANNELS.iter().enumerate() {
+        // A floating bus reads all ones, nothing is connected
+        if channel.read_reg(REG_STATUS) == 0xFF {
+            continue;
+        }
+        channel.set_control(CONTROL_NIEN);
+        for slave in [false, true] {
+            if let Some(drive) = AtaDrive::identify(index, slave) {
+                super::register(Arc::new(drive));
+            }
+        }
+        channel.set_control(0);
+        // Clear whatever IDENTIFY left behind
+        channel.read_reg(REG_ERROR);
+        channel.read_reg(REG_STATUS);
+        channel.irq_fired.store(false, Ordering::Release);
+    }
+}
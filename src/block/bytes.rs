@@ -0,0 +1,35 @@
+/// Reads and writes index by byte offset and panic past the end of the
+/// buffer, like any out of range index
+pub fn le16(bytes: &[u8], at: usize) -> u16 {
+    u16::from_le_bytes(bytes[at..at + 2].try_into().unwrap())
+}
+
+pub fn le32(bytes: &[u8], at: usize) -> u32 {
+    u32::from_le_bytes(bytes[at..at + 4].try_into().unwrap())
+}
+
+pub fn le64(bytes: &[u8], at: usize) -> u64 {
+    u64::from_le_bytes(bytes[at..at + 8].try_into().unwrap())
+}
+
+pub fn put16(bytes: &mut [u8], at: usize, value: u16) {
+    bytes[at..at + 2].copy_from_slice(&value.to_le_bytes());
+}
+
+pub fn put32(bytes: &mut [u8], at: usize, value: u32) {
+    bytes[at..at + 4].copy_from_slice(&value.to_le_bytes());
+}
+
+pub fn put64(bytes: &mut [u8], at: usize, value: u64) {
+    bytes[at..at + 8].copy_from_slice(&value.to_le_bytes());
+}
+
+#[test_case]
+fn test_bytes() {
+    let mut buf = [0u8; 16];
+    put16(&mut buf, 1, 0xbeef);
+    put32(&mut buf, 3, 0x1234_5678);
+    put64(&mut buf, 7, 0x0102_0304_0506_0708);
+    assert_eq!(buf[..4], [0, 0xef, 0xbe, 0x78]);
+    assert_eq!((le16(&buf, 1), le32(&buf, 3), le64(&buf, 7)), (0xbeef, 0x1234_5678, 0x0102_0304_0506_0708));
+}
@@ -0,0 +1,451 @@
+extern crate alloc;
+use alloc::{boxed::Box, collections::BTreeMap, sync::Arc, vec::Vec};
+use core::{sync::atomic::{AtomicU64, Ordering}, task::Poll};
+use futures_util::{future::poll_fn, task::AtomicWaker};
+use spin::Mutex;
+use crate::{allocator::HEAP_SIZE, interrupts::{self, TIMER_HZ}, println};
+use super::{block_on, check_request, BlockDevice, BlockError, BlockFuture, SECTOR_SIZE};
+
+/// Heap bytes the cache keeps sectors in until `set_budget` changes it
+pub const DEFAULT_BUDGET: usize = 16 * 1024;
+
+/// Largest budget `set_budget` accepts, the rest of the heap is left to
+/// the filesystems and tasks
+pub const MAX_BUDGET: usize = HEAP_SIZE / 4;
+
+/// How often dirty sectors are written back without a `sync`
+const WRITEBACK_INTERVAL_TICKS: u64 = 5 * TIMER_HZ;
+
+/// Sectors copied out and written back at a time
+const WRITEBACK_BATCH: usize = 16;
+
+/// Index of the device in `CacheState::devices` and sector number
+type Key = (usize, u64);
+
+/// A dirty sector copied out for writing back, with the version it had
+type Dirty = (Key, Box<[u8]>, u64);
+
+struct Entry {
+    data: Box<[u8]>,
+    dirty: bool,
+    /// Key of the entry in `CacheState::lru`, renewed on every use
+    stamp: u64,
+    /// Stamp of the last write, a write-back only cleans the version it saw
+    version: u64,
+}
+
+struct CacheState {
+    /// In sectors
+    capacity: usize,
+    devices: Vec<Arc<dyn BlockDevice>>,
+    entries: BTreeMap<Key, Entry>,
+    /// Keys by last use, oldest first
+    lru: BTreeMap<u64, Key>,
+    next_stamp: u64,
+}
+
+impl CacheState {
+    fn stamp(&mut self) -> u64 {
+        self.next_stamp += 1;
+        self.next_stamp
+    }
+
+    /// Marks `key` as just used, returns its entry
+    fn touch(&mut self, key: Key) -> Option<&mut Entry> {
+        let stamp = self.stamp();
+        let entry = self.entries.get_mut(&key)?;
+        self.lru.remove(&entry.stamp);
+        self.lru.insert(stamp, key);
+        entry.stamp = stamp;
+        Some(entry)
+    }
+
+    fn insert(&mut self, key: Key, data: &[u8], dirty: bool) -> Result<(), BlockError> {
+        let data = copy(data)?;
+        let stamp = self.stamp();
+        self.lru.insert(stamp, key);
+        let entry = Entry { data, dirty, stamp, version: stamp };
+        if let Some(old) = self.entries.insert(key, entry) {
+            self.lru.remove(&old.stamp);
+        }
+        Ok(())
+    }
+
+    /// Copies the sector into `buf` if it is cached
+    fn get(&mut self, key: Key, buf: &mut [u8]) -> bool {
+        match self.touch(key) {
+            Some(entry) => {
+                buf.copy_from_slice(&entry.data);
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Adds a sector just read from the device. One written meanwhile is
+    /// newer, it stays and is copied into `sector` instead. Without the
+    /// memory to keep it the sector is simply not cached.
+    fn fill(&mut self, key: Key, sector: &mut [u8]) {
+        if !self.get(key, sector) {
+            let _ = self.insert(key, sector, false);
+        }
+    }
+
+    fn store(&mut self, key: Key, data: &[u8]) -> Result<(), BlockError> {
+        let version = self.stamp();
+        match self.touch(key) {
+            Some(entry) => {
+                entry.data.copy_from_slice(data);
+                entry.dirty = true;
+                entry.version = version;
+                Ok(())
+            }
+            None => self.insert(key, data, true),
+        }
+    }
+
+    /// Drops the least recently used clean sectors until the cache fits its
+    /// budget, false if only dirty ones are left and it still does not
+    fn evict_clean(&mut self) -> bool {
+        while self.entries.len() > self.capacity {
+            let oldest_clean = self.lru.iter().map(|(_, &key)| key).find(|key| !self.entries[key].dirty);
+            let Some(key) = oldest_clean else {
+                return false;
+            };
+            let entry = self.entries.remove(&key).unwrap();
+            self.lru.remove(&entry.stamp);
+        }
+        true
+    }
+
+    /// Copies of up to `WRITEBACK_BATCH` dirty sectors, of one device or
+    /// all, oldest first
+    fn dirty(&self, device: Option<usize>) -> Result<Vec<Dirty>, BlockError> {
+        let mut batch = Vec::new();
+        batch.try_reserve_exact(WRITEBACK_BATCH).map_err(|_| BlockError::NoMemory)?;
+        let dirty = self.lru.values().filter(|key| device.is_none_or(|id| key.0 == id) && self.entries[key].dirty);
+        for key in dirty.take(WRITEBACK_BATCH) {
+            let entry = &self.entries[key];
+            batch.push((*key, copy(&entry.data)?, entry.version));
+        }
+        Ok(batch)
+    }
+}
+
+/// `data` in a heap allocation of its own, `NoMemory` instead of a panic
+/// when the heap is full
+fn copy(data: &[u8]) -> Result<Box<[u8]>, BlockError> {
+    let mut buf = Vec::new();
+    buf.try_reserve_exact(data.len()).map_err(|_| BlockError::NoMemory)?;
+    buf.extend_from_slice(data);
+    Ok(buf.into_boxed_slice())
+}
+
+/// Counters and sizes as shown in `/proc/blockcache`
+#[derive(Debug, Clone, Copy)]
+pub struct CacheStats {
+    pub budget: usize,
+    pub used: usize,
+    pub dirty: usize,
+    pub hits: u64,
+    pub misses: u64,
+    pub writebacks: u64,
+}
+
+/// Sectors of every cached device, sharing one memory budget and evicted
+/// least recently used first
+///
+/// Writes stay in the cache until `sync`, the periodic write-back or
+/// eviction sends them to the device.
+pub struct BlockCache {
+    state: Mutex<CacheState>,
+    hits: AtomicU64,
+    misses: AtomicU64,
+    writebacks: AtomicU64,
+}
+
+/// The cache every device passed to `block::register` goes through
+pub static CACHE: BlockCache = BlockCache::new(DEFAULT_BUDGET);
+
+impl BlockCache {
+    pub const fn new(budget: usize) -> Self {
+        BlockCache {
+            state: Mutex::new(CacheState {
+                capacity: budget / SECTOR_SIZE,
+                devices: Vec::new(),
+                entries: BTreeMap::new(),
+                lru: BTreeMap::new(),
+                next_stamp: 0,
+            }),
+            hits: AtomicU64::new(0),
+            misses: AtomicU64::new(0),
+            writebacks: AtomicU64::new(0),
+        }
+    }
+
+    /// Puts `device` behind the cache
+    pub fn wrap(&'static self, device: Arc<dyn BlockDevice>) -> CachedDevice {
+        let mut state = self.state.lock();
+        state.devices.push(device.clone());
+        CachedDevice {
+            cache: self,
+            id: state.devices.len() - 1,
+            inner: device,
+        }
+    }
+
+    /// Changes how much heap the cache may use, at most `MAX_BUDGET`.
+    /// Clean sectors over the new budget are dropped right away and dirty
+    /// ones once written back.
+    pub fn set_budget(&self, bytes: usize) {
+        let mut state = self.state.lock();
+        state.capacity = bytes.min(MAX_BUDGET) / SECTOR_SIZE;
+        state.evict_clean();
+    }
+
+    pub fn stats(&self) -> CacheStats {
+        let state = self.state.lock();
+        CacheStats {
+            budget: state.capacity * SECTOR_SIZE,
+            used: state.entries.len() * SECTOR_SIZE,
+            dirty: state.entries.values().filter(|e| e.dirty).count() * SECTOR_SIZE,
+            hits: self.hits.load(Ordering::Relaxed),
+            misses: self.misses.load(Ordering::Relaxed),
+            writebacks: self.writebacks.load(Ordering::Relaxed),
+        }
+    }
+
+    /// Writes one batch of dirty sectors to their devices, returns the
+    /// devices written to, none once everything is clean
+    async fn write_back(&self, device: Option<usize>) -> Result<Vec<usize>, BlockError> {
+        let (batch, devices) = {
+            let state = self.state.lock();
+            (state.dirty(device)?, state.devices.clone())
+        };
+        let mut written = Vec::new();
+        for ((id, lba), data, version) in batch {
+            devices[id].write_sectors(lba, &data).await?;
+            self.writebacks.fetch_add(1, Ordering::Relaxed);
+            if let Some(entry) = self.state.lock().entries.get_mut(&(id, lba))
+                && entry.version == version
+            {
+                entry.dirty = false;
+            }
+            if !written.contains(&id) {
+                written.push(id);
+            }
+        }
+        Ok(written)
+    }
+
+    /// Writes dirty sectors back batch by batch until none are left,
+    /// returns the devices written to
+    async fn write_back_all(&self, device: Option<usize>) -> Result<Vec<usize>, BlockError> {
+        let mut written = Vec::new();
+        loop {
+            let batch = self.write_back(device).await?;
+            if batch.is_empty() {
+                return Ok(written);
+            }
+            for id in batch {
+                if !written.contains(&id) {
+                    written.push(id);
+                }
+            }
+        }
+    }
+
+    /// Writes every dirty sector back and flushes the devices that got any
+    pub async fn sync(&self) -> Result<(), BlockError> {
+        let written = self.write_back_all(None).await?;
+        let devices = self.state.lock().devices.clone();
+        for id in written {
+            devices[id].flush().await?;
+        }
+        Ok(())
+    }
+
+    /// Gets the cache back within its budget, writing dirty sectors back
+    /// when no clean ones are left to drop
+    async fn shrink(&self) -> Result<(), BlockError> {
+        while !self.state.lock().evict_clean() {
+            self.write_back(None).await?;
+        }
+        Ok(())
+    }
+}
+
+/// A device whose sectors go through a `BlockCache`
+pub struct CachedDevice {
+    cache: &'static BlockCache,
+    id: usize,
+    inner: Arc<dyn BlockDevice>,
+}
+
+impl CachedDevice {
+    async fn read(&self, lba: u64, buf: &mut [u8]) -> Result<(), BlockError> {
+        let count = check_request(self, lba, buf.len())? as usize;
+        let key = |i: usize| (self.id, lba + i as u64);
+        let mut i = 0;
+        while i < count {
+            if self.cache.state.lock().get(key(i), &mut buf[i * SECTOR_SIZE..][..SECTOR_SIZE]) {
+                self.cache.hits.fetch_add(1, Ordering::Relaxed);
+                i += 1;
+                continue;
+            }
+            // Fetch the whole run of missing sectors with one request
+            let mut end = i + 1;
+            while end < count && !self.cache.state.lock().entries.contains_key(&key(end)) {
+                end += 1;
+            }
+            self.cache.misses.fetch_add((end - i) as u64, Ordering::Relaxed);
+            self.inner.read_sectors(lba + i as u64, &mut buf[i * SECTOR_SIZE..end * SECTOR_SIZE]).await?;
+            let mut state = self.cache.state.lock();
+            for (j, sector) in buf[i * SECTOR_SIZE..end * SECTOR_SIZE].chunks_exact_mut(SECTOR_SIZE).enumerate() {
+                state.fill(key(i + j), sector);
+            }
+            drop(state);
+            i = end;
+        }
+        self.cache.shrink().await
+    }
+
+    async fn write(&self, lba: u64, data: &[u8]) -> Result<(), BlockError> {
+        check_request(self, lba, data.len())?;
+        // Refused here, a write-back could not report it to the caller
+        if self.inner.is_read_only() {
+            return Err(BlockError::ReadOnly);
+        }
+        let stored = {
+            let mut state = self.cache.state.lock();
+            data.chunks_exact(SECTOR_SIZE)
+                .enumerate()
+                .try_for_each(|(i, sector)| state.store((self.id, lba + i as u64), sector))
+        };
+        // Sectors stored before the heap ran out are still written back
+        self.cache.shrink().await?;
+        stored
+    }
+
+    async fn flush_device(&self) -> Result<(), BlockError> {
+        self.cache.write_back_all(Some(self.id)).await?;
+        self.inner.flush().await
+    }
+}
+
+impl BlockDevice for CachedDevice {
+    fn name(&self) -> &str {
+        self.inner.name()
+    }
+
+    fn sector_count(&self) -> u64 {
+        self.inner.sector_count()
+    }
+
+    fn model(&self) -> &str {
+        self.inner.model()
+    }
+
+    fn is_read_only(&self) -> bool {
+        self.inner.is_read_only()
+    }
+
+    fn read_sectors<'a>(&'a self, lba: u64, buf: &'a mut [u8]) -> BlockFuture<'a, ()> {
+        Box::pin(self.read(lba, buf))
+    }
+
+    fn write_sectors<'a>(&'a self, lba: u64, data: &'a [u8]) -> BlockFuture<'a, ()> {
+        Box::pin(self.write(lba, data))
+    }
+
+    /// Writes the device's dirty sectors back before flushing it
+    fn flush(&self) -> BlockFuture<'_, ()> {
+        Box::pin(self.flush_device())
+    }
+}
+
+static WRITEBACK_DUE: AtomicU64 = AtomicU64::new(0);
+static WRITEBACK_WAKER: AtomicWaker = AtomicWaker::new();
+
+/// Called from the timer interrupt handler
+pub(crate) fn timer_tick(ticks: u64) {
+    if ticks >= WRITEBACK_DUE.load(Ordering::Relaxed) {
+        WRITEBACK_WAKER.wake();
+    }
+}
+
+/// Syncs `CACHE` every few seconds, spawned once on the executor
+pub async fn writeback_task() {
+    loop {
+        WRITEBACK_DUE.store(interrupts::ticks() + WRITEBACK_INTERVAL_TICKS, Ordering::Relaxed);
+        poll_fn(|cx| {
+            WRITEBACK_WAKER.register(cx.waker());
+            if interrupts::ticks() >= WRITEBACK_DUE.load(Ordering::Relaxed) {
+                Poll::Ready(())
+            } else {
+                Poll::Pending
+            }
+        })
+        .await;
+        // Waited for here instead of awaited, so shell commands never find
+        // a device locked by a task the executor cannot get back to
+        if let Err(e) = block_on(CACHE.sync()) {
+            println!("WARNING: block cache write-back failed: {}", e);
+        }
+    }
+}
+
+#[test_case]
+fn test_block_cache() {
+    use super::MemDisk;
+
+    let cache: &'static BlockCache = Box::leak(Box::new(BlockCache::new(4 * SECTOR_SIZE)));
+    let disk = Arc::new(MemDisk::new("mem0", 16));
+    let cached = cache.wrap(disk.clone());
+    let mut sector = [0u8; SECTOR_SIZE];
+
+    block_on(cached.write_sectors(1, &[0xAA; SECTOR_SIZE])).unwrap();
+    block_on(disk.read_sectors(1, &mut sector)).unwrap();
+    assert_eq!(sector[0], 0, "written through before sync");
+    block_on(cached.read_sectors(1, &mut sector)).unwrap();
+    assert_eq!(sector[0], 0xAA);
+    assert_eq!(cache.stats().hits, 1);
+    assert_eq!(cache.stats().dirty, SECTOR_SIZE);
+
+    block_on(cache.sync()).unwrap();
+    block_on(disk.read_sectors(1, &mut sector)).unwrap();
+    assert_eq!(sector[0], 0xAA);
+    assert_eq!(cache.stats().dirty, 0);
+
+    // Six sectors through a four sector cache, sector 1 is the oldest
+    let mut buf = alloc::vec![0u8; 6 * SECTOR_SIZE];
+    block_on(cached.read_sectors(2, &mut buf)).unwrap();
+    assert_eq!(cache.stats().misses, 6);
+    assert_eq!(cache.stats().used, 4 * SECTOR_SIZE);
+    block_on(cached.read_sectors(1, &mut sector)).unwrap();
+    assert_eq!(cache.stats().misses, 7);
+
+    // Dirty sectors are written back rather than dropped
+    let data = alloc::vec![0x55u8; 6 * SECTOR_SIZE];
+    block_on(cached.write_sectors(8, &data)).unwrap();
+    block_on(disk.read_sectors(8, &mut sector)).unwrap();
+    assert_eq!(sector[0], 0x55);
+    assert!(cache.stats().used <= 4 * SECTOR_SIZE);
+    block_on(cached.read_sectors(13, &mut sector)).unwrap();
+    assert_eq!(sector[0], 0x55);
+
+    // The budget is capped, and more dirty sectors than one write-back
+    // batch all reach the disk on sync
+    cache.set_budget(usize::MAX);
+    assert_eq!(cache.stats().budget, MAX_BUDGET / SECTOR_SIZE * SECTOR_SIZE);
+    let count = WRITEBACK_BATCH + 3;
+    let disk = Arc::new(MemDisk::new("mem1", count));
+    let cached = cache.wrap(disk.clone());
+    block_on(cached.write_sectors(0, &alloc::vec![0x77u8; count * SECTOR_SIZE])).unwrap();
+    assert!(cache.stats().dirty >= count * SECTOR_SIZE);
+    block_on(cache.sync()).unwrap();
+    assert_eq!(cache.stats().dirty, 0);
+    let mut buf = alloc::vec![0u8; count * SECTOR_SIZE];
+    block_on(disk.read_sectors(0, &mut buf)).unwrap();
+    assert!(buf.iter().all(|&b| b == 0x77));
+}
@@ -0,0 +1,247 @@
+extern crate alloc;
+use alloc::{boxed::Box, sync::Arc, task::Wake, vec::Vec};
+use core::{fmt, future::Future, pin::{pin, Pin}, sync::atomic::{AtomicBool, Ordering}, task::{Context, Poll, Waker}};
+use spin::Mutex;
+use crate::println;
+use partition::Partition;
+
+pub mod ata;
+/// Little-endian fields of partition tables and filesystems
+pub mod bytes;
+pub mod cache;
+pub mod partition;
+pub mod virtio;
+
+/// Size of one sector, the unit every block device is addressed in
+pub const SECTOR_SIZE: usize = 512;
+
+/// Errors returned by block devices
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BlockError {
+    /// The request reaches past the last sector of the device
+    OutOfRange,
+    /// The buffer is not a whole number of sectors
+    BadBuffer,
+    /// The device reported an error for the request
+    DeviceError,
+    /// The device did not answer in time
+    TimedOut,
+    /// The device does not accept writes
+    ReadOnly,
+    /// The heap could not hold a buffer for the request
+    NoMemory,
+}
+
+impl fmt::Display for BlockError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let msg = match self {
+            BlockError::OutOfRange => "sector out of range",
+            BlockError::BadBuffer => "buffer is not a whole number of sectors",
+            BlockError::DeviceError => "input/output error",
+            BlockError::TimedOut => "device timed out",
+            BlockError::ReadOnly => "read-only device",
+            BlockError::NoMemory => "out of memory",
+        };
+        f.write_str(msg)
+    }
+}
+
+/// A request in flight, completes once the device is done with it
+pub type BlockFuture<'a, T> = Pin<Box<dyn Future<Output = Result<T, BlockError>> + Send + 'a>>;
+
+/// A disk or anything else that stores fixed size sectors
+///
+/// Requests complete asynchronously, use `block_on` to wait for one from
+/// synchronous code.
+pub trait BlockDevice: Send + Sync {
+    /// Short name like "hda", unique among registered devices
+    fn name(&self) -> &str;
+    fn sector_count(&self) -> u64;
+
+    /// Model string reported by the hardware, empty if there is none
+    fn model(&self) -> &str {
+        ""
+    }
+
+    /// True if writes fail with `BlockError::ReadOnly`
+    fn is_read_only(&self) -> bool {
+        false
+    }
+
+    /// Fills `buf`, a whole number of sectors, starting at sector `lba`
+    fn read_sectors<'a>(&'a self, lba: u64, buf: &'a mut [u8]) -> BlockFuture<'a, ()>;
+
+    /// Writes `data`, a whole number of sectors, starting at sector `lba`
+    fn write_sectors<'a>(&'a self, lba: u64, data: &'a [u8]) -> BlockFuture<'a, ()>;
+
+    /// Waits until written sectors have reached the medium
+    fn flush(&self) -> BlockFuture<'_, ()> {
+        Box::pin(async { Ok(()) })
+    }
+}
+
+/// Checks a request of `len` bytes at sector `lba` against the device,
+/// returns the number of sectors
+pub(crate) fn check_request(device: &dyn BlockDevice, lba: u64, len: usize) -> Result<u64, BlockError> {
+    if !len.is_multiple_of(SECTOR_SIZE) {
+        return Err(BlockError::BadBuffer);
+    }
+    let count = (len / SECTOR_SIZE) as u64;
+    match lba.checked_add(count) {
+        Some(end) if end <= device.sector_count() => Ok(count),
+        _ => Err(BlockError::OutOfRange),
+    }
+}
+
+static DEVICES: Mutex<Vec<Arc<dyn BlockDevice>>> = Mutex::new(Vec::new());
+static PARTITIONS: Mutex<Vec<Arc<Partition>>> = Mutex::new(Vec::new());
+
+/// Probes the disk controllers and registers every drive found, then the
+/// partitions on them
+pub fn init() {
+    ata::init();
+    virtio::init();
+    for disk in devices() {
+        add_partitions(&disk);
+    }
+}
+
+/// Registers the partitions of `disk`. They go through the cache of the
+/// disk, so unlike disks they are not wrapped again.
+fn add_partitions(disk: &Arc<dyn BlockDevice>) {
+    match block_on(partition::scan(disk)) {
+        Ok(found) => {
+            for partition in found {
+                let partition = Arc::new(partition);
+                PARTITIONS.lock().push(partition.clone());
+                DEVICES.lock().push(partition);
+            }
+        }
+        Err(e) => println!("WARNING: {}: cannot read partition table: {}", disk.name(), e),
+    }
+}
+
+/// Makes `device` available through `devices` and `get`, behind
+/// `cache::CACHE`
+pub fn register(device: Arc<dyn BlockDevice>) {
+    let cached = cache::CACHE.wrap(device);
+    DEVICES.lock().push(Arc::new(cached));
+}
+
+/// Every registered device, in the order they were found
+pub fn devices() -> Vec<Arc<dyn BlockDevice>> {
+    DEVICES.lock().clone()
+}
+
+/// Every registered partition, disk by disk in table order
+pub fn partitions() -> Vec<Arc<Partition>> {
+    PARTITIONS.lock().clone()
+}
+
+pub fn get(name: &str) -> Option<Arc<dyn BlockDevice>> {
+    DEVICES.lock().iter().find(|d| d.name() == name).cloned()
+}
+
+/// Set by the waker of `block_on`
+struct Woken(AtomicBool);
+
+impl Wake for Woken {
+    fn wake(self: Arc<Self>) {
+        self.0.store(true, Ordering::Release);
+    }
+
+    fn wake_by_ref(self: &Arc<Self>) {
+        self.0.store(true, Ordering::Release);
+    }
+}
+
+/// Runs `future` to completion from synchronous code, halting the CPU
+/// until the next interrupt whenever it has to wait
+///
+/// Needs interrupts enabled to make progress on interrupt driven devices.
+pub fn block_on<F: Future>(future: F) -> F::Output {
+    use x86_64::instructions::interrupts;
+
+    let mut future = pin!(future);
+    let woken = Arc::new(Woken(AtomicBool::new(false)));
+    let waker = Waker::from(woken.clone());
+    let mut cx = Context::from_waker(&waker);
+    loop {
+        woken.0.store(false, Ordering::Release);
+        if let Poll::Ready(output) = future.as_mut().poll(&mut cx) {
+            return output;
+        }
+        if !interrupts::are_enabled() {
+            core::hint::spin_loop();
+            continue;
+        }
+        // Checked with interrupts off, so a wake cannot slip in before hlt
+        interrupts::disable();
+        if woken.0.load(Ordering::Acquire) {
+            interrupts::enable();
+        } else {
+            interrupts::enable_and_hlt();
+        }
+    }
+}
+
+/// Device backed by a heap buffer, for tests of the layers above
+#[cfg(test)]
+pub(crate) struct MemDisk {
+    name: &'static str,
+    sectors: Mutex<Vec<u8>>,
+}
+
+#[cfg(test)]
+impl MemDisk {
+    pub(crate) fn new(name: &'static str, sector_count: usize) -> Self {
+        MemDisk {
+            name,
+            sectors: Mutex::new(alloc::vec![0u8; sector_count * SECTOR_SIZE]),
+        }
+    }
+}
+
+#[cfg(test)]
+impl BlockDevice for MemDisk {
+    fn name(&self) -> &str {
+        self.name
+    }
+
+    fn sector_count(&self) -> u64 {
+        (self.sectors.lock().len() / SECTOR_SIZE) as u64
+    }
+
+    fn read_sectors<'a>(&'a self, lba: u64, buf: &'a mut [u8]) -> BlockFuture<'a, ()> {
+        Box::pin(async move {
+            check_request(self, lba, buf.len())?;
+            let start = lba as usize * SECTOR_SIZE;
+            buf.copy_from_slice(&self.sectors.lock()[start..start + buf.len()]);
+            Ok(())
+        })
+    }
+
+    fn write_sectors<'a>(&'a self, lba: u64, data: &'a [u8]) -> BlockFuture<'a, ()> {
+        Box::pin(async move {
+            check_request(self, lba, data.len())?;
+            let start = lba as usize * SECTOR_SIZE;
+            self.sectors.lock()[start..start + data.len()].copy_from_slice(data);
+            Ok(())
+        })
+    }
+}
+
+#[test_case]
+fn test_block_device() {
+    let disk = MemDisk::new("mem0", 4);
+    let mut buf = [0u8; 2 * SECTOR_SIZE];
+    buf[SECTOR_SIZE] = 0xAA;
+    block_on(disk.write_sectors(2, &buf)).unwrap();
+    let mut sector = [0u8; SECTOR_SIZE];
+    block_on(disk.read_sectors(3, &mut sector)).unwrap();
+    assert_eq!(sector[0], 0xAA);
+
+    assert_eq!(block_on(disk.read_sectors(3, &mut buf)), Err(BlockError::OutOfRange));
+    assert_eq!(block_on(disk.read_sectors(0, &mut buf[..100])), Err(BlockError::BadBuffer));
+    assert_eq!(block_on(disk.read_sectors(u64::MAX, &mut sector)), Err(BlockError::OutOfRange));
+}
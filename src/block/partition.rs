@@ -0,0 +1,377 @@
+extern crate alloc;
+use alloc::{boxed::Box, format, string::String, sync::Arc, vec::Vec};
+use core::fmt;
+use super::{bytes::{le16, le32, le64}, check_request, BlockDevice, BlockError, BlockFuture, SECTOR_SIZE};
+
+const MBR_TABLE: usize = 446;
+const MBR_SIGNATURE: [u8; 2] = [0x55, 0xAA];
+/// Type of the single MBR entry covering a GPT disk
+const MBR_GPT_PROTECTIVE: u8 = 0xEE;
+const MBR_EXTENDED: [u8; 3] = [0x05, 0x0F, 0x85];
+/// Logical partitions followed before an extended partition counts as a loop
+const MAX_LOGICAL: u32 = 128;
+
+const GPT_SIGNATURE: &[u8] = b"EFI PART";
+/// Entries read at most, tools create 128
+const GPT_MAX_ENTRIES: usize = 1024;
+const GPT_MIN_ENTRY_SIZE: usize = 128;
+/// Largest entry array read, the 16 KiB tools reserve for 128 entries
+const GPT_MAX_TABLE: usize = 16 * 1024;
+
+/// CRC-32 as used by GPT, the same as zlib's
+fn crc32(data: &[u8]) -> u32 {
+    let mut crc = !0u32;
+    for &byte in data {
+        crc ^= u32::from(byte);
+        for _ in 0..8 {
+            crc = if crc & 1 != 0 { crc >> 1 ^ 0xEDB8_8320 } else { crc >> 1 };
+        }
+    }
+    !crc
+}
+
+/// A GUID in its on-disk byte order
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Guid([u8; 16]);
+
+impl fmt::Display for Guid {
+    /// The first three fields are stored little endian, the rest as is
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let b = &self.0;
+        write!(f, "{:08X}-{:04X}-{:04X}-", le32(b, 0), le16(b, 4), le16(b, 6))?;
+        write!(f, "{:02X}{:02X}-", b[8], b[9])?;
+        b[10..].iter().try_for_each(|byte| write!(f, "{:02X}", byte))
+    }
+}
+
+/// What a partition table says a partition holds
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PartitionType {
+    /// System id byte of an MBR entry
+    Mbr(u8),
+    /// Partition type GUID of a GPT entry
+    Gpt(Guid),
+}
+
+impl fmt::Display for PartitionType {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            PartitionType::Mbr(id) => write!(f, "0x{:02x}", id),
+            PartitionType::Gpt(guid) => guid.fmt(f),
+        }
+    }
+}
+
+/// One partition of a disk, a block device of its own addressed from its
+/// first sector
+pub struct Partition {
+    name: String,
+    disk: Arc<dyn BlockDevice>,
+    start: u64,
+    sectors: u64,
+    kind: PartitionType,
+    /// GPT partition name, empty on MBR disks
+    label: String,
+}
+
+impl Partition {
+    /// Name of the disk the partition is on
+    pub fn disk(&self) -> &str {
+        self.disk.name()
+    }
+
+    /// First sector on the disk
+    pub fn start(&self) -> u64 {
+        self.start
+    }
+
+    pub fn kind(&self) -> PartitionType {
+        self.kind
+    }
+
+    pub fn label(&self) -> &str {
+        &self.label
+    }
+}
+
+impl BlockDevice for Partition {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn sector_count(&self) -> u64 {
+        self.sectors
+    }
+
+    fn is_read_only(&self) -> bool {
+        self.disk.is_read_only()
+    }
+
+    fn read_sectors<'a>(&'a self, lba: u64, buf: &'a mut [u8]) -> BlockFuture<'a, ()> {
+        Box::pin(async move {
+            check_request(self, lba, buf.len())?;
+            self.disk.read_sectors(self.start + lba, buf).await
+        })
+    }
+
+    fn write_sectors<'a>(&'a self, lba: u64, data: &'a [u8]) -> BlockFuture<'a, ()> {
+        Box::pin(async move {
+            check_request(self, lba, data.len())?;
+            self.disk.write_sectors(self.start + lba, data).await
+        })
+    }
+
+    fn flush(&self) -> BlockFuture<'_, ()> {
+        self.disk.flush()
+    }
+}
+
+/// Collects the partitions found on one disk
+struct Scan<'a> {
+    disk: &'a Arc<dyn BlockDevice>,
+    found: Vec<Partition>,
+}
+
+impl Scan<'_> {
+    /// Adds partition `number`, entries reaching past the end of the disk
+    /// are ignored
+    fn add(&mut self, number: u32, start: u64, sectors: u64, kind: PartitionType, label: String) {
+        let fits = start.checked_add(sectors).is_some_and(|end| end <= self.disk.sector_count());
+        if sectors == 0 || start == 0 || !fits {
+            return;
+        }
+        let disk = self.disk.name();
+        // "nvme0n1" would be ambiguous with a number right after it
+        let separator = if disk.ends_with(|c: char| c.is_ascii_digit()) { "p" } else { "" };
+        self.found.push(Partition {
+            name: format!("{}{}{}", disk, separator, number),
+            disk: self.disk.clone(),
+            start,
+            sectors,
+            kind,
+            label,
+        });
+    }
+
+    async fn read(&self, lba: u64, sectors: usize) -> Result<Vec<u8>, BlockError> {
+        let mut buf = Vec::new();
+        buf.try_reserve_exact(sectors * SECTOR_SIZE).map_err(|_| BlockError::NoMemory)?;
+        buf.resize(sectors * SECTOR_SIZE, 0);
+        self.disk.read_sectors(lba, &mut buf).await?;
+        Ok(buf)
+    }
+
+    /// Primary partitions are 1 to 4, logical ones in an extended partition
+    /// are numbered from 5 on
+    async fn mbr(&mut self, mbr: &[u8]) -> Result<(), BlockError> {
+        for (index, entry) in mbr[MBR_TABLE..MBR_TABLE + 64].chunks_exact(16).enumerate() {
+            let (kind, start, sectors) = (entry[4], le32(entry, 8) as u64, le32(entry, 12) as u64);
+            if MBR_EXTENDED.contains(&kind) {
+                self.logical(start).await?;
+            } else if kind != 0 {
+                self.add(index as u32 + 1, start, sectors, PartitionType::Mbr(kind), String::new());
+            }
+        }
+        Ok(())
+    }
+
+    /// Follows the chain of boot records in the extended partition at
+    /// `extended`, each holding one logical partition and a link
+    async fn logical(&mut self, extended: u64) -> Result<(), BlockError> {
+        let mut ebr = extended;
+        for number in 5..5 + MAX_LOGICAL {
+            if ebr >= self.disk.sector_count() {
+                break;
+            }
+            let record = self.read(ebr, 1).await?;
+            if record[510..512] != MBR_SIGNATURE {
+                break;
+            }
+            let entry = &record[MBR_TABLE..MBR_TABLE + 16];
+            if entry[4] != 0 {
+                let start = ebr + le32(entry, 8) as u64;
+                self.add(number, start, le32(entry, 12) as u64, PartitionType::Mbr(entry[4]), String::new());
+            }
+            // The link is relative to the extended partition, not this record
+            let link = &record[MBR_TABLE + 16..MBR_TABLE + 32];
+            if !MBR_EXTENDED.contains(&link[4]) || le32(link, 8) == 0 {
+                break;
+            }
+            ebr = extended + le32(link, 8) as u64;
+        }
+        Ok(())
+    }
+
+    /// Reads the primary GPT, false if its header or entries fail their
+    /// checksums
+    async fn gpt(&mut self) -> Result<bool, BlockError> {
+        let mut header = self.read(1, 1).await?;
+        let header_size = le32(&header, 12) as usize;
+        if &header[..8] != GPT_SIGNATURE || !(92..=SECTOR_SIZE).contains(&header_size) {
+            return Ok(false);
+        }
+        let crc = le32(&header, 16);
+        header[16..20].fill(0);
+        if crc32(&header[..header_size]) != crc {
+            return Ok(false);
+        }
+
+        let entries_lba = le64(&header, 72);
+        let count = le32(&header, 80) as usize;
+        let entry_size = le32(&header, 84) as usize;
+        let sized = (GPT_MIN_ENTRY_SIZE..=SECTOR_SIZE).contains(&entry_size) && entry_size.is_multiple_of(8);
+        if count > GPT_MAX_ENTRIES || !sized || count * entry_size > GPT_MAX_TABLE {
+            return Ok(false);
+        }
+        let len = count * entry_size;
+        let sectors = len.div_ceil(SECTOR_SIZE);
+        let fits = entries_lba.checked_add(sectors as u64).is_some_and(|end| end <= self.disk.sector_count());
+        if entries_lba < 2 || !fits {
+            return Ok(false);
+        }
+        let table = self.read(entries_lba, sectors).await?;
+        if crc32(&table[..len]) != le32(&header, 88) {
+            return Ok(false);
+        }
+
+        for (index, entry) in table[..len].chunks_exact(entry_size).enumerate() {
+            let guid = Guid(entry[..16].try_into().unwrap());
+            if guid.0 == [0; 16] {
+                continue;
+            }
+            let (first, last) = (le64(entry, 32), le64(entry, 40));
+            let Some(sectors) = last.checked_add(1).and_then(|end| end.checked_sub(first)) else {
+                continue;
+            };
+            let label = char::decode_utf16((0..36).map(|i| le16(entry, 56 + 2 * i)).take_while(|&c| c != 0))
+                .map(|c| c.unwrap_or(char::REPLACEMENT_CHARACTER))
+                .collect();
+            self.add(index as u32 + 1, first, sectors, PartitionType::Gpt(guid), label);
+        }
+        Ok(true)
+    }
+}
+
+/// Reads the partition table of `disk`. A protective MBR means the disk
+/// uses GPT. Disks without a valid table have no partitions.
+pub async fn scan(disk: &Arc<dyn BlockDevice>) -> Result<Vec<Partition>, BlockError> {
+    let mut scan = Scan { disk, found: Vec::new() };
+    if disk.sector_count() < 2 {
+        return Ok(scan.found);
+    }
+    let mbr = scan.read(0, 1).await?;
+    if mbr[510..512] != MBR_SIGNATURE {
+        return Ok(scan.found);
+    }
+    let protective = mbr[MBR_TABLE..MBR_TABLE + 64].chunks_exact(16).any(|e| e[4] == MBR_GPT_PROTECTIVE);
+    if protective {
+        // A damaged GPT is left alone rather than read as the MBR around it
+        if !scan.gpt().await? {
+            scan.found.clear();
+        }
+    } else {
+        scan.mbr(&mbr).await?;
+    }
+    Ok(scan.found)
+}
+
+#[test_case]
+fn test_partitions() {
+    use super::{block_on, bytes::{put16, put32, put64}, MemDisk};
+
+    assert_eq!(crc32(b"123456789"), 0xCBF4_3926);
+
+    let mbr_entry = |sector: &mut [u8], slot: usize, kind: u8, start: u32, len: u32| {
+        let at = MBR_TABLE + 16 * slot;
+        sector[at + 4] = kind;
+        put32(sector, at + 8, start);
+        put32(sector, at + 12, len);
+        sector[510..512].copy_from_slice(&MBR_SIGNATURE);
+    };
+    let write = |disk: &Arc<dyn BlockDevice>, lba: u64, data: &[u8]| block_on(disk.write_sectors(lba, data)).unwrap();
+
+    // Primary 1, an extended partition with two logical ones, and an
+    // entry reaching past the end of the disk
+    let disk: Arc<dyn BlockDevice> = Arc::new(MemDisk::new("mem", 64));
+    let mut sector = [0u8; SECTOR_SIZE];
+    mbr_entry(&mut sector, 0, 0x83, 2, 4);
+    mbr_entry(&mut sector, 1, 0x05, 10, 20);
+    mbr_entry(&mut sector, 3, 0x0C, 60, 10);
+    write(&disk, 0, &sector);
+    let mut sector = [0u8; SECTOR_SIZE];
+    mbr_entry(&mut sector, 0, 0x83, 1, 3);
+    mbr_entry(&mut sector, 1, 0x05, 5, 3);
+    write(&disk, 10, &sector);
+    let mut sector = [0u8; SECTOR_SIZE];
+    mbr_entry(&mut sector, 0, 0x07, 1, 2);
+    write(&disk, 15, &sector);
+
+    let parts = block_on(scan(&disk)).unwrap();
+    let found: Vec<_> = parts.iter().map(|p| (p.name(), p.start(), p.sector_count(), p.kind())).collect();
+    assert_eq!(found, [
+        ("mem1", 2, 4, PartitionType::Mbr(0x83)),
+        ("mem5", 11, 3, PartitionType::Mbr(0x83)),
+        ("mem6", 16, 2, PartitionType::Mbr(0x07)),
+    ]);
+    block_on(parts[0].write_sectors(1, &[0xAA; SECTOR_SIZE])).unwrap();
+    block_on(disk.read_sectors(3, &mut sector)).unwrap();
+    assert_eq!(sector[0], 0xAA);
+    assert_eq!(block_on(parts[0].read_sectors(4, &mut sector)), Err(BlockError::OutOfRange));
+
+    // GPT with the second of four entries used
+    let disk: Arc<dyn BlockDevice> = Arc::new(MemDisk::new("vd0", 64));
+    let mut sector = [0u8; SECTOR_SIZE];
+    mbr_entry(&mut sector, 0, MBR_GPT_PROTECTIVE, 1, 63);
+    write(&disk, 0, &sector);
+    let mut table = [0u8; SECTOR_SIZE];
+    let linux = [0xAF, 0x3D, 0xC6, 0x0F, 0x83, 0x84, 0x72, 0x47, 0x8E, 0x79, 0x3D, 0x69, 0xD8, 0x47, 0x7D, 0xE4];
+    table[128..144].copy_from_slice(&linux);
+    put64(&mut table, 160, 34);
+    put64(&mut table, 168, 40);
+    for (i, c) in "root".encode_utf16().enumerate() {
+        put16(&mut table, 184 + 2 * i, c);
+    }
+    write(&disk, 2, &table);
+    let mut header = [0u8; SECTOR_SIZE];
+    header[..8].copy_from_slice(GPT_SIGNATURE);
+    put32(&mut header, 12, 92);
+    put64(&mut header, 72, 2);
+    put32(&mut header, 80, 4);
+    put32(&mut header, 84, 128);
+    put32(&mut header, 88, crc32(&table));
+    let crc = crc32(&header[..92]);
+    put32(&mut header, 16, crc);
+    write(&disk, 1, &header);
+
+    let parts = block_on(scan(&disk)).unwrap();
+    assert_eq!(parts.len(), 1);
+    assert_eq!(parts[0].name(), "vd0p2");
+    assert_eq!((parts[0].start(), parts[0].sector_count()), (34, 7));
+    assert_eq!(parts[0].label(), "root");
+    assert_eq!(format!("{}", parts[0].kind()), "0FC63DAF-8483-4772-8E79-3D69D8477DE4");
+
+    // A broken checksum hides the table
+    header[16] ^= 1;
+    write(&disk, 1, &header);
+    assert!(block_on(scan(&disk)).unwrap().is_empty());
+
+    // So do tables too large for the heap or past the end of the disk,
+    // even with a valid checksum
+    let mut resign = |at: usize, value: &[u8]| {
+        header[at..at + value.len()].copy_from_slice(value);
+        header[16..20].fill(0);
+        let crc = crc32(&header[..92]);
+        header[16..20].copy_from_slice(&crc.to_le_bytes());
+        write(&disk, 1, &header);
+    };
+    resign(80, &1024u32.to_le_bytes());
+    assert!(block_on(scan(&disk)).unwrap().is_empty());
+    resign(80, &4u32.to_le_bytes());
+    resign(84, &0x8000_0000u32.to_le_bytes());
+    assert!(block_on(scan(&disk)).unwrap().is_empty());
+    resign(84, &128u32.to_le_bytes());
+    resign(72, &64u64.to_le_bytes());
+    assert!(block_on(scan(&disk)).unwrap().is_empty());
+    resign(72, &2u64.to_le_bytes());
+    assert_eq!(block_on(scan(&disk)).unwrap().len(), 1);
+}
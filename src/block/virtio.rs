@@ -0,0 +1,607 @@
+extern crate alloc;
+use alloc::{boxed::Box, format, string::String, sync::Arc, vec::Vec};
+use core::{future::Future, ptr, sync::atomic::{fence, AtomicBool, Ordering}, task::Poll};
+use futures_util::{future::poll_fn, task::AtomicWaker};
+use spin::Mutex;
+use x86_64::{instructions::port::Port, PhysAddr, VirtAddr};
+use crate::{interrupts::{self, TIMER_HZ}, memory::{self, DmaRegion}, pci::{self, Bar, PciDevice}, println};
+use super::{check_request, BlockDevice, BlockError, BlockFuture, SECTOR_SIZE};
+
+const VENDOR_VIRTIO: u16 = 0x1AF4;
+/// Transitional devices offer both transports
+const DEVICE_BLOCK_TRANSITIONAL: u16 = 0x1001;
+const DEVICE_BLOCK_MODERN: u16 = 0x1042;
+
+const STATUS_ACKNOWLEDGE: u8 = 1;
+const STATUS_DRIVER: u8 = 2;
+const STATUS_DRIVER_OK: u8 = 4;
+const STATUS_FEATURES_OK: u8 = 8;
+const STATUS_FAILED: u8 = 0x80;
+
+const FEATURE_BLK_RO: u64 = 1 << 5;
+const FEATURE_BLK_FLUSH: u64 = 1 << 9;
+const FEATURE_VERSION_1: u64 = 1 << 32;
+
+// Registers of the legacy transport, relative to the I/O BAR
+const LEGACY_DEVICE_FEATURES: u16 = 0x00;
+const LEGACY_DRIVER_FEATURES: u16 = 0x04;
+const LEGACY_QUEUE_PFN: u16 = 0x08;
+const LEGACY_QUEUE_SIZE: u16 = 0x0C;
+const LEGACY_QUEUE_SELECT: u16 = 0x0E;
+const LEGACY_QUEUE_NOTIFY: u16 = 0x10;
+const LEGACY_STATUS: u16 = 0x12;
+const LEGACY_ISR: u16 = 0x13;
+/// Device specific configuration, where it starts while MSI-X is off
+const LEGACY_CONFIG: u16 = 0x14;
+
+// Fields of the modern common configuration structure
+const COMMON_DEVICE_FEATURE_SELECT: usize = 0x00;
+const COMMON_DEVICE_FEATURE: usize = 0x04;
+const COMMON_DRIVER_FEATURE_SELECT: usize = 0x08;
+const COMMON_DRIVER_FEATURE: usize = 0x0C;
+const COMMON_STATUS: usize = 0x14;
+const COMMON_QUEUE_SELECT: usize = 0x16;
+const COMMON_QUEUE_SIZE: usize = 0x18;
+const COMMON_QUEUE_ENABLE: usize = 0x1C;
+const COMMON_QUEUE_NOTIFY_OFF: usize = 0x1E;
+const COMMON_QUEUE_DESC: usize = 0x20;
+const COMMON_QUEUE_DRIVER: usize = 0x28;
+const COMMON_QUEUE_DEVICE: usize = 0x30;
+
+/// PCI capability that locates one structure of the modern transport
+const CAP_VENDOR: u8 = 0x09;
+const CFG_COMMON: u8 = 1;
+const CFG_NOTIFY: u8 = 2;
+const CFG_ISR: u8 = 3;
+const CFG_DEVICE: u8 = 4;
+
+const REQ_IN: u32 = 0;
+const REQ_OUT: u32 = 1;
+const REQ_FLUSH: u32 = 4;
+const REQ_STATUS_OK: u8 = 0;
+
+const DESC_NEXT: u16 = 1;
+const DESC_WRITE: u16 = 2;
+
+/// Queue size asked for where the transport lets the driver pick, one
+/// request takes three descriptors
+const QUEUE_SIZE: u16 = 16;
+/// The legacy transport wants the used ring on a page boundary
+const QUEUE_ALIGN: usize = 4096;
+
+/// Sectors moved by one request, requests are split into pieces this big
+const MAX_SECTORS_PER_REQUEST: usize = 64;
+
+// Layout of the bounce buffer
+const HEADER_OFFSET: usize = 0;
+const STATUS_OFFSET: usize = 16;
+const DATA_OFFSET: usize = SECTOR_SIZE;
+const BUFFER_SIZE: usize = DATA_OFFSET + MAX_SECTORS_PER_REQUEST * SECTOR_SIZE;
+
+/// How long a device may take to complete a request
+const REQUEST_TIMEOUT_TICKS: u64 = 5 * TIMER_HZ;
+
+/// Status reads while waiting for a reset to finish
+const RESET_POLL_LIMIT: usize = 1_000_000;
+
+fn volatile_read<T>(base: VirtAddr, offset: usize) -> T {
+    unsafe { ptr::read_volatile((base + offset as u64).as_ptr::<T>()) }
+}
+
+fn volatile_write<T>(base: VirtAddr, offset: usize, value: T) {
+    unsafe { ptr::write_volatile((base + offset as u64).as_mut_ptr::<T>(), value) }
+}
+
+/// How the registers of a device are reached
+enum Transport {
+    /// Virtio 0.9.5 registers in an I/O port BAR
+    Legacy { io: u16 },
+    /// Virtio 1.0 structures in memory BARs, located through capabilities
+    Modern {
+        common: VirtAddr,
+        /// Where the request queue is notified once it is set up
+        notify: VirtAddr,
+        notify_multiplier: u32,
+        isr: VirtAddr,
+        device: VirtAddr,
+    },
+}
+
+impl Transport {
+    /// Prefers the modern transport, transitional devices fall back to the
+    /// legacy one when its capabilities are missing
+    fn find(pci: &PciDevice) -> Result<Transport, &'static str> {
+        let (mut common, mut notify, mut isr, mut device) = (None, None, None, None);
+        let mut notify_multiplier = 0;
+        for (_, cap) in pci.capabilities().into_iter().filter(|&(id, _)| id == CAP_VENDOR) {
+            let cfg_type = pci.read_u8(cap + 3);
+            let slot = match cfg_type {
+                CFG_COMMON => &mut common,
+                CFG_NOTIFY => &mut notify,
+                CFG_ISR => &mut isr,
+                CFG_DEVICE => &mut device,
+                _ => continue,
+            };
+            // A structure may be offered more than once, the first is preferred
+            if slot.is_some() {
+                continue;
+            }
+            let Some(Bar::Memory(base)) = pci.bar(pci.read_u8(cap + 4)) else {
+                continue;
+            };
+            let offset = u64::from(pci.read_u32(cap + 8));
+            let length = pci.read_u32(cap + 12) as usize;
+            let addr = memory::map_mmio(PhysAddr::new(base + offset), length)
+                .ok_or("cannot map registers")?;
+            *slot = Some(addr);
+            if cfg_type == CFG_NOTIFY {
+                notify_multiplier = pci.read_u32(cap + 16);
+            }
+        }
+
+        if let (Some(common), Some(notify), Some(isr), Some(device)) = (common, notify, isr, device) {
+            return Ok(Transport::Modern { common, notify, notify_multiplier, isr, device });
+        }
+        match pci.bar(0) {
+            Some(Bar::Io(io)) if pci.device_id == DEVICE_BLOCK_TRANSITIONAL => Ok(Transport::Legacy { io }),
+            _ => Err("no usable transport"),
+        }
+    }
+
+    fn is_modern(&self) -> bool {
+        matches!(self, Transport::Modern { .. })
+    }
+
+    fn status(&self) -> u8 {
+        match *self {
+            Transport::Legacy { io } => unsafe { Port::new(io + LEGACY_STATUS).read() },
+            Transport::Modern { common, .. } => volatile_read(common, COMMON_STATUS),
+        }
+    }
+
+    fn set_status(&self, status: u8) {
+        match *self {
+            Transport::Legacy { io } => unsafe { Port::new(io + LEGACY_STATUS).write(status) },
+            Transport::Modern { common, .. } => volatile_write(common, COMMON_STATUS, status),
+        }
+    }
+
+    /// Writing 0 resets the device, modern devices may take a while
+    fn reset(&self) -> Result<(), &'static str> {
+        self.set_status(0);
+        for _ in 0..RESET_POLL_LIMIT {
+            if self.status() == 0 {
+                return Ok(());
+            }
+        }
+        Err("reset did not finish")
+    }
+
+    /// Legacy devices only have the lower 32 feature bits
+    fn device_features(&self) -> u64 {
+        match *self {
+            Transport::Legacy { io } => u64::from(unsafe { Port::<u32>::new(io + LEGACY_DEVICE_FEATURES).read() }),
+            Transport::Modern { common, .. } => {
+                let mut features = 0;
+                for select in 0..2 {
+                    volatile_write(common, COMMON_DEVICE_FEATURE_SELECT, select as u32);
+                    features |= u64::from(volatile_read::<u32>(common, COMMON_DEVICE_FEATURE)) << (32 * select);
+                }
+                features
+            }
+        }
+    }
+
+    fn set_driver_features(&self, features: u64) {
+        match *self {
+            Transport::Legacy { io } => unsafe { Port::new(io + LEGACY_DRIVER_FEATURES).write(features as u32) },
+            Transport::Modern { common, .. } => {
+                for select in 0..2 {
+                    volatile_write(common, COMMON_DRIVER_FEATURE_SELECT, select as u32);
+                    volatile_write(common, COMMON_DRIVER_FEATURE, (features >> (32 * select)) as u32);
+                }
+            }
+        }
+    }
+
+    /// 32 bits of the device specific configuration
+    fn config_u32(&self, offset: u16) -> u32 {
+        match *self {
+            Transport::Legacy { io } => unsafe { Port::new(io + LEGACY_CONFIG + offset).read() },
+            Transport::Modern { device, .. } => volatile_read(device, offset as usize),
+        }
+    }
+
+    /// Reading the ISR status acknowledges the interrupt
+    fn isr(&self) -> u8 {
+        match *self {
+            Transport::Legacy { io } => unsafe { Port::new(io + LEGACY_ISR).read() },
+            Transport::Modern { isr, .. } => volatile_read(isr, 0),
+        }
+    }
+
+    /// Sets up queue 0, the only one a block device has without multiqueue
+    fn setup_queue(&mut self) -> Result<Queue, &'static str> {
+        match self {
+            Transport::Legacy { io } => {
+                let size = unsafe {
+                    Port::new(*io + LEGACY_QUEUE_SELECT).write(0u16);
+                    Port::<u16>::new(*io + LEGACY_QUEUE_SIZE).read()
+                };
+                if size == 0 {
+                    return Err("no request queue");
+                }
+                let queue = Queue::new(size).ok_or("out of DMA memory")?;
+                let pfn = (queue.ring.phys().as_u64() / QUEUE_ALIGN as u64) as u32;
+                unsafe { Port::new(*io + LEGACY_QUEUE_PFN).write(pfn) };
+                Ok(queue)
+            }
+            Transport::Modern { common, notify, notify_multiplier, .. } => {
+                let common = *common;
+                volatile_write(common, COMMON_QUEUE_SELECT, 0u16);
+                let max = volatile_read::<u16>(common, COMMON_QUEUE_SIZE);
+                if max == 0 {
+                    return Err("no request queue");
+                }
+                let size = max.min(QUEUE_SIZE);
+                let queue = Queue::new(size).ok_or("out of DMA memory")?;
+                volatile_write(common, COMMON_QUEUE_SIZE, size);
+                for (field, addr) in [
+                    (COMMON_QUEUE_DESC, queue.desc_addr()),
+                    (COMMON_QUEUE_DRIVER, queue.avail_addr()),
+                    (COMMON_QUEUE_DEVICE, queue.used_addr()),
+                ] {
+                    volatile_write(common, field, addr.as_u64() as u32);
+                    volatile_write(common, field + 4, (addr.as_u64() >> 32) as u32);
+                }
+                let notify_off = volatile_read::<u16>(common, COMMON_QUEUE_NOTIFY_OFF);
+                *notify += u64::from(notify_off) * u64::from(*notify_multiplier);
+                volatile_write(common, COMMON_QUEUE_ENABLE, 1u16);
+                Ok(queue)
+            }
+        }
+    }
+
+    /// Tells the device queue 0 has new requests
+    fn notify(&self) {
+        match *self {
+            Transport::Legacy { io } => unsafe { Port::new(io + LEGACY_QUEUE_NOTIFY).write(0u16) },
+            Transport::Modern { notify, .. } => volatile_write(notify, 0, 0u16),
+        }
+    }
+}
+
+/// A split virtqueue, descriptor table, available ring and used ring in one
+/// DMA region laid out as the legacy transport requires
+struct Queue {
+    ring: DmaRegion,
+    size: u16,
+    /// Offsets of the rings in the region
+    avail: usize,
+    used: usize,
+}
+
+impl Queue {
+    fn new(size: u16) -> Option<Queue> {
+        let n = size as usize;
+        let avail = 16 * n;
+        let used = (avail + 6 + 2 * n).next_multiple_of(QUEUE_ALIGN);
+        let ring = memory::alloc_dma(used + 6 + 8 * n)?;
+        Some(Queue { ring, size, avail, used })
+    }
+
+    fn base(&self) -> VirtAddr {
+        VirtAddr::from_ptr(self.ring.as_ptr())
+    }
+
+    fn desc_addr(&self) -> PhysAddr {
+        self.ring.phys()
+    }
+
+    fn avail_addr(&self) -> PhysAddr {
+        self.ring.phys() + self.avail as u64
+    }
+
+    fn used_addr(&self) -> PhysAddr {
+        self.ring.phys() + self.used as u64
+    }
+
+    fn set_desc(&self, index: u16, addr: PhysAddr, len: usize, flags: u16, next: u16) {
+        let at = 16 * index as usize;
+        volatile_write(self.base(), at, addr.as_u64());
+        volatile_write(self.base(), at + 8, len as u32);
+        volatile_write(self.base(), at + 12, flags);
+        volatile_write(self.base(), at + 14, next);
+    }
+
+    fn avail_idx(&self) -> u16 {
+        volatile_read(self.base(), self.avail + 2)
+    }
+
+    fn used_idx(&self) -> u16 {
+        volatile_read(self.base(), self.used + 2)
+    }
+
+    /// True once the device has returned every chain it was given
+    fn is_idle(&self) -> bool {
+        self.used_idx() == self.avail_idx()
+    }
+
+    /// Hands the chain starting at descriptor `head` to the device
+    fn push(&self, head: u16) {
+        let idx = self.avail_idx();
+        volatile_write(self.base(), self.avail + 4 + 2 * (idx % self.size) as usize, head);
+        // The device must see the entry before the index that announces it
+        fence(Ordering::SeqCst);
+        volatile_write(self.base(), self.avail + 2, idx.wrapping_add(1));
+        fence(Ordering::SeqCst);
+    }
+}
+
+/// Releases the device when the request is done or dropped
+struct RequestGuard<'a> {
+    device: &'a VirtioBlk,
+}
+
+impl Drop for RequestGuard<'_> {
+    fn drop(&mut self) {
+        self.device.busy.store(false, Ordering::Release);
+    }
+}
+
+/// A virtio block device on the PCI bus, one request in flight at a time
+pub struct VirtioBlk {
+    name: String,
+    transport: Transport,
+    queue: Queue,
+    /// Request header, status byte and data. Requests are copied through
+    /// it so that callers' buffers need not be physically contiguous.
+    buffer: DmaRegion,
+    irq: u8,
+    /// No handler for the IRQ line, requests are polled instead
+    polled: bool,
+    sectors: u64,
+    read_only: bool,
+    can_flush: bool,
+    waker: AtomicWaker,
+    /// Taken while a request uses the queue and buffer
+    busy: AtomicBool,
+}
+
+impl VirtioBlk {
+    fn new(pci: &PciDevice, name: String) -> Result<VirtioBlk, &'static str> {
+        pci.enable();
+        let mut transport = Transport::find(pci)?;
+        transport.reset()?;
+        transport.set_status(STATUS_ACKNOWLEDGE | STATUS_DRIVER);
+        match Self::negotiate(&mut transport) {
+            Ok((queue, features)) => {
+                let buffer = memory::alloc_dma(BUFFER_SIZE).ok_or("out of DMA memory")?;
+                let sectors = u64::from(transport.config_u32(0)) | u64::from(transport.config_u32(4)) << 32;
+                transport.set_status(transport.status() | STATUS_DRIVER_OK);
+                let irq = pci.interrupt_line();
+                Ok(VirtioBlk {
+                    name,
+                    transport,
+                    queue,
+                    buffer,
+                    irq,
+                    polled: !interrupts::PCI_IRQS.contains(&irq),
+                    sectors,
+                    read_only: features & FEATURE_BLK_RO != 0,
+                    can_flush: features & FEATURE_BLK_FLUSH != 0,
+                    waker: AtomicWaker::new(),
+                    busy: AtomicBool::new(false),
+                })
+            }
+            Err(msg) => {
+                transport.set_status(STATUS_FAILED);
+                Err(msg)
+            }
+        }
+    }
+
+    /// Agrees on features and sets up the request queue, returns the
+    /// queue and the accepted features
+    fn negotiate(transport: &mut Transport) -> Result<(Queue, u64), &'static str> {
+        let offered = transport.device_features();
+        let mut features = offered & (FEATURE_BLK_RO | FEATURE_BLK_FLUSH);
+        if transport.is_modern() {
+            if offered & FEATURE_VERSION_1 == 0 {
+                return Err("device does not offer VERSION_1");
+            }
+            features |= FEATURE_VERSION_1;
+        }
+        transport.set_driver_features(features);
+        // Legacy devices have no way to refuse
+        if transport.is_modern() {
+            transport.set_status(transport.status() | STATUS_FEATURES_OK);
+            if transport.status() & STATUS_FEATURES_OK == 0 {
+                return Err("features not accepted");
+            }
+        }
+        Ok((transport.setup_queue()?, features))
+    }
+
+    /// Waits for the device to be free, retrying on every poll like
+    /// `ata::Channel::lock` does
+    fn lock(&self) -> impl Future<Output = RequestGuard<'_>> + '_ {
+        poll_fn(move |cx| {
+            match self.busy.compare_exchange(false, true, Ordering::Acquire, Ordering::Relaxed) {
+                Ok(_) => Poll::Ready(RequestGuard { device: self }),
+                Err(_) => {
+                    cx.waker().wake_by_ref();
+                    Poll::Pending
+                }
+            }
+        })
+    }
+
+    /// Completes once the device has returned the request
+    fn wait(&self) -> impl Future<Output = Result<(), BlockError>> + '_ {
+        let start = interrupts::ticks();
+        poll_fn(move |cx| {
+            if self.queue.is_idle() {
+                return Poll::Ready(Ok(()));
+            }
+            if self.polled {
+                cx.waker().wake_by_ref();
+            } else {
+                self.waker.register(cx.waker());
+                if self.queue.is_idle() {
+                    self.waker.take();
+                    return Poll::Ready(Ok(()));
+                }
+            }
+            if interrupts::ticks() - start > REQUEST_TIMEOUT_TICKS {
+                return Poll::Ready(Err(BlockError::TimedOut));
+            }
+            Poll::Pending
+        })
+    }
+
+    /// Sends one request whose data, `len` bytes, is in the bounce buffer
+    /// and waits for its status. Needs the guard from `lock`.
+    async fn submit(&self, kind: u32, lba: u64, len: usize) -> Result<(), BlockError> {
+        // The device still owns the buffer of a request that timed out
+        if !self.queue.is_idle() {
+            return Err(BlockError::TimedOut);
+        }
+        let buf = VirtAddr::from_ptr(self.buffer.as_ptr());
+        volatile_write(buf, HEADER_OFFSET, kind);
+        volatile_write(buf, HEADER_OFFSET + 4, 0u32);
+        volatile_write(buf, HEADER_OFFSET + 8, lba);
+        volatile_write(buf, STATUS_OFFSET, 0xFFu8);
+
+        let phys = self.buffer.phys();
+        if len > 0 {
+            let write = if kind == REQ_IN { DESC_WRITE } else { 0 };
+            self.queue.set_desc(0, phys + HEADER_OFFSET as u64, 16, DESC_NEXT, 1);
+            self.queue.set_desc(1, phys + DATA_OFFSET as u64, len, DESC_NEXT | write, 2);
+        } else {
+            self.queue.set_desc(0, phys + HEADER_OFFSET as u64, 16, DESC_NEXT, 2);
+        }
+        self.queue.set_desc(2, phys + STATUS_OFFSET as u64, 1, DESC_WRITE, 0);
+        self.queue.push(0);
+        self.transport.notify();
+
+        self.wait().await?;
+        fence(Ordering::SeqCst);
+        match volatile_read::<u8>(buf, STATUS_OFFSET) {
+            REQ_STATUS_OK => Ok(()),
+            _ => Err(BlockError::DeviceError),
+        }
+    }
+
+    fn copy_in(&self, data: &[u8]) {
+        unsafe { ptr::copy_nonoverlapping(data.as_ptr(), self.buffer.as_ptr().add(DATA_OFFSET), data.len()) }
+    }
+
+    fn copy_out(&self, buf: &mut [u8]) {
+        unsafe { ptr::copy_nonoverlapping(self.buffer.as_ptr().add(DATA_OFFSET), buf.as_mut_ptr(), buf.len()) }
+    }
+
+    async fn read(&self, lba: u64, buf: &mut [u8]) -> Result<(), BlockError> {
+        check_request(self, lba, buf.len())?;
+        let _guard = self.lock().await;
+
+        let mut start = lba;
+        for chunk in buf.chunks_mut(MAX_SECTORS_PER_REQUEST * SECTOR_SIZE) {
+            self.submit(REQ_IN, start, chunk.len()).await?;
+            self.copy_out(chunk);
+            start += (chunk.len() / SECTOR_SIZE) as u64;
+        }
+        Ok(())
+    }
+
+    async fn write(&self, lba: u64, data: &[u8]) -> Result<(), BlockError> {
+        check_request(self, lba, data.len())?;
+        if self.read_only {
+            return Err(BlockError::ReadOnly);
+        }
+        let _guard = self.lock().await;
+
+        let mut start = lba;
+        for chunk in data.chunks(MAX_SECTORS_PER_REQUEST * SECTOR_SIZE) {
+            self.copy_in(chunk);
+            self.submit(REQ_OUT, start, chunk.len()).await?;
+            start += (chunk.len() / SECTOR_SIZE) as u64;
+        }
+        Ok(())
+    }
+
+    /// Without the flush feature the device writes through, nothing to do
+    async fn flush_cache(&self) -> Result<(), BlockError> {
+        if !self.can_flush {
+            return Ok(());
+        }
+        let _guard = self.lock().await;
+        self.submit(REQ_FLUSH, 0, 0).await
+    }
+}
+
+impl BlockDevice for VirtioBlk {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn sector_count(&self) -> u64 {
+        self.sectors
+    }
+
+    fn is_read_only(&self) -> bool {
+        self.read_only
+    }
+
+    fn read_sectors<'a>(&'a self, lba: u64, buf: &'a mut [u8]) -> BlockFuture<'a, ()> {
+        Box::pin(self.read(lba, buf))
+    }
+
+    fn write_sectors<'a>(&'a self, lba: u64, data: &'a [u8]) -> BlockFuture<'a, ()> {
+        Box::pin(self.write(lba, data))
+    }
+
+    fn flush(&self) -> BlockFuture<'_, ()> {
+        Box::pin(self.flush_cache())
+    }
+}
+
+/// Devices with an IRQ handler, only locked with interrupts off outside
+/// of `handle_irq` so the handler never finds it held
+static IRQ_DEVICES: Mutex<Vec<Arc<VirtioBlk>>> = Mutex::new(Vec::new());
+
+/// Called from the PCI IRQ handlers with the line that fired
+pub(crate) fn handle_irq(line: u8) {
+    for device in IRQ_DEVICES.lock().iter().filter(|d| d.irq == line) {
+        if device.transport.isr() != 0 {
+            device.waker.wake();
+        }
+    }
+}
+
+/// Finds virtio block devices on the PCI bus and registers them as vda,
+/// vdb and so on. Needs `memory::install` for DMA memory.
+pub fn init() {
+    let found = pci::scan().into_iter().filter(|d| {
+        d.vendor_id == VENDOR_VIRTIO && matches!(d.device_id, DEVICE_BLOCK_TRANSITIONAL | DEVICE_BLOCK_MODERN)
+    });
+    for (index, pci) in found.enumerate().take(26) {
+        let name = format!("vd{}", (b'a' + index as u8) as char);
+        let device = match VirtioBlk::new(&pci, name) {
+            Ok(device) => Arc::new(device),
+            Err(msg) => {
+                println!("virtio-blk {:02x}:{:02x}.{}: {}", pci.bus, pci.device, pci.function, msg);
+                continue;
+            }
+        };
+        if device.polled {
+            println!("{}: IRQ {} has no handler, polling", device.name, device.irq);
+        } else {
+            x86_64::instructions::interrupts::without_interrupts(|| {
+                IRQ_DEVICES.lock().push(device.clone());
+            });
+            interrupts::enable_pci_irq(device.irq);
+        }
+        super::register(device);
+    }
+}
@@ -0,0 +1,312 @@
+extern crate alloc;
+use alloc::{string::{String, ToString}, vec::Vec};
+use conquer_once::spin::OnceCell;
+use core::sync::atomic::{AtomicU64, Ordering};
+use crossbeam_queue::ArrayQueue;
+use pc_keyboard::{DecodedKey, KeyCode};
+use x86_64::instructions::{interrupts, random::RdRand};
+use crate::{serial, task::keyboard, vga_buffer};
+use super::{FileSystem, FileType, FsError, Metadata};
+
+/// How much a whole-file `read` of an endless device returns
+const READ_CHUNK: usize = 256;
+
+/// Bytes of typed keys kept for `/dev/kbd` until someone reads them
+const KBD_BUFFER_SIZE: usize = 256;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Device {
+    Null,
+    Zero,
+    Serial0,
+    Console,
+    Kbd,
+    Random,
+}
+
+const DEVICES: &[(&str, Device)] = &[
+    ("null", Device::Null),
+    ("zero", Device::Zero),
+    ("serial0", Device::Serial0),
+    ("console", Device::Console),
+    ("kbd", Device::Kbd),
+    ("random", Device::Random),
+];
+
+static KBD_QUEUE: OnceCell<ArrayQueue<u8>> = OnceCell::uninit();
+static RANDOM_STATE: AtomicU64 = AtomicU64::new(0);
+
+/// What a VT100 style terminal sends for `key`, `None` for keys it sends
+/// nothing for, like the modifiers on their own
+fn escape_sequence(key: KeyCode) -> Option<&'static [u8]> {
+    Some(match key {
+        KeyCode::ArrowUp => b"\x1b[A",
+        KeyCode::ArrowDown => b"\x1b[B",
+        KeyCode::ArrowRight => b"\x1b[C",
+        KeyCode::ArrowLeft => b"\x1b[D",
+        KeyCode::Home => b"\x1b[H",
+        KeyCode::End => b"\x1b[F",
+        KeyCode::Insert => b"\x1b[2~",
+        KeyCode::Delete => b"\x1b[3~",
+        KeyCode::PageUp => b"\x1b[5~",
+        KeyCode::PageDown => b"\x1b[6~",
+        KeyCode::F1 => b"\x1bOP",
+        KeyCode::F2 => b"\x1bOQ",
+        KeyCode::F3 => b"\x1bOR",
+        KeyCode::F4 => b"\x1bOS",
+        KeyCode::F5 => b"\x1b[15~",
+        KeyCode::F6 => b"\x1b[17~",
+        KeyCode::F7 => b"\x1b[18~",
+        KeyCode::F8 => b"\x1b[19~",
+        KeyCode::F9 => b"\x1b[20~",
+        KeyCode::F10 => b"\x1b[21~",
+        KeyCode::F11 => b"\x1b[23~",
+        KeyCode::F12 => b"\x1b[24~",
+        _ => return None,
+    })
+}
+
+/// Copies typed keys into the `/dev/kbd` buffer, characters as UTF-8 and
+/// the other keys as escape sequences, dropping the oldest bytes when
+/// nobody reads them
+fn kbd_callback(key: DecodedKey) {
+    let Ok(queue) = KBD_QUEUE.try_get() else {
+        return;
+    };
+    let mut utf8 = [0u8; 4];
+    let bytes = match key {
+        DecodedKey::Unicode(c) => c.encode_utf8(&mut utf8).as_bytes(),
+        DecodedKey::RawKey(key) => escape_sequence(key).unwrap_or_default(),
+    };
+    for &byte in bytes {
+        queue.force_push(byte);
+    }
+}
+
+/// xorshift64*, used when the CPU has no RDRAND
+fn next_pseudo_random() -> u64 {
+    let mut x = RANDOM_STATE.load(Ordering::Relaxed);
+    if x == 0 {
+        // Seed from the time stamp counter, `| 1` keeps xorshift out of zero
+        x = unsafe { core::arch::x86_64::_rdtsc() } | 1;
+    }
+    x ^= x >> 12;
+    x ^= x << 25;
+    x ^= x >> 27;
+    RANDOM_STATE.store(x, Ordering::Relaxed);
+    x.wrapping_mul(0x2545_F491_4F6C_DD1D)
+}
+
+fn random_u64() -> u64 {
+    RdRand::new()
+        .and_then(|rd| rd.get_u64())
+        .unwrap_or_else(next_pseudo_random)
+}
+
+impl Device {
+    fn ino(self) -> u64 {
+        let index = DEVICES.iter().position(|&(_, d)| d == self).unwrap();
+        index as u64 + 2
+    }
+
+    /// Fills `buf` with whatever the device has ready, never blocks
+    fn read(self, buf: &mut [u8]) -> usize {
+        match self {
+            Device::Null | Device::Console => 0,
+            Device::Zero => {
+                buf.fill(0);
+                buf.len()
+            }
+            Device::Random => {
+                for chunk in buf.chunks_mut(8) {
+                    let bytes = random_u64().to_le_bytes();
+                    chunk.copy_from_slice(&bytes[..chunk.len()]);
+                }
+                buf.len()
+            }
+            Device::Kbd => {
+                let Ok(queue) = KBD_QUEUE.try_get() else {
+                    return 0;
+                };
+                let mut count = 0;
+                while count < buf.len() {
+                    match queue.pop() {
+                        Some(byte) => buf[count] = byte,
+                        None => break,
+                    }
+                    count += 1;
+                }
+                count
+            }
+            Device::Serial0 => {
+                let mut count = 0;
+                while count < buf.len() {
+                    match serial::try_receive() {
+                        Some(byte) => buf[count] = byte,
+                        None => break,
+                    }
+                    count += 1;
+                }
+                count
+            }
+        }
+    }
+
+    fn write(self, data: &[u8]) -> Result<usize, FsError> {
+        match self {
+            Device::Null | Device::Zero => {}
+            Device::Kbd => return Err(FsError::Unsupported),
+            Device::Random => {
+                // Mix the written bytes into the fallback generator
+                for chunk in data.chunks(8) {
+                    let mut bytes = [0u8; 8];
+                    bytes[..chunk.len()].copy_from_slice(chunk);
+                    RANDOM_STATE.fetch_xor(u64::from_le_bytes(bytes), Ordering::Relaxed);
+                }
+            }
+            Device::Serial0 => serial::send_bytes(data),
+            Device::Console => {
+                let text = String::from_utf8_lossy(data);
+                interrupts::without_interrupts(|| {
+                    vga_buffer::WRITER.lock().write_string(&text);
+                });
+            }
+        }
+        Ok(data.len())
+    }
+}
+
+/// Kernel devices exposed as files, mounted at `/dev`
+///
+/// Devices are streams: offsets are ignored and reads return what is
+/// available right now, which may be nothing.
+pub(crate) struct DevFs;
+
+impl DevFs {
+    pub(crate) fn new() -> Self {
+        if KBD_QUEUE.try_init_once(|| ArrayQueue::new(KBD_BUFFER_SIZE)).is_ok() {
+            keyboard::register_key_callback(kbd_callback);
+        }
+        DevFs
+    }
+
+    fn device(path: &str) -> Result<Device, FsError> {
+        let name = path.trim_matches('/');
+        if name.is_empty() {
+            return Err(FsError::IsADirectory);
+        }
+        DEVICES
+            .iter()
+            .find(|&&(n, _)| n == name)
+            .map(|&(_, d)| d)
+            .ok_or(FsError::NotFound)
+    }
+
+    fn is_root(path: &str) -> bool {
+        path.trim_matches('/').is_empty()
+    }
+}
+
+impl FileSystem for DevFs {
+    fn name(&self) -> &'static str {
+        "devfs"
+    }
+
+    fn read(&self, path: &str) -> Result<Vec<u8>, FsError> {
+        let device = Self::device(path)?;
+        let mut buf = alloc::vec![0u8; READ_CHUNK];
+        let count = device.read(&mut buf);
+        buf.truncate(count);
+        Ok(buf)
+    }
+
+    fn write(&self, path: &str, data: &[u8]) -> Result<(), FsError> {
+        Self::device(path)?.write(data).map(|_| ())
+    }
+
+    fn read_at(&self, path: &str, _offset: u64, buf: &mut [u8]) -> Result<usize, FsError> {
+        Ok(Self::device(path)?.read(buf))
+    }
+
+    fn write_at(&self, path: &str, _offset: u64, data: &[u8]) -> Result<usize, FsError> {
+        Self::device(path)?.write(data)
+    }
+
+    fn truncate(&self, path: &str, _len: u64) -> Result<(), FsError> {
+        Self::device(path).map(|_| ())
+    }
+
+    fn touch(&self, path: &str) -> Result<(), FsError> {
+        match Self::device(path) {
+            Ok(_) => Ok(()),
+            Err(FsError::NotFound) => Err(FsError::Unsupported),
+            Err(e) => Err(e),
+        }
+    }
+
+    fn create_dir(&self, _path: &str) -> Result<(), FsError> {
+        Err(FsError::Unsupported)
+    }
+
+    fn list_dir(&self, path: &str) -> Result<Vec<String>, FsError> {
+        if !Self::is_root(path) {
+            Self::device(path)?;
+            return Err(FsError::NotADirectory);
+        }
+        Ok(DEVICES.iter().map(|&(name, _)| name.to_string()).collect())
+    }
+
+    fn stat(&self, path: &str) -> Result<Metadata, FsError> {
+        let (ino, kind, size, mode, nlink) = if Self::is_root(path) {
+            (1, FileType::Directory, DEVICES.len() as u64, 0o755, 2)
+        } else {
+            (Self::device(path)?.ino(), FileType::CharDevice, 0, 0o666, 1)
+        };
+        Ok(Metadata {
+            ino,
+            kind,
+            size,
+            created: 0,
+            modified: 0,
+            accessed: 0,
+            mode,
+            nlink,
+        })
+    }
+
+    fn remove_file(&self, _path: &str) -> Result<(), FsError> {
+        Err(FsError::Unsupported)
+    }
+
+    fn remove_dir(&self, _path: &str) -> Result<(), FsError> {
+        Err(FsError::Unsupported)
+    }
+
+    fn rename(&self, _from: &str, _to: &str) -> Result<(), FsError> {
+        Err(FsError::Unsupported)
+    }
+}
+
+#[test_case]
+fn test_devices() {
+    let fs = DevFs::new();
+    assert!(fs.list_dir("/").unwrap().iter().any(|name| name == "zero"));
+    assert_eq!(fs.stat("/null").unwrap().kind, FileType::CharDevice);
+    assert_eq!(fs.read("/null").unwrap(), b"");
+    assert_eq!(fs.write_at("/null", 0, b"gone").unwrap(), 4);
+
+    let mut buf = [1u8; 16];
+    assert_eq!(fs.read_at("/zero", 0, &mut buf).unwrap(), 16);
+    assert_eq!(buf, [0u8; 16]);
+    assert_eq!(fs.read_at("/random", 0, &mut buf).unwrap(), 16);
+    assert_eq!(fs.create_dir("/sub"), Err(FsError::Unsupported));
+    assert_eq!(fs.stat("/missing"), Err(FsError::NotFound));
+
+    // Keys without a character come out as escape sequences
+    while fs.read_at("/kbd", 0, &mut buf).unwrap() > 0 {}
+    kbd_callback(DecodedKey::Unicode('é'));
+    kbd_callback(DecodedKey::RawKey(KeyCode::ArrowUp));
+    kbd_callback(DecodedKey::RawKey(KeyCode::ShiftLeft));
+    let count = fs.read_at("/kbd", 0, &mut buf).unwrap();
+    assert_eq!(&buf[..count], "é\x1b[A".as_bytes());
+}
@@ -0,0 +1,86 @@
+use core::fmt;
+use crate::block::BlockError;
+
+/// Errors returned by the filesystem API
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FsError {
+    /// `fs::init` has not run yet
+    NotInitialized,
+    /// A path component does not exist
+    NotFound,
+    /// A path component that must be a directory is not one
+    NotADirectory,
+    /// The operation needs a file but the path names a directory
+    IsADirectory,
+    /// The path to create already exists
+    AlreadyExists,
+    /// The path is empty or names the root where that makes no sense
+    InvalidPath,
+    /// The heap could not hold the new data
+    NoSpace,
+    /// The mount point is in use
+    Busy,
+    /// The file descriptor is not open, or not open for this operation
+    BadDescriptor,
+    /// Every slot of the open file table is taken
+    TooManyOpenFiles,
+    /// A parameter is out of range, e.g. seeking before the start of a file
+    InvalidArgument,
+    /// The directory to remove or replace still has entries
+    DirectoryNotEmpty,
+    /// A rename or hard link would span two mounted filesystems
+    CrossDevice,
+    /// Resolving the path followed too many symlinks, probably a loop
+    TooManySymlinks,
+    /// The filesystem does not implement the operation
+    Unsupported,
+    /// On-disk or archive data is malformed
+    InvalidData,
+    /// The filesystem cannot be modified
+    ReadOnly,
+    /// A lock is taken and waiting for it is not allowed here
+    WouldBlock,
+    /// Waited too long for data from a device
+    TimedOut,
+    /// The disk under the filesystem failed a request
+    Io,
+}
+
+impl fmt::Display for FsError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let msg = match self {
+            FsError::NotInitialized => "filesystem not initialized",
+            FsError::NotFound => "no such file or directory",
+            FsError::NotADirectory => "not a directory",
+            FsError::IsADirectory => "is a directory",
+            FsError::AlreadyExists => "file exists",
+            FsError::InvalidPath => "invalid path",
+            FsError::NoSpace => "no space left on device",
+            FsError::Busy => "device or resource busy",
+            FsError::BadDescriptor => "bad file descriptor",
+            FsError::TooManyOpenFiles => "too many open files",
+            FsError::InvalidArgument => "invalid argument",
+            FsError::DirectoryNotEmpty => "directory not empty",
+            FsError::CrossDevice => "cross-device link",
+            FsError::TooManySymlinks => "too many levels of symbolic links",
+            FsError::Unsupported => "operation not supported",
+            FsError::InvalidData => "invalid or corrupted data",
+            FsError::ReadOnly => "read-only file system",
+            FsError::WouldBlock => "resource temporarily unavailable",
+            FsError::TimedOut => "timed out",
+            FsError::Io => "input/output error",
+        };
+        f.write_str(msg)
+    }
+}
+
+impl From<BlockError> for FsError {
+    fn from(e: BlockError) -> Self {
+        match e {
+            BlockError::ReadOnly => FsError::ReadOnly,
+            BlockError::TimedOut => FsError::TimedOut,
+            BlockError::NoMemory => FsError::NoSpace,
+            _ => FsError::Io,
+        }
+    }
+}
@@ -0,0 +1,541 @@
+extern crate alloc;
+use alloc::{string::{String, ToString}, sync::Arc, vec::Vec};
+use crate::block::{block_on, bytes::{le16, le32}, BlockDevice, SECTOR_SIZE};
+use super::{path::Path, FileSystem, FileType, FsError, FsStats, Metadata};
+
+const ROOT_INO: u32 = 2;
+const MAGIC: u16 = 0xEF53;
+/// Byte offset of the superblock, whatever the block size
+const SUPERBLOCK_OFFSET: u64 = 1024;
+const GOOD_OLD_INODE_SIZE: usize = 128;
+const DESCRIPTOR_SIZE: usize = 32;
+
+/// Directory entries carry a file type byte instead of a 16 bit name length
+const INCOMPAT_FILETYPE: u32 = 0x0002;
+/// Only changes where the block groups put their metadata
+const INCOMPAT_FLEX_BG: u32 = 0x0200;
+const SUPPORTED_INCOMPAT: u32 = INCOMPAT_FILETYPE | INCOMPAT_FLEX_BG;
+
+const MODE_TYPE: u16 = 0xF000;
+const MODE_DIR: u16 = 0x4000;
+const MODE_SYMLINK: u16 = 0xA000;
+const MODE_CHAR_DEVICE: u16 = 0x2000;
+
+/// Pointers in `i_block` before the single, double and triple indirect ones
+const DIRECT_BLOCKS: u64 = 12;
+/// Symlink targets shorter than this live in `i_block` itself
+const FAST_SYMLINK_LEN: u64 = 60;
+
+/// Maximum number of symlinks followed while resolving one path
+const MAX_SYMLINK_FOLLOWS: usize = 8;
+
+/// The parts of an on-disk inode this driver looks at
+struct Inode {
+    ino: u32,
+    mode: u16,
+    size: u64,
+    links: u16,
+    /// 512 byte sectors allocated, extended attribute block included
+    sectors: u32,
+    file_acl: u32,
+    /// `i_block`: block pointers, or the target of a fast symlink
+    block: [u8; 60],
+}
+
+/// A read-only ext2 volume on a block device
+///
+/// Timestamps, owners and extended attributes are ignored. Nothing is
+/// cached but the block group descriptors, every read goes to the device.
+pub(crate) struct Ext2Fs {
+    device: Arc<dyn BlockDevice>,
+    block_size: u64,
+    inode_size: u64,
+    inodes_per_group: u32,
+    inode_count: u32,
+    block_count: u32,
+    free_blocks: u32,
+    free_inodes: u32,
+    /// First block of the inode table of every block group
+    inode_tables: Vec<u32>,
+}
+
+/// Components of `path` as owned strings, so symlink targets read on the
+/// way can be spliced in
+fn split_path(path: &str) -> Vec<String> {
+    Path::new(path).components().map(|c| c.as_str().to_string()).collect()
+}
+
+/// Reads `buf.len()` bytes starting at byte `offset` of `device`
+fn read_bytes(device: &dyn BlockDevice, offset: u64, buf: &mut [u8]) -> Result<(), FsError> {
+    let mut sector = [0u8; SECTOR_SIZE];
+    let (mut pos, mut done) = (offset, 0);
+    while done < buf.len() {
+        let lba = pos / SECTOR_SIZE as u64;
+        let at = (pos % SECTOR_SIZE as u64) as usize;
+        let len = if at == 0 && buf.len() - done >= SECTOR_SIZE {
+            // Whole sectors go straight into the buffer
+            let len = (buf.len() - done) / SECTOR_SIZE * SECTOR_SIZE;
+            block_on(device.read_sectors(lba, &mut buf[done..done + len]))?;
+            len
+        } else {
+            let len = (SECTOR_SIZE - at).min(buf.len() - done);
+            block_on(device.read_sectors(lba, &mut sector))?;
+            buf[done..done + len].copy_from_slice(&sector[at..at + len]);
+            len
+        };
+        done += len;
+        pos += len as u64;
+    }
+    Ok(())
+}
+
+impl Inode {
+    fn kind(&self) -> FileType {
+        match self.mode & MODE_TYPE {
+            MODE_DIR => FileType::Directory,
+            MODE_SYMLINK => FileType::Symlink,
+            MODE_CHAR_DEVICE => FileType::CharDevice,
+            // Block devices, fifos and sockets have nothing better to map to
+            _ => FileType::File,
+        }
+    }
+
+    fn pointer(&self, index: usize) -> u32 {
+        le32(&self.block, index * 4)
+    }
+}
+
+impl Ext2Fs {
+    /// Reads the superblock of `device`, fails with `InvalidData` if it does
+    /// not hold an ext2 volume and `Unsupported` for ext4 only features
+    pub(crate) fn new(device: Arc<dyn BlockDevice>) -> Result<Self, FsError> {
+        let mut sb = [0u8; 1024];
+        read_bytes(&*device, SUPERBLOCK_OFFSET, &mut sb)?;
+        if le16(&sb, 56) != MAGIC {
+            return Err(FsError::InvalidData);
+        }
+        if le32(&sb, 96) & !SUPPORTED_INCOMPAT != 0 {
+            return Err(FsError::Unsupported);
+        }
+
+        let log_block_size = le32(&sb, 24);
+        if log_block_size > 6 {
+            return Err(FsError::InvalidData);
+        }
+        let block_size = 1024u64 << log_block_size;
+        let inode_size = match le32(&sb, 76) {
+            0 => GOOD_OLD_INODE_SIZE as u64,
+            _ => le16(&sb, 88) as u64,
+        };
+        let block_count = le32(&sb, 4);
+        let first_data_block = le32(&sb, 20);
+        let blocks_per_group = le32(&sb, 32);
+        let inodes_per_group = le32(&sb, 40);
+        if inode_size < GOOD_OLD_INODE_SIZE as u64
+            || inode_size > block_size
+            || blocks_per_group == 0
+            || inodes_per_group == 0
+            || block_count <= first_data_block
+            || block_count as u64 * block_size > device.sector_count() * SECTOR_SIZE as u64
+        {
+            return Err(FsError::InvalidData);
+        }
+
+        // The descriptor table starts in the block after the superblock
+        let groups = (block_count - first_data_block).div_ceil(blocks_per_group) as usize;
+        let mut descriptors = Vec::new();
+        descriptors
+            .try_reserve_exact(groups * DESCRIPTOR_SIZE)
+            .map_err(|_| FsError::NoSpace)?;
+        descriptors.resize(groups * DESCRIPTOR_SIZE, 0);
+        read_bytes(&*device, (first_data_block as u64 + 1) * block_size, &mut descriptors)?;
+        let inode_tables = descriptors
+            .chunks_exact(DESCRIPTOR_SIZE)
+            .map(|descriptor| le32(descriptor, 8))
+            .collect();
+
+        Ok(Ext2Fs {
+            device,
+            block_size,
+            inode_size,
+            inodes_per_group,
+            inode_count: le32(&sb, 0),
+            block_count,
+            free_blocks: le32(&sb, 12),
+            free_inodes: le32(&sb, 16),
+            inode_tables,
+        })
+    }
+
+    fn read_inode(&self, ino: u32) -> Result<Inode, FsError> {
+        if ino == 0 || ino > self.inode_count {
+            return Err(FsError::InvalidData);
+        }
+        let group = ((ino - 1) / self.inodes_per_group) as usize;
+        let index = ((ino - 1) % self.inodes_per_group) as u64;
+        let table = *self.inode_tables.get(group).ok_or(FsError::InvalidData)?;
+
+        let mut raw = [0u8; GOOD_OLD_INODE_SIZE];
+        read_bytes(&*self.device, table as u64 * self.block_size + index * self.inode_size, &mut raw)?;
+        let mode = le16(&raw, 0);
+        let mut size = le32(&raw, 4) as u64;
+        // Revision 1 keeps the upper half of regular file sizes here
+        if mode & MODE_TYPE != MODE_DIR {
+            size |= (le32(&raw, 108) as u64) << 32;
+        }
+        let mut block = [0u8; 60];
+        block.copy_from_slice(&raw[40..100]);
+        Ok(Inode {
+            ino,
+            mode,
+            size,
+            links: le16(&raw, 26),
+            sectors: le32(&raw, 28),
+            file_acl: le32(&raw, 104),
+            block,
+        })
+    }
+
+    /// Entry `index` of the block of pointers `block`, 0 for a hole
+    fn read_pointer(&self, block: u32, index: u64) -> Result<u32, FsError> {
+        if block == 0 {
+            return Ok(0);
+        }
+        let mut raw = [0u8; 4];
+        read_bytes(&*self.device, block as u64 * self.block_size + index * 4, &mut raw)?;
+        Ok(u32::from_le_bytes(raw))
+    }
+
+    /// Device block holding block `n` of the file, 0 for a hole
+    fn map_block(&self, inode: &Inode, n: u64) -> Result<u32, FsError> {
+        let per_block = self.block_size / 4;
+        if n < DIRECT_BLOCKS {
+            return Ok(inode.pointer(n as usize));
+        }
+        let n = n - DIRECT_BLOCKS;
+        if n < per_block {
+            return self.read_pointer(inode.pointer(12), n);
+        }
+        let n = n - per_block;
+        if n < per_block * per_block {
+            let middle = self.read_pointer(inode.pointer(13), n / per_block)?;
+            return self.read_pointer(middle, n % per_block);
+        }
+        let n = n - per_block * per_block;
+        if n < per_block * per_block * per_block {
+            let upper = self.read_pointer(inode.pointer(14), n / (per_block * per_block))?;
+            let middle = self.read_pointer(upper, n / per_block % per_block)?;
+            return self.read_pointer(middle, n % per_block);
+        }
+        Err(FsError::InvalidData)
+    }
+
+    /// Copies file bytes from `offset` on into `buf`, which must not reach
+    /// past the end of the file
+    fn read_data(&self, inode: &Inode, offset: u64, buf: &mut [u8]) -> Result<(), FsError> {
+        let (mut pos, mut done) = (offset, 0);
+        while done < buf.len() {
+            let within = pos % self.block_size;
+            let len = ((self.block_size - within) as usize).min(buf.len() - done);
+            match self.map_block(inode, pos / self.block_size)? {
+                0 => buf[done..done + len].fill(0),
+                block => read_bytes(&*self.device, block as u64 * self.block_size + within, &mut buf[done..done + len])?,
+            }
+            done += len;
+            pos += len as u64;
+        }
+        Ok(())
+    }
+
+    fn read_all(&self, inode: &Inode) -> Result<Vec<u8>, FsError> {
+        let mut data = Vec::new();
+        data.try_reserve_exact(inode.size as usize)
+            .map_err(|_| FsError::NoSpace)?;
+        data.resize(inode.size as usize, 0);
+        self.read_data(inode, 0, &mut data)?;
+        Ok(data)
+    }
+
+    /// Names and inode numbers in the directory `inode`, without `.` and `..`
+    fn read_dir(&self, inode: &Inode) -> Result<Vec<(String, u32)>, FsError> {
+        if inode.kind() != FileType::Directory {
+            return Err(FsError::NotADirectory);
+        }
+        let data = self.read_all(inode)?;
+        let mut entries = Vec::new();
+        let mut at = 0;
+        while at + 8 <= data.len() {
+            let ino = le32(&data, at);
+            let rec_len = le16(&data, at + 4) as usize;
+            let name_len = data[at + 6] as usize;
+            if rec_len < 8 || at + rec_len > data.len() || 8 + name_len > rec_len {
+                return Err(FsError::InvalidData);
+            }
+            let name = &data[at + 8..at + 8 + name_len];
+            if ino != 0 && name != b"." && name != b".." {
+                entries.push((String::from_utf8_lossy(name).into_owned(), ino));
+            }
+            at += rec_len;
+        }
+        Ok(entries)
+    }
+
+    fn read_symlink(&self, inode: &Inode) -> Result<String, FsError> {
+        if inode.kind() != FileType::Symlink {
+            return Err(FsError::InvalidArgument);
+        }
+        // A fast symlink has no data blocks, only maybe an attribute block
+        let acl_sectors = if inode.file_acl != 0 { self.block_size / SECTOR_SIZE as u64 } else { 0 };
+        let target = if inode.size < FAST_SYMLINK_LEN && inode.sectors as u64 == acl_sectors {
+            inode.block[..inode.size as usize].to_vec()
+        } else {
+            self.read_all(inode)?
+        };
+        String::from_utf8(target).map_err(|_| FsError::InvalidData)
+    }
+
+    /// Walks `path` from the root. Symlinks met on the way are followed, the
+    /// last component only if `follow_last` is set.
+    fn resolve(&self, path: &str, follow_last: bool) -> Result<Inode, FsError> {
+        // Directories walked so far, so `..` can step back out of them
+        let mut stack: Vec<u32> = Vec::new();
+        let mut pending: Vec<String> = split_path(path).into_iter().rev().collect();
+        let mut follows = 0;
+        let mut inode = self.read_inode(ROOT_INO)?;
+
+        while let Some(part) = pending.pop() {
+            match part.as_str() {
+                "/" => {
+                    stack.clear();
+                    inode = self.read_inode(ROOT_INO)?;
+                    continue;
+                }
+                "." => continue,
+                ".." => {
+                    inode = self.read_inode(stack.pop().unwrap_or(ROOT_INO))?;
+                    continue;
+                }
+                _ => {}
+            }
+
+            let (_, child) = self
+                .read_dir(&inode)?
+                .into_iter()
+                .find(|(name, _)| *name == part)
+                .ok_or(FsError::NotFound)?;
+            let child = self.read_inode(child)?;
+
+            if child.kind() == FileType::Symlink && (follow_last || !pending.is_empty()) {
+                follows += 1;
+                if follows > MAX_SYMLINK_FOLLOWS {
+                    return Err(FsError::TooManySymlinks);
+                }
+                // An absolute target starts with "/", which restarts the walk
+                pending.extend(split_path(&self.read_symlink(&child)?).into_iter().rev());
+                continue;
+            }
+
+            stack.push(inode.ino);
+            inode = child;
+        }
+        Ok(inode)
+    }
+
+    fn metadata(&self, inode: &Inode) -> Result<Metadata, FsError> {
+        let size = match inode.kind() {
+            FileType::Directory => self.read_dir(inode)?.len() as u64,
+            _ => inode.size,
+        };
+        Ok(Metadata {
+            ino: inode.ino as u64,
+            kind: inode.kind(),
+            size,
+            created: 0,
+            modified: 0,
+            accessed: 0,
+            mode: inode.mode & 0o7777,
+            nlink: inode.links as u32,
+        })
+    }
+
+    /// Fails like a lookup would for a missing path, `ReadOnly` otherwise
+    fn read_only(&self, path: &str) -> Result<(), FsError> {
+        self.resolve(path, false).and(Err(FsError::ReadOnly))
+    }
+}
+
+impl FileSystem for Ext2Fs {
+    fn name(&self) -> &'static str {
+        "ext2"
+    }
+
+    fn read(&self, path: &str) -> Result<Vec<u8>, FsError> {
+        let inode = self.resolve(path, true)?;
+        if inode.kind() == FileType::Directory {
+            return Err(FsError::IsADirectory);
+        }
+        self.read_all(&inode)
+    }
+
+    fn write(&self, path: &str, _data: &[u8]) -> Result<(), FsError> {
+        self.read_only(path)
+    }
+
+    fn read_at(&self, path: &str, offset: u64, buf: &mut [u8]) -> Result<usize, FsError> {
+        let inode = self.resolve(path, true)?;
+        if inode.kind() == FileType::Directory {
+            return Err(FsError::IsADirectory);
+        }
+        if offset >= inode.size {
+            return Ok(0);
+        }
+        let len = (inode.size - offset).min(buf.len() as u64) as usize;
+        self.read_data(&inode, offset, &mut buf[..len])?;
+        Ok(len)
+    }
+
+    fn write_at(&self, path: &str, _offset: u64, _data: &[u8]) -> Result<usize, FsError> {
+        self.read_only(path).map(|_| 0)
+    }
+
+    fn truncate(&self, path: &str, _len: u64) -> Result<(), FsError> {
+        self.read_only(path)
+    }
+
+    fn touch(&self, _path: &str) -> Result<(), FsError> {
+        Err(FsError::ReadOnly)
+    }
+
+    fn create_dir(&self, _path: &str) -> Result<(), FsError> {
+        Err(FsError::ReadOnly)
+    }
+
+    fn list_dir(&self, path: &str) -> Result<Vec<String>, FsError> {
+        let inode = self.resolve(path, true)?;
+        let entries = self.read_dir(&inode)?;
+        Ok(entries.into_iter().map(|(name, _)| name).collect())
+    }
+
+    fn stat(&self, path: &str) -> Result<Metadata, FsError> {
+        let inode = self.resolve(path, true)?;
+        self.metadata(&inode)
+    }
+
+    fn statfs(&self) -> Result<FsStats, FsError> {
+        Ok(FsStats {
+            total_bytes: self.block_count as u64 * self.block_size,
+            used_bytes: self.block_count.saturating_sub(self.free_blocks) as u64 * self.block_size,
+            total_inodes: self.inode_count as u64,
+            used_inodes: self.inode_count.saturating_sub(self.free_inodes) as u64,
+        })
+    }
+
+    fn symlink_metadata(&self, path: &str) -> Result<Metadata, FsError> {
+        let inode = self.resolve(path, false)?;
+        self.metadata(&inode)
+    }
+
+    fn read_link(&self, path: &str) -> Result<String, FsError> {
+        let inode = self.resolve(path, false)?;
+        self.read_symlink(&inode)
+    }
+
+    fn remove_file(&self, path: &str) -> Result<(), FsError> {
+        self.read_only(path)
+    }
+
+    fn remove_dir(&self, path: &str) -> Result<(), FsError> {
+        self.read_only(path)
+    }
+
+    fn rename(&self, from: &str, _to: &str) -> Result<(), FsError> {
+        self.read_only(from)
+    }
+}
+
+#[test_case]
+fn test_ext2() {
+    use crate::block::{bytes::{put16, put32}, MemDisk};
+
+    fn dirent(block: &mut [u8], at: usize, ino: u32, rec_len: u16, name: &str) {
+        put32(block, at, ino);
+        put16(block, at + 4, rec_len);
+        block[at + 6] = name.len() as u8;
+        block[at + 8..at + 8 + name.len()].copy_from_slice(name.as_bytes());
+    }
+    fn inode(image: &mut [u8], ino: usize, mode: u16, size: u32, links: u16, blocks: &[u32]) {
+        let at = 5 * 1024 + (ino - 1) * 128;
+        put16(image, at, mode);
+        put32(image, at + 4, size);
+        put16(image, at + 26, links);
+        for (i, &block) in blocks.iter().enumerate() {
+            put32(image, at + 40 + i * 4, block);
+        }
+    }
+
+    // 16 blocks of 1 KiB in one group: superblock in block 1, descriptors
+    // in 2, the inode table in 5 and 6, data from block 7 on
+    let mut image = alloc::vec![0u8; 16 * 1024];
+    let sb = &mut image[1024..2048];
+    put32(sb, 0, 16);
+    put32(sb, 4, 16);
+    put32(sb, 12, 7);
+    put32(sb, 16, 10);
+    put32(sb, 20, 1);
+    put32(sb, 32, 8192);
+    put32(sb, 40, 16);
+    put16(sb, 56, MAGIC);
+    put32(sb, 76, 1);
+    put16(sb, 88, 128);
+    put32(sb, 96, INCOMPAT_FILETYPE);
+    put32(&mut image, 2048 + 8, 5);
+
+    let root = &mut image[7 * 1024..8 * 1024];
+    dirent(root, 0, 2, 12, ".");
+    dirent(root, 12, 2, 12, "..");
+    dirent(root, 24, 11, 20, "hello.txt");
+    dirent(root, 44, 12, 12, "link");
+    dirent(root, 56, 13, 12, "sub");
+    dirent(root, 68, 0, 12, "gone");
+    dirent(root, 80, 14, 1024 - 80, "sparse");
+    let sub = &mut image[9 * 1024..10 * 1024];
+    dirent(sub, 0, 13, 12, ".");
+    dirent(sub, 12, 2, 12, "..");
+    dirent(sub, 24, 15, 1000, "up");
+    image[8 * 1024..8 * 1024 + 6].copy_from_slice(b"hello\n");
+    // Block 12 of "sparse" goes through the indirect block 10 to block 11
+    put32(&mut image, 10 * 1024, 11);
+    image[11 * 1024] = b'!';
+
+    inode(&mut image, 2, MODE_DIR | 0o755, 1024, 3, &[7]);
+    inode(&mut image, 11, 0x8000 | 0o644, 6, 1, &[8]);
+    inode(&mut image, 13, MODE_DIR | 0o755, 1024, 2, &[9]);
+    inode(&mut image, 14, 0x8000 | 0o600, 13 * 1024, 1, &[0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 10]);
+    inode(&mut image, 12, MODE_SYMLINK | 0o777, 9, 1, &[]);
+    image[5 * 1024 + 11 * 128 + 40..][..9].copy_from_slice(b"hello.txt");
+    inode(&mut image, 15, MODE_SYMLINK | 0o777, 2, 1, &[]);
+    image[5 * 1024 + 14 * 128 + 40..][..2].copy_from_slice(b"..");
+
+    let disk = Arc::new(MemDisk::new("ext0", 32));
+    block_on(disk.write_sectors(0, &image)).unwrap();
+    let fs = Ext2Fs::new(disk).unwrap();
+
+    assert_eq!(fs.list_dir("/").unwrap(), ["hello.txt", "link", "sub", "sparse"]);
+    assert_eq!(fs.read("/hello.txt").unwrap(), b"hello\n");
+    assert_eq!(fs.read("/link").unwrap(), b"hello\n");
+    assert_eq!(fs.read_link("/link").unwrap(), "hello.txt");
+    assert_eq!(fs.symlink_metadata("/link").unwrap().kind, FileType::Symlink);
+    assert_eq!(fs.read("/sub/up/sub/up/hello.txt").unwrap(), b"hello\n");
+
+    let meta = fs.stat("/sparse").unwrap();
+    assert_eq!((meta.ino, meta.mode, meta.size), (14, 0o600, 13 * 1024));
+    let mut buf = [1u8; 4];
+    assert_eq!(fs.read_at("/sparse", 12 * 1024 - 2, &mut buf).unwrap(), 4);
+    assert_eq!(buf, *b"\0\0!\0");
+    assert_eq!(fs.stat("/").unwrap().size, 4);
+
+    assert_eq!(fs.write("/hello.txt", b"x"), Err(FsError::ReadOnly));
+    assert_eq!(fs.remove_file("/missing"), Err(FsError::NotFound));
+    assert_eq!(fs.read("/sub"), Err(FsError::IsADirectory));
+    assert_eq!(fs.statfs().unwrap().used_inodes, 6);
+}
@@ -0,0 +1,1349 @@
+extern crate alloc;
+use alloc::{format, string::String, sync::Arc, vec::Vec};
+use crate::block::{block_on, bytes::{le16, le32, put16, put32}, BlockDevice, SECTOR_SIZE};
+use super::{lock::FsLock, path::{Component, Path}, FileSystem, FileType, FsError, FsStats, Metadata};
+
+const ROOT_INO: u64 = 1;
+const FILE_MODE: u16 = 0o644;
+const READ_ONLY_MODE: u16 = 0o444;
+const DIR_MODE: u16 = 0o755;
+
+const ATTR_READ_ONLY: u8 = 0x01;
+const ATTR_VOLUME_ID: u8 = 0x08;
+const ATTR_DIRECTORY: u8 = 0x10;
+const ATTR_ARCHIVE: u8 = 0x20;
+/// Read-only, hidden, system and volume id together mark a long name entry
+const ATTR_LONG_NAME: u8 = 0x0F;
+
+const ENTRY_SIZE: usize = 32;
+/// First name byte of a deleted entry
+const DELETED: u8 = 0xE5;
+/// Reserved byte flags for an all lowercase base name or extension
+const LOWER_BASE: u8 = 0x08;
+const LOWER_EXT: u8 = 0x10;
+/// Set in the ordinal of the long name entry that holds the end of the name
+const LAST_LONG_ENTRY: u8 = 0x40;
+/// Characters of the name stored in one long name entry
+const LONG_CHARS: usize = 13;
+/// Where those characters sit inside the entry, as UTF-16 units
+const LONG_OFFSETS: [usize; LONG_CHARS] = [1, 3, 5, 7, 9, 14, 16, 18, 20, 22, 24, 28, 30];
+const MAX_NAME_LEN: usize = 255;
+/// Characters allowed in a short name besides letters and digits
+const SHORT_EXTRA: &[u8] = b"!#$%&'()-@^_`{}~";
+/// 1980-01-01, the first day FAT can store. There is no clock to read the
+/// real date from.
+const DEFAULT_DATE: u16 = (1 << 5) | 1;
+
+const FSINFO_LEAD: u32 = 0x4161_5252;
+const FSINFO_STRUCT: u32 = 0x6141_7272;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum FatType {
+    Fat12,
+    Fat16,
+    Fat32,
+}
+
+/// Sector and byte offset of one directory entry
+type Slot = (u64, usize);
+
+/// Where the entries of a directory live
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum DirLoc {
+    /// The fixed size root directory of FAT12 and FAT16
+    FixedRoot,
+    /// A cluster chain starting at this cluster
+    Chain(u32),
+}
+
+/// A directory entry with the long name it was found under
+#[derive(Clone)]
+struct Entry {
+    name: String,
+    /// The short entry as stored on disk
+    raw: [u8; ENTRY_SIZE],
+    slot: Slot,
+    /// Slots of the long name entries in front of it
+    long: Vec<Slot>,
+}
+
+/// What a path resolves to
+enum Node {
+    Root,
+    Entry(Entry),
+}
+
+/// The allocation table sectors read last, so walking a chain does not hit
+/// the disk for every cluster
+struct Window {
+    /// First of the two sectors in `buf`, `u64::MAX` while empty
+    lba: u64,
+    buf: [u8; 2 * SECTOR_SIZE],
+}
+
+struct FatState {
+    window: Window,
+    /// Where the search for a free cluster starts
+    next_free: u32,
+    /// The free cluster count in the FSInfo sector has been marked unknown
+    free_count_cleared: bool,
+}
+
+/// Long name entries collected in front of a short entry
+#[derive(Default)]
+struct LongName {
+    /// 13 UTF-16 units per entry, the end of the name first as on disk
+    parts: Vec<[u16; LONG_CHARS]>,
+    slots: Vec<Slot>,
+    checksum: u8,
+    /// Ordinal the next entry must have, 0 once the name is complete
+    expect: u8,
+}
+
+/// A FAT12, FAT16 or FAT32 volume on a block device
+///
+/// Names are matched without regard to ASCII case. Long names are read and
+/// written, every file also gets an 8.3 alias. Timestamps are not kept.
+pub(crate) struct FatFs {
+    device: Arc<dyn BlockDevice>,
+    kind: FatType,
+    sectors_per_cluster: u64,
+    fat_start: u64,
+    fat_sectors: u64,
+    fat_count: u64,
+    root_start: u64,
+    root_sectors: u64,
+    data_start: u64,
+    cluster_count: u32,
+    /// First cluster of the root directory on FAT32
+    root_cluster: u32,
+    fsinfo: Option<u64>,
+    state: FsLock<FatState>,
+}
+
+fn split_path(path: &str) -> Vec<Component<'_>> {
+    Path::new(path).components().collect()
+}
+
+/// Splits a path into its parent components and the final name
+fn split_parent(path: &str) -> Result<(Vec<Component<'_>>, &str), FsError> {
+    let mut parts = split_path(path);
+    match parts.pop() {
+        Some(Component::Normal(name)) => Ok((parts, name)),
+        _ => Err(FsError::InvalidPath),
+    }
+}
+
+/// Checksum of a short name, repeated in each of its long name entries
+fn checksum(short: &[u8; 11]) -> u8 {
+    short.iter().fold(0u8, |sum, &b| sum.rotate_right(1).wrapping_add(b))
+}
+
+impl Window {
+    fn new() -> Self {
+        Window {
+            lba: u64::MAX,
+            buf: [0; 2 * SECTOR_SIZE],
+        }
+    }
+}
+
+impl LongName {
+    fn push(&mut self, raw: &[u8], slot: Slot) {
+        let ord = raw[0] & !LAST_LONG_ENTRY;
+        if raw[0] & LAST_LONG_ENTRY != 0 {
+            *self = LongName {
+                checksum: raw[13],
+                expect: ord,
+                ..LongName::default()
+            };
+        }
+        if ord == 0 || ord != self.expect || raw[13] != self.checksum {
+            *self = LongName::default();
+            return;
+        }
+        let mut units = [0u16; LONG_CHARS];
+        for (unit, &at) in units.iter_mut().zip(LONG_OFFSETS.iter()) {
+            *unit = le16(raw, at);
+        }
+        self.parts.push(units);
+        self.slots.push(slot);
+        self.expect -= 1;
+    }
+
+    /// The name, if every part was found and they belong to `short`
+    fn finish(&self, short: &[u8; 11]) -> Option<String> {
+        if self.parts.is_empty() || self.expect != 0 || self.checksum != checksum(short) {
+            return None;
+        }
+        let units: Vec<u16> = self
+            .parts
+            .iter()
+            .rev()
+            .flatten()
+            .copied()
+            .take_while(|&unit| unit != 0)
+            .collect();
+        String::from_utf16(&units).ok()
+    }
+}
+
+/// Turns `NAME    TXT` into "NAME.TXT", lowercased as the flags ask
+fn short_to_string(short: &[u8; 11], flags: u8) -> String {
+    let mut name = String::new();
+    let mut push = |part: &[u8], lower: bool| {
+        for (i, &b) in part.iter().enumerate() {
+            // 0x05 stands in for a real 0xE5, which would mark the entry deleted
+            let b = if i == 0 && b == 0x05 { DELETED } else { b };
+            name.push(if lower { b.to_ascii_lowercase() } else { b } as char);
+        }
+    };
+    let base = short[..8].trim_ascii_end();
+    let ext = short[8..].trim_ascii_end();
+    push(base, flags & LOWER_BASE != 0);
+    if !ext.is_empty() {
+        push(b".", false);
+        push(ext, flags & LOWER_EXT != 0);
+    }
+    name
+}
+
+/// Whether FAT can store `name` at all
+fn valid_name(name: &str) -> bool {
+    !name.is_empty()
+        && name.encode_utf16().count() <= MAX_NAME_LEN
+        && !name.ends_with(['.', ' '])
+        && !name.chars().any(|c| c < ' ' || "\"*/:<>?\\|".contains(c))
+}
+
+fn short_char(b: u8) -> bool {
+    b.is_ascii_alphanumeric() || SHORT_EXTRA.contains(&b)
+}
+
+/// The short name `name` can be stored as on its own, with the case flags
+/// for the reserved byte, if it is a plain 8.3 name
+fn exact_short_name(name: &str) -> Option<([u8; 11], u8)> {
+    let (base, ext) = name.rsplit_once('.').unwrap_or((name, ""));
+    if base.is_empty() || base.len() > 8 || ext.len() > 3 {
+        return None;
+    }
+    let mut flags = 0;
+    for (part, lower) in [(base, LOWER_BASE), (ext, LOWER_EXT)] {
+        if !part.bytes().all(short_char) {
+            return None;
+        }
+        let has_lower = part.bytes().any(|b| b.is_ascii_lowercase());
+        let has_upper = part.bytes().any(|b| b.is_ascii_uppercase());
+        match (has_lower, has_upper) {
+            (true, true) => return None,
+            (true, false) => flags |= lower,
+            _ => {}
+        }
+    }
+
+    let mut short = [b' '; 11];
+    short[..base.len()].copy_from_slice(base.as_bytes());
+    short[8..8 + ext.len()].copy_from_slice(ext.as_bytes());
+    short.make_ascii_uppercase();
+    Some((short, flags))
+}
+
+/// Picks a `BASIS~N.EXT` alias for a name that needs long name entries
+fn generate_short_name(name: &str, taken: &[[u8; 11]]) -> Result<[u8; 11], FsError> {
+    let clean = |part: &str| -> Vec<u8> {
+        part.chars()
+            .filter(|&c| c != ' ' && c != '.')
+            .map(|c| match c.to_ascii_uppercase() {
+                c if c.is_ascii() && short_char(c as u8) => c as u8,
+                _ => b'_',
+            })
+            .collect()
+    };
+    let trimmed = name.trim_start_matches('.');
+    let (base, ext) = trimmed.rsplit_once('.').unwrap_or((trimmed, ""));
+    let base = clean(base);
+    let mut ext = clean(ext);
+    ext.truncate(3);
+
+    for n in 1..1_000_000u32 {
+        let tail = format!("~{}", n);
+        let keep = base.len().min(8 - tail.len());
+        let mut short = [b' '; 11];
+        short[..keep].copy_from_slice(&base[..keep]);
+        short[keep..keep + tail.len()].copy_from_slice(tail.as_bytes());
+        short[8..8 + ext.len()].copy_from_slice(&ext);
+        if !taken.contains(&short) {
+            return Ok(short);
+        }
+    }
+    Err(FsError::NoSpace)
+}
+
+/// Long name entries for `name`, in the order they go on disk
+fn long_entries(name: &str, short: &[u8; 11]) -> Vec<[u8; ENTRY_SIZE]> {
+    let units: Vec<u16> = name.encode_utf16().collect();
+    let count = units.len().div_ceil(LONG_CHARS);
+    let sum = checksum(short);
+    (1..=count)
+        .rev()
+        .map(|ord| {
+            let mut raw = [0u8; ENTRY_SIZE];
+            raw[0] = ord as u8 | if ord == count { LAST_LONG_ENTRY } else { 0 };
+            raw[11] = ATTR_LONG_NAME;
+            raw[13] = sum;
+            for (i, &at) in LONG_OFFSETS.iter().enumerate() {
+                let index = (ord - 1) * LONG_CHARS + i;
+                // The name ends with a 0 unit, the rest is padded with 0xFFFF
+                let unit = match index.cmp(&units.len()) {
+                    core::cmp::Ordering::Less => units[index],
+                    core::cmp::Ordering::Equal => 0,
+                    core::cmp::Ordering::Greater => 0xFFFF,
+                };
+                put16(&mut raw, at, unit);
+            }
+            raw
+        })
+        .collect()
+}
+
+/// A short entry without a name yet
+fn new_entry(attr: u8, cluster: u32) -> [u8; ENTRY_SIZE] {
+    let mut raw = [0u8; ENTRY_SIZE];
+    raw[11] = attr;
+    put16(&mut raw, 16, DEFAULT_DATE);
+    put16(&mut raw, 18, DEFAULT_DATE);
+    put16(&mut raw, 24, DEFAULT_DATE);
+    put16(&mut raw, 20, (cluster >> 16) as u16);
+    put16(&mut raw, 26, cluster as u16);
+    raw
+}
+
+impl Entry {
+    fn parse(raw: &[u8], slot: Slot, long: LongName) -> Entry {
+        let mut entry = Entry {
+            name: String::new(),
+            raw: [0; ENTRY_SIZE],
+            slot,
+            long: long.slots.clone(),
+        };
+        entry.raw.copy_from_slice(raw);
+        let short = entry.short();
+        entry.name = long
+            .finish(&short)
+            .unwrap_or_else(|| short_to_string(&short, raw[12]));
+        entry
+    }
+
+    fn short(&self) -> [u8; 11] {
+        let mut short = [0u8; 11];
+        short.copy_from_slice(&self.raw[..11]);
+        short
+    }
+
+    fn is_dir(&self) -> bool {
+        self.raw[11] & ATTR_DIRECTORY != 0
+    }
+
+    fn cluster(&self) -> u32 {
+        ((le16(&self.raw, 20) as u32) << 16) | le16(&self.raw, 26) as u32
+    }
+
+    fn set_cluster(&mut self, cluster: u32) {
+        put16(&mut self.raw, 20, (cluster >> 16) as u16);
+        put16(&mut self.raw, 26, cluster as u16);
+    }
+
+    fn size(&self) -> u64 {
+        le32(&self.raw, 28) as u64
+    }
+
+    /// Also flags the file for backup, as every FAT driver does on a change
+    fn set_size(&mut self, size: u64) {
+        put32(&mut self.raw, 28, size as u32);
+        self.raw[11] |= ATTR_ARCHIVE;
+    }
+
+    /// Every slot the entry takes, long name entries first
+    fn slots(&self) -> Vec<Slot> {
+        let mut slots = self.long.clone();
+        slots.push(self.slot);
+        slots
+    }
+
+    fn metadata(&self, size: u64) -> Metadata {
+        let (kind, mode, nlink) = if self.is_dir() {
+            (FileType::Directory, DIR_MODE, 2)
+        } else if self.raw[11] & ATTR_READ_ONLY != 0 {
+            (FileType::File, READ_ONLY_MODE, 1)
+        } else {
+            (FileType::File, FILE_MODE, 1)
+        };
+        let (lba, at) = self.slot;
+        Metadata {
+            // Stable until the entry is renamed, FAT has no inode numbers
+            ino: lba * (SECTOR_SIZE / ENTRY_SIZE) as u64 + (at / ENTRY_SIZE) as u64,
+            kind,
+            size,
+            created: 0,
+            modified: 0,
+            accessed: 0,
+            mode,
+            nlink,
+        }
+    }
+}
+
+impl FatFs {
+    /// Reads the boot sector of `device`, fails with `InvalidData` if it
+    /// does not hold a FAT volume
+    pub(crate) fn new(device: Arc<dyn BlockDevice>) -> Result<Self, FsError> {
+        let mut boot = [0u8; SECTOR_SIZE];
+        block_on(device.read_sectors(0, &mut boot))?;
+        if boot[510..] != [0x55, 0xAA] {
+            return Err(FsError::InvalidData);
+        }
+        if le16(&boot, 11) as usize != SECTOR_SIZE {
+            return Err(FsError::Unsupported);
+        }
+
+        let sectors_per_cluster = boot[13] as u64;
+        let reserved = le16(&boot, 14) as u64;
+        let fat_count = boot[16] as u64;
+        let root_entries = le16(&boot, 17) as u64;
+        let total = match le16(&boot, 19) {
+            0 => le32(&boot, 32) as u64,
+            total => total as u64,
+        };
+        let fat16_sectors = le16(&boot, 22);
+        let fat_sectors = match fat16_sectors {
+            0 => le32(&boot, 36) as u64,
+            size => size as u64,
+        };
+        if !sectors_per_cluster.is_power_of_two() || reserved == 0 || fat_count == 0 || fat_sectors == 0 {
+            return Err(FsError::InvalidData);
+        }
+
+        let fat_start = reserved;
+        let root_start = fat_start + fat_count * fat_sectors;
+        let root_sectors = (root_entries * ENTRY_SIZE as u64).div_ceil(SECTOR_SIZE as u64);
+        let data_start = root_start + root_sectors;
+        if data_start >= total || total > device.sector_count() {
+            return Err(FsError::InvalidData);
+        }
+        let cluster_count = ((total - data_start) / sectors_per_cluster).min(0x0FFF_FFF5) as u32;
+        if cluster_count == 0 {
+            return Err(FsError::InvalidData);
+        }
+
+        // Only FAT32 leaves the 16 bit table size empty. `mkfs.fat -F 32`
+        // also formats small disks, below the cluster count the spec asks for.
+        let kind = match cluster_count {
+            _ if fat16_sectors == 0 => FatType::Fat32,
+            0..4085 => FatType::Fat12,
+            4085..65525 => FatType::Fat16,
+            _ => return Err(FsError::InvalidData),
+        };
+        let table_bytes = match kind {
+            FatType::Fat12 => (cluster_count as u64 + 2) * 3 / 2,
+            FatType::Fat16 => (cluster_count as u64 + 2) * 2,
+            FatType::Fat32 => (cluster_count as u64 + 2) * 4,
+        };
+        if table_bytes > fat_sectors * SECTOR_SIZE as u64 {
+            return Err(FsError::InvalidData);
+        }
+
+        let (root_cluster, fsinfo) = match kind {
+            FatType::Fat32 => {
+                let root_cluster = le32(&boot, 44);
+                if root_entries != 0 || !(2..cluster_count + 2).contains(&root_cluster) {
+                    return Err(FsError::InvalidData);
+                }
+                let fsinfo = match le16(&boot, 48) {
+                    0 | 0xFFFF => None,
+                    sector => Some(sector as u64),
+                };
+                (root_cluster, fsinfo)
+            }
+            _ => (0, None),
+        };
+
+        Ok(FatFs {
+            device,
+            kind,
+            sectors_per_cluster,
+            fat_start,
+            fat_sectors,
+            fat_count,
+            root_start,
+            root_sectors,
+            data_start,
+            cluster_count,
+            root_cluster,
+            fsinfo,
+            state: FsLock::new(FatState {
+                window: Window::new(),
+                next_free: 2,
+                free_count_cleared: false,
+            }),
+        })
+    }
+
+    fn read_sectors(&self, lba: u64, buf: &mut [u8]) -> Result<(), FsError> {
+        Ok(block_on(self.device.read_sectors(lba, buf))?)
+    }
+
+    fn write_sectors(&self, lba: u64, data: &[u8]) -> Result<(), FsError> {
+        Ok(block_on(self.device.write_sectors(lba, data))?)
+    }
+
+    fn cluster_size(&self) -> u64 {
+        self.sectors_per_cluster * SECTOR_SIZE as u64
+    }
+
+    fn root(&self) -> DirLoc {
+        match self.kind {
+            FatType::Fat32 => DirLoc::Chain(self.root_cluster),
+            _ => DirLoc::FixedRoot,
+        }
+    }
+
+    fn cluster_lba(&self, cluster: u32) -> Result<u64, FsError> {
+        if !(2..self.cluster_count + 2).contains(&cluster) {
+            return Err(FsError::InvalidData);
+        }
+        Ok(self.data_start + (cluster - 2) as u64 * self.sectors_per_cluster)
+    }
+
+    /// Smallest table value that ends a chain
+    fn end_of_chain(&self) -> u32 {
+        match self.kind {
+            FatType::Fat12 => 0xFF8,
+            FatType::Fat16 => 0xFFF8,
+            FatType::Fat32 => 0x0FFF_FFF8,
+        }
+    }
+
+    /// Loads the table sectors holding the entry of `cluster`, returns the
+    /// byte offset of the entry in `window.buf`
+    fn load_table(&self, window: &mut Window, cluster: u32) -> Result<usize, FsError> {
+        let offset = match self.kind {
+            FatType::Fat12 => cluster as u64 + cluster as u64 / 2,
+            FatType::Fat16 => cluster as u64 * 2,
+            FatType::Fat32 => cluster as u64 * 4,
+        };
+        let lba = self.fat_start + offset / SECTOR_SIZE as u64;
+        if lba != window.lba {
+            // FAT12 entries may straddle two sectors
+            let count = if lba + 1 < self.fat_start + self.fat_sectors { 2 } else { 1 };
+            window.lba = u64::MAX;
+            self.read_sectors(lba, &mut window.buf[..count * SECTOR_SIZE])?;
+            window.lba = lba;
+        }
+        Ok((offset % SECTOR_SIZE as u64) as usize)
+    }
+
+    fn table_get(&self, window: &mut Window, cluster: u32) -> Result<u32, FsError> {
+        let at = self.load_table(window, cluster)?;
+        Ok(match self.kind {
+            FatType::Fat12 if cluster & 1 == 1 => (le16(&window.buf, at) >> 4) as u32,
+            FatType::Fat12 => (le16(&window.buf, at) & 0xFFF) as u32,
+            FatType::Fat16 => le16(&window.buf, at) as u32,
+            FatType::Fat32 => le32(&window.buf, at) & 0x0FFF_FFFF,
+        })
+    }
+
+    /// Sets the entry of `cluster` in every copy of the table
+    fn table_set(&self, window: &mut Window, cluster: u32, value: u32) -> Result<(), FsError> {
+        let at = self.load_table(window, cluster)?;
+        let buf = &mut window.buf;
+        match self.kind {
+            FatType::Fat12 => {
+                let old = le16(buf, at);
+                let new = if cluster & 1 == 1 {
+                    (old & 0x000F) | ((value as u16) << 4)
+                } else {
+                    (old & 0xF000) | (value as u16 & 0x0FFF)
+                };
+                put16(buf, at, new);
+            }
+            FatType::Fat16 => put16(buf, at, value as u16),
+            // The top four bits are reserved and must be kept
+            FatType::Fat32 => {
+                let old = le32(buf, at);
+                put32(buf, at, (old & 0xF000_0000) | (value & 0x0FFF_FFFF));
+            }
+        }
+        let count = if at + 1 >= SECTOR_SIZE { 2 } else { 1 };
+        for copy in 0..self.fat_count {
+            self.write_sectors(window.lba + copy * self.fat_sectors, &window.buf[..count * SECTOR_SIZE])?;
+        }
+        Ok(())
+    }
+
+    /// The cluster after `cluster` in its chain, `None` at the end
+    fn next(&self, window: &mut Window, cluster: u32) -> Result<Option<u32>, FsError> {
+        match self.table_get(window, cluster)? {
+            value if value >= self.end_of_chain() => Ok(None),
+            value if (2..self.cluster_count + 2).contains(&value) => Ok(Some(value)),
+            // Free or bad clusters have no place inside a chain
+            _ => Err(FsError::InvalidData),
+        }
+    }
+
+    /// Marks the free cluster count in the FSInfo sector unknown, before
+    /// the first change to the table makes it wrong
+    fn forget_free_count(&self, st: &mut FatState) -> Result<(), FsError> {
+        if st.free_count_cleared {
+            return Ok(());
+        }
+        st.free_count_cleared = true;
+        let Some(lba) = self.fsinfo else {
+            return Ok(());
+        };
+        let mut sector = [0u8; SECTOR_SIZE];
+        self.read_sectors(lba, &mut sector)?;
+        if le32(&sector, 0) == FSINFO_LEAD && le32(&sector, 484) == FSINFO_STRUCT {
+            put32(&mut sector, 488, u32::MAX);
+            self.write_sectors(lba, &sector)?;
+        }
+        Ok(())
+    }
+
+    fn zero_cluster(&self, cluster: u32) -> Result<(), FsError> {
+        let lba = self.cluster_lba(cluster)?;
+        let zeros = [0u8; SECTOR_SIZE];
+        for i in 0..self.sectors_per_cluster {
+            self.write_sectors(lba + i, &zeros)?;
+        }
+        Ok(())
+    }
+
+    /// Takes a free cluster and appends it to the chain ending at `prev`,
+    /// zeroing it first if `zero` is set
+    fn alloc(&self, st: &mut FatState, prev: Option<u32>, zero: bool) -> Result<u32, FsError> {
+        self.forget_free_count(st)?;
+        let end = self.cluster_count + 2;
+        let mut cluster = st.next_free.clamp(2, end - 1);
+        for _ in 0..self.cluster_count {
+            if self.table_get(&mut st.window, cluster)? == 0 {
+                if zero {
+                    self.zero_cluster(cluster)?;
+                }
+                self.table_set(&mut st.window, cluster, 0x0FFF_FFFF)?;
+                if let Some(prev) = prev {
+                    self.table_set(&mut st.window, prev, cluster)?;
+                }
+                st.next_free = cluster + 1;
+                return Ok(cluster);
+            }
+            cluster = if cluster + 1 == end { 2 } else { cluster + 1 };
+        }
+        Err(FsError::NoSpace)
+    }
+
+    /// Returns the chain starting at `first` to the free clusters, nothing
+    /// happens for 0
+    fn free_chain(&self, st: &mut FatState, first: u32) -> Result<(), FsError> {
+        let mut cluster = first;
+        for _ in 0..self.cluster_count {
+            if !(2..self.cluster_count + 2).contains(&cluster) {
+                break;
+            }
+            self.forget_free_count(st)?;
+            let next = self.table_get(&mut st.window, cluster)?;
+            self.table_set(&mut st.window, cluster, 0)?;
+            if cluster < st.next_free {
+                st.next_free = cluster;
+            }
+            cluster = next;
+        }
+        Ok(())
+    }
+
+    /// Copies bytes from `offset` on into `buf`, which must not reach past
+    /// the end of the file
+    fn read_data(&self, window: &mut Window, first: u32, offset: u64, buf: &mut [u8]) -> Result<(), FsError> {
+        let cluster_size = self.cluster_size();
+        let mut cluster = first;
+        for _ in 0..offset / cluster_size {
+            cluster = self.next(window, cluster)?.ok_or(FsError::InvalidData)?;
+        }
+
+        let mut sector = [0u8; SECTOR_SIZE];
+        let (mut pos, mut done) = (offset, 0);
+        while done < buf.len() {
+            if done > 0 && pos % cluster_size == 0 {
+                cluster = self.next(window, cluster)?.ok_or(FsError::InvalidData)?;
+            }
+            let lba = self.cluster_lba(cluster)? + (pos % cluster_size) / SECTOR_SIZE as u64;
+            let at = (pos % SECTOR_SIZE as u64) as usize;
+            let len = (SECTOR_SIZE - at).min(buf.len() - done);
+            if len == SECTOR_SIZE {
+                self.read_sectors(lba, &mut buf[done..done + len])?;
+            } else {
+                self.read_sectors(lba, &mut sector)?;
+                buf[done..done + len].copy_from_slice(&sector[at..at + len]);
+            }
+            done += len;
+            pos += len as u64;
+        }
+        Ok(())
+    }
+
+    /// Writes `data` at `offset` into the chain starting at `*first`, 0 for
+    /// none yet, allocating clusters as needed. `*first` is kept up to date
+    /// even if the write fails halfway.
+    fn write_data(&self, st: &mut FatState, first: &mut u32, offset: u64, data: &[u8]) -> Result<(), FsError> {
+        if data.is_empty() {
+            return Ok(());
+        }
+        let cluster_size = self.cluster_size();
+        let end = offset.checked_add(data.len() as u64).ok_or(FsError::InvalidArgument)?;
+        // New clusters only need zeroing if the write leaves part of them
+        let partial = |index: u64| offset > index * cluster_size || end < (index + 1) * cluster_size;
+
+        if *first == 0 {
+            *first = self.alloc(st, None, partial(0))?;
+        }
+        let (mut cluster, mut index) = (*first, 0);
+        let mut sector = [0u8; SECTOR_SIZE];
+        let (mut pos, mut done) = (offset, 0);
+        while done < data.len() {
+            while index < pos / cluster_size {
+                cluster = match self.next(&mut st.window, cluster)? {
+                    Some(next) => next,
+                    None => self.alloc(st, Some(cluster), partial(index + 1))?,
+                };
+                index += 1;
+            }
+            let lba = self.cluster_lba(cluster)? + (pos % cluster_size) / SECTOR_SIZE as u64;
+            let at = (pos % SECTOR_SIZE as u64) as usize;
+            let len = (SECTOR_SIZE - at).min(data.len() - done);
+            if len == SECTOR_SIZE {
+                self.write_sectors(lba, &data[done..done + len])?;
+            } else {
+                self.read_sectors(lba, &mut sector)?;
+                sector[at..at + len].copy_from_slice(&data[done..done + len]);
+                self.write_sectors(lba, &sector)?;
+            }
+            done += len;
+            pos += len as u64;
+        }
+        Ok(())
+    }
+
+    /// Zero extends a file from `size` to `len` bytes
+    fn grow(&self, st: &mut FatState, first: &mut u32, size: u64, len: u64) -> Result<(), FsError> {
+        // New clusters come zeroed, only the rest of the last cluster in use
+        // may still hold bytes of an older, longer file
+        let zeros = [0u8; SECTOR_SIZE];
+        let tail_end = len.min(size.next_multiple_of(self.cluster_size()));
+        let mut pos = size;
+        while pos < tail_end {
+            let count = (SECTOR_SIZE - (pos % SECTOR_SIZE as u64) as usize).min((tail_end - pos) as usize);
+            self.write_data(st, first, pos, &zeros[..count])?;
+            pos += count as u64;
+        }
+        if len > tail_end {
+            self.write_data(st, first, len - 1, &[0])?;
+        }
+        Ok(())
+    }
+
+    /// Cuts the chain starting at `*first` down to what `len` bytes need
+    fn shrink(&self, st: &mut FatState, first: &mut u32, len: u64) -> Result<(), FsError> {
+        if len == 0 {
+            self.free_chain(st, *first)?;
+            *first = 0;
+            return Ok(());
+        }
+        let mut last = *first;
+        for _ in 1..len.div_ceil(self.cluster_size()) {
+            last = self.next(&mut st.window, last)?.ok_or(FsError::InvalidData)?;
+        }
+        if let Some(rest) = self.next(&mut st.window, last)? {
+            self.table_set(&mut st.window, last, 0x0FFF_FFFF)?;
+            self.free_chain(st, rest)?;
+        }
+        Ok(())
+    }
+
+    /// Sectors holding the entries of `dir`, in order
+    fn dir_sectors(&self, window: &mut Window, dir: DirLoc) -> Result<Vec<u64>, FsError> {
+        let DirLoc::Chain(first) = dir else {
+            return Ok((self.root_start..self.root_start + self.root_sectors).collect());
+        };
+        let mut sectors = Vec::new();
+        let mut cluster = first;
+        // Bounded, so a corrupted table with a loop cannot hang us
+        for _ in 0..self.cluster_count {
+            let lba = self.cluster_lba(cluster)?;
+            sectors.extend(lba..lba + self.sectors_per_cluster);
+            match self.next(window, cluster)? {
+                Some(next) => cluster = next,
+                None => return Ok(sectors),
+            }
+        }
+        Err(FsError::InvalidData)
+    }
+
+    /// Every entry of `dir` except volume labels, `.` and `..`
+    fn read_dir(&self, window: &mut Window, dir: DirLoc) -> Result<Vec<Entry>, FsError> {
+        let mut entries = Vec::new();
+        let mut long = LongName::default();
+        let mut sector = [0u8; SECTOR_SIZE];
+        for lba in self.dir_sectors(window, dir)? {
+            self.read_sectors(lba, &mut sector)?;
+            for (i, raw) in sector.chunks_exact(ENTRY_SIZE).enumerate() {
+                match raw[0] {
+                    0 => return Ok(entries),
+                    DELETED => {
+                        long = LongName::default();
+                        continue;
+                    }
+                    _ => {}
+                }
+                let slot = (lba, i * ENTRY_SIZE);
+                if raw[11] & 0x3F == ATTR_LONG_NAME {
+                    long.push(raw, slot);
+                    continue;
+                }
+                let pending = core::mem::take(&mut long);
+                if raw[11] & ATTR_VOLUME_ID == 0 && raw[0] != b'.' {
+                    entries.push(Entry::parse(raw, slot, pending));
+                }
+            }
+        }
+        Ok(entries)
+    }
+
+    fn find(&self, window: &mut Window, dir: DirLoc, name: &str) -> Result<Option<Entry>, FsError> {
+        let entries = self.read_dir(window, dir)?;
+        Ok(entries.into_iter().find(|e| e.name.eq_ignore_ascii_case(name)))
+    }
+
+    fn dir_loc(&self, node: &Node) -> Result<DirLoc, FsError> {
+        match node {
+            Node::Root => Ok(self.root()),
+            Node::Entry(entry) if entry.is_dir() => Ok(DirLoc::Chain(entry.cluster())),
+            Node::Entry(_) => Err(FsError::NotADirectory),
+        }
+    }
+
+    fn lookup(&self, window: &mut Window, path: &[Component]) -> Result<Node, FsError> {
+        // Directories walked so far, so `..` can step back out of them
+        let mut stack = Vec::new();
+        let mut node = Node::Root;
+        for component in path {
+            match component {
+                Component::RootDir | Component::CurDir => {}
+                Component::ParentDir => node = stack.pop().unwrap_or(Node::Root),
+                Component::Normal(name) => {
+                    let dir = self.dir_loc(&node)?;
+                    let entry = self.find(window, dir, name)?.ok_or(FsError::NotFound)?;
+                    stack.push(core::mem::replace(&mut node, Node::Entry(entry)));
+                }
+            }
+        }
+        Ok(node)
+    }
+
+    fn lookup_dir(&self, window: &mut Window, path: &[Component]) -> Result<DirLoc, FsError> {
+        let node = self.lookup(window, path)?;
+        self.dir_loc(&node)
+    }
+
+    fn lookup_file(&self, window: &mut Window, path: &[Component]) -> Result<Entry, FsError> {
+        match self.lookup(window, path)? {
+            Node::Entry(entry) if !entry.is_dir() => Ok(entry),
+            _ => Err(FsError::IsADirectory),
+        }
+    }
+
+    /// Reads the sectors of `slots` once each, lets `patch` change the
+    /// entries and writes them back
+    fn patch_slots(&self, slots: &[Slot], mut patch: impl FnMut(usize, &mut [u8])) -> Result<(), FsError> {
+        let mut sector = [0u8; SECTOR_SIZE];
+        let mut current: Option<u64> = None;
+        for (i, &(lba, at)) in slots.iter().enumerate() {
+            if current != Some(lba) {
+                if let Some(done) = current {
+                    self.write_sectors(done, &sector)?;
+                }
+                self.read_sectors(lba, &mut sector)?;
+                current = Some(lba);
+            }
+            patch(i, &mut sector[at..at + ENTRY_SIZE]);
+        }
+        if let Some(done) = current {
+            self.write_sectors(done, &sector)?;
+        }
+        Ok(())
+    }
+
+    fn update_entry(&self, entry: &Entry) -> Result<(), FsError> {
+        self.patch_slots(&[entry.slot], |_, raw| raw.copy_from_slice(&entry.raw))
+    }
+
+    fn delete_entry(&self, entry: &Entry) -> Result<(), FsError> {
+        self.patch_slots(&entry.slots(), |_, raw| raw[0] = DELETED)
+    }
+
+    /// Finds `count` unused slots in a row in `dir`, growing it by as many
+    /// clusters as that takes
+    fn free_slots(&self, st: &mut FatState, dir: DirLoc, count: usize) -> Result<Vec<Slot>, FsError> {
+        let mut run = Vec::new();
+        let mut sector = [0u8; SECTOR_SIZE];
+        // Everything after the first never used slot is unused as well
+        let mut ended = false;
+        let sectors = self.dir_sectors(&mut st.window, dir)?;
+        for &lba in &sectors {
+            if !ended {
+                self.read_sectors(lba, &mut sector)?;
+            }
+            for at in (0..SECTOR_SIZE).step_by(ENTRY_SIZE) {
+                ended |= sector[at] == 0;
+                if ended || sector[at] == DELETED {
+                    run.push((lba, at));
+                    if run.len() == count {
+                        return Ok(run);
+                    }
+                } else {
+                    run.clear();
+                }
+            }
+        }
+
+        let DirLoc::Chain(first) = dir else {
+            return Err(FsError::NoSpace);
+        };
+        let mut last = first;
+        while let Some(next) = self.next(&mut st.window, last)? {
+            last = next;
+        }
+        loop {
+            last = self.alloc(st, Some(last), true)?;
+            let lba = self.cluster_lba(last)?;
+            for sector in lba..lba + self.sectors_per_cluster {
+                for at in (0..SECTOR_SIZE).step_by(ENTRY_SIZE) {
+                    run.push((sector, at));
+                    if run.len() == count {
+                        return Ok(run);
+                    }
+                }
+            }
+        }
+    }
+
+    /// Adds the entry `name` to `dir`. `raw` is the short entry with
+    /// everything but the name filled in.
+    fn add_entry(&self, st: &mut FatState, dir: DirLoc, name: &str, mut raw: [u8; ENTRY_SIZE]) -> Result<Entry, FsError> {
+        if !valid_name(name) {
+            return Err(FsError::InvalidPath);
+        }
+        let existing = self.read_dir(&mut st.window, dir)?;
+        let taken: Vec<[u8; 11]> = existing.iter().map(Entry::short).collect();
+        let (short, flags, mut entries) = match exact_short_name(name) {
+            Some((short, flags)) if !taken.contains(&short) => (short, flags, Vec::new()),
+            _ => {
+                let short = generate_short_name(name, &taken)?;
+                (short, 0, long_entries(name, &short))
+            }
+        };
+        raw[..11].copy_from_slice(&short);
+        raw[12] = flags;
+        entries.push(raw);
+
+        let mut slots = self.free_slots(st, dir, entries.len())?;
+        self.patch_slots(&slots, |i, slot| slot.copy_from_slice(&entries[i]))?;
+        let slot = slots.pop().expect("at least the short entry");
+        Ok(Entry {
+            name: String::from(name),
+            raw,
+            slot,
+            long: slots,
+        })
+    }
+
+    /// Value of `..` in a subdirectory of `dir`, the root is always 0
+    fn parent_cluster(&self, dir: DirLoc) -> u32 {
+        match dir {
+            DirLoc::Chain(cluster) if !(self.kind == FatType::Fat32 && cluster == self.root_cluster) => cluster,
+            _ => 0,
+        }
+    }
+
+    /// Points `..` of the directory starting at `cluster` to `parent`
+    fn set_parent(&self, cluster: u32, parent: DirLoc) -> Result<(), FsError> {
+        let slot = (self.cluster_lba(cluster)?, ENTRY_SIZE);
+        let parent = self.parent_cluster(parent);
+        self.patch_slots(&[slot], |_, raw| {
+            if &raw[..2] == b".." {
+                put16(raw, 20, (parent >> 16) as u16);
+                put16(raw, 26, parent as u16);
+            }
+        })
+    }
+
+    fn node_metadata(&self, window: &mut Window, node: &Node) -> Result<Metadata, FsError> {
+        let entry = match node {
+            Node::Root => {
+                let count = self.read_dir(window, self.root())?.len() as u64;
+                return Ok(Metadata {
+                    ino: ROOT_INO,
+                    kind: FileType::Directory,
+                    size: count,
+                    created: 0,
+                    modified: 0,
+                    accessed: 0,
+                    mode: DIR_MODE,
+                    nlink: 2,
+                });
+            }
+            Node::Entry(entry) => entry,
+        };
+        let size = match entry.is_dir() {
+            true => self.read_dir(window, DirLoc::Chain(entry.cluster()))?.len() as u64,
+            false => entry.size(),
+        };
+        Ok(entry.metadata(size))
+    }
+}
+
+impl FileSystem for FatFs {
+    fn name(&self) -> &'static str {
+        match self.kind {
+            FatType::Fat12 => "fat12",
+            FatType::Fat16 => "fat16",
+            FatType::Fat32 => "fat32",
+        }
+    }
+
+    fn read(&self, path: &str) -> Result<Vec<u8>, FsError> {
+        let parts = split_path(path);
+        let _guard = self.state.read()?;
+        let mut window = Window::new();
+        let entry = self.lookup_file(&mut window, &parts)?;
+
+        let mut data = Vec::new();
+        data.try_reserve_exact(entry.size() as usize)
+            .map_err(|_| FsError::NoSpace)?;
+        data.resize(entry.size() as usize, 0);
+        self.read_data(&mut window, entry.cluster(), 0, &mut data)?;
+        Ok(data)
+    }
+
+    fn write(&self, path: &str, data: &[u8]) -> Result<(), FsError> {
+        if data.len() as u64 > u32::MAX as u64 {
+            return Err(FsError::NoSpace);
+        }
+        let (dirs, name) = split_parent(path)?;
+        let mut __guard__ = self.state.write()?;
+        let st = &mut *__guard__;
+        let dir = self.lookup_dir(&mut st.window, &dirs)?;
+        let mut entry = match self.find(&mut st.window, dir, name)? {
+            Some(entry) if entry.is_dir() => return Err(FsError::IsADirectory),
+            Some(entry) => entry,
+            None => self.add_entry(st, dir, name, new_entry(ATTR_ARCHIVE, 0))?,
+        };
+
+        // Free the old contents first so a file can be rewritten on a
+        // nearly full volume. On failure the file is left empty.
+        self.free_chain(st, entry.cluster())?;
+        let mut first = 0;
+        let result = self.write_data(st, &mut first, 0, data);
+        if result.is_err() {
+            self.free_chain(st, first)?;
+            first = 0;
+        }
+        entry.set_cluster(first);
+        entry.set_size(if first == 0 { 0 } else { data.len() as u64 });
+        self.update_entry(&entry)?;
+        result
+    }
+
+    fn read_at(&self, path: &str, offset: u64, buf: &mut [u8]) -> Result<usize, FsError> {
+        let parts = split_path(path);
+        let _guard = self.state.read()?;
+        let mut window = Window::new();
+        let entry = self.lookup_file(&mut window, &parts)?;
+        if offset >= entry.size() {
+            return Ok(0);
+        }
+        let len = (entry.size() - offset).min(buf.len() as u64) as usize;
+        self.read_data(&mut window, entry.cluster(), offset, &mut buf[..len])?;
+        Ok(len)
+    }
+
+    fn write_at(&self, path: &str, offset: u64, data: &[u8]) -> Result<usize, FsError> {
+        let parts = split_path(path);
+        let mut __guard__ = self.state.write()?;
+        let st = &mut *__guard__;
+        let mut entry = self.lookup_file(&mut st.window, &parts)?;
+        let end = offset.checked_add(data.len() as u64).ok_or(FsError::InvalidArgument)?;
+        if end > u32::MAX as u64 {
+            return Err(FsError::NoSpace);
+        }
+
+        let size = entry.size();
+        let mut first = entry.cluster();
+        let mut result = Ok(());
+        if offset > size {
+            result = self.grow(st, &mut first, size, offset);
+        }
+        result = result.and_then(|_| self.write_data(st, &mut first, offset, data));
+        // Clusters taken before a failure stay with the file, past its end
+        entry.set_cluster(first);
+        if result.is_ok() {
+            entry.set_size(size.max(end));
+        }
+        self.update_entry(&entry)?;
+        result.map(|_| data.len())
+    }
+
+    fn truncate(&self, path: &str, len: u64) -> Result<(), FsError> {
+        if len > u32::MAX as u64 {
+            return Err(FsError::NoSpace);
+        }
+        let parts = split_path(path);
+        let mut __guard__ = self.state.write()?;
+        let st = &mut *__guard__;
+        let mut entry = self.lookup_file(&mut st.window, &parts)?;
+
+        let size = entry.size();
+        let mut first = entry.cluster();
+        let result = if len < size {
+            self.shrink(st, &mut first, len)
+        } else {
+            self.grow(st, &mut first, size, len)
+        };
+        entry.set_cluster(first);
+        if result.is_ok() {
+            entry.set_size(len);
+        }
+        self.update_entry(&entry)?;
+        result
+    }
+
+    fn touch(&self, path: &str) -> Result<(), FsError> {
+        let (dirs, name) = split_parent(path)?;
+        let mut __guard__ = self.state.write()?;
+        let st = &mut *__guard__;
+        let dir = self.lookup_dir(&mut st.window, &dirs)?;
+        // Without a clock there are no timestamps to bump
+        if self.find(&mut st.window, dir, name)?.is_none() {
+            self.add_entry(st, dir, name, new_entry(ATTR_ARCHIVE, 0))?;
+        }
+        Ok(())
+    }
+
+    fn create_dir(&self, path: &str) -> Result<(), FsError> {
+        let (dirs, name) = split_parent(path)?;
+        let mut __guard__ = self.state.write()?;
+        let st = &mut *__guard__;
+        let dir = self.lookup_dir(&mut st.window, &dirs)?;
+        if self.find(&mut st.window, dir, name)?.is_some() {
+            return Err(FsError::AlreadyExists);
+        }
+        if !valid_name(name) {
+            return Err(FsError::InvalidPath);
+        }
+
+        let cluster = self.alloc(st, None, true)?;
+        let mut dot = new_entry(ATTR_DIRECTORY, cluster);
+        dot[..11].copy_from_slice(b".          ");
+        let mut dotdot = new_entry(ATTR_DIRECTORY, self.parent_cluster(dir));
+        dotdot[..11].copy_from_slice(b"..         ");
+        let lba = self.cluster_lba(cluster)?;
+        let result = self
+            .patch_slots(&[(lba, 0), (lba, ENTRY_SIZE)], |i, raw| {
+                raw.copy_from_slice(if i == 0 { &dot } else { &dotdot })
+            })
+            .and_then(|_| self.add_entry(st, dir, name, new_entry(ATTR_DIRECTORY, cluster)));
+        if let Err(e) = result {
+            self.free_chain(st, cluster)?;
+            return Err(e);
+        }
+        Ok(())
+    }
+
+    fn list_dir(&self, path: &str) -> Result<Vec<String>, FsError> {
+        let parts = split_path(path);
+        let _guard = self.state.read()?;
+        let mut window = Window::new();
+        let dir = self.lookup_dir(&mut window, &parts)?;
+        let entries = self.read_dir(&mut window, dir)?;
+        Ok(entries.into_iter().map(|e| e.name).collect())
+    }
+
+    fn stat(&self, path: &str) -> Result<Metadata, FsError> {
+        let parts = split_path(path);
+        let _guard = self.state.read()?;
+        let mut window = Window::new();
+        let node = self.lookup(&mut window, &parts)?;
+        self.node_metadata(&mut window, &node)
+    }
+
+    fn statfs(&self) -> Result<FsStats, FsError> {
+        let _guard = self.state.read()?;
+        let mut window = Window::new();
+        let mut used = 0;
+        for cluster in 2..self.cluster_count + 2 {
+            if self.table_get(&mut window, cluster)? != 0 {
+                used += 1;
+            }
+        }
+        Ok(FsStats {
+            total_bytes: self.cluster_count as u64 * self.cluster_size(),
+            used_bytes: used * self.cluster_size(),
+            total_inodes: 0,
+            used_inodes: 0,
+        })
+    }
+
+    fn remove_file(&self, path: &str) -> Result<(), FsError> {
+        let parts = split_path(path);
+        let mut __guard__ = self.state.write()?;
+        let st = &mut *__guard__;
+        let entry = match self.lookup(&mut st.window, &parts)? {
+            Node::Entry(entry) if !entry.is_dir() => entry,
+            _ => return Err(FsError::IsADirectory),
+        };
+        self.delete_entry(&entry)?;
+        self.free_chain(st, entry.cluster())
+    }
+
+    fn remove_dir(&self, path: &str) -> Result<(), FsError> {
+        let parts = split_path(path);
+        let mut __guard__ = self.state.write()?;
+        let st = &mut *__guard__;
+        let entry = match self.lookup(&mut st.window, &parts)? {
+            Node::Root => return Err(FsError::InvalidPath),
+            Node::Entry(entry) if !entry.is_dir() => return Err(FsError::NotADirectory),
+            Node::Entry(entry) => entry,
+        };
+        if !self.read_dir(&mut st.window, DirLoc::Chain(entry.cluster()))?.is_empty() {
+            return Err(FsError::DirectoryNotEmpty);
+        }
+        self.delete_entry(&entry)?;
+        self.free_chain(st, entry.cluster())
+    }
+
+    fn rename(&self, from: &str, to: &str) -> Result<(), FsError> {
+        let (from_dirs, from_name) = split_parent(from)?;
+        let (to_dirs, to_name) = split_parent(to)?;
+        let mut __guard__ = self.state.write()?;
+        let st = &mut *__guard__;
+
+        let from_dir = self.lookup_dir(&mut st.window, &from_dirs)?;
+        let entry = self
+            .find(&mut st.window, from_dir, from_name)?
+            .ok_or(FsError::NotFound)?;
+        let to_dir = self.lookup_dir(&mut st.window, &to_dirs)?;
+        // Paths arrive canonical, so moving into itself shows in the names
+        let inside = to_dirs.len() > from_dirs.len()
+            && from_dirs
+                .iter()
+                .chain([Component::Normal(from_name)].iter())
+                .zip(&to_dirs)
+                .all(|(a, b)| a.as_str().eq_ignore_ascii_case(b.as_str()));
+        if entry.is_dir() && inside {
+            return Err(FsError::InvalidArgument);
+        }
+
+        if let Some(existing) = self.find(&mut st.window, to_dir, to_name)? {
+            if existing.slot == entry.slot {
+                // Only the case changes, or nothing at all
+                if existing.name == to_name {
+                    return Ok(());
+                }
+            } else {
+                match (entry.is_dir(), existing.is_dir()) {
+                    (false, true) => return Err(FsError::IsADirectory),
+                    (true, false) => return Err(FsError::NotADirectory),
+                    (true, true) if !self.read_dir(&mut st.window, DirLoc::Chain(existing.cluster()))?.is_empty() => {
+                        return Err(FsError::DirectoryNotEmpty);
+                    }
+                    _ => {
+                        self.delete_entry(&existing)?;
+                        self.free_chain(st, existing.cluster())?;
+                    }
+                }
+            }
+        }
+
+        // Add the new name before dropping the old one, so a failure never
+        // loses the file
+        self.add_entry(st, to_dir, to_name, entry.raw)?;
+        self.delete_entry(&entry)?;
+        if entry.is_dir() && from_dir != to_dir {
+            self.set_parent(entry.cluster(), to_dir)?;
+        }
+        Ok(())
+    }
+}
+
+#[test_case]
+fn test_fat() {
+    use crate::block::MemDisk;
+
+    // A 32 KiB FAT12 volume: boot sector, two one-sector tables, a root
+    // directory of 16 entries and 60 clusters of one sector
+    let disk = Arc::new(MemDisk::new("fat0", 64));
+    let mut boot = [0u8; SECTOR_SIZE];
+    put16(&mut boot, 11, SECTOR_SIZE as u16);
+    boot[13] = 1;
+    put16(&mut boot, 14, 1);
+    boot[16] = 2;
+    put16(&mut boot, 17, 16);
+    put16(&mut boot, 19, 64);
+    boot[21] = 0xF8;
+    put16(&mut boot, 22, 1);
+    boot[510..].copy_from_slice(&[0x55, 0xAA]);
+    let mut table = [0u8; SECTOR_SIZE];
+    table[..3].copy_from_slice(&[0xF8, 0xFF, 0xFF]);
+    block_on(disk.write_sectors(0, &boot)).unwrap();
+    block_on(disk.write_sectors(1, &table)).unwrap();
+    block_on(disk.write_sectors(2, &table)).unwrap();
+
+    // Clusters larger than the data area leave no room for any
+    boot[13] = 64;
+    block_on(disk.write_sectors(0, &boot)).unwrap();
+    assert_eq!(FatFs::new(disk.clone()).err(), Some(FsError::InvalidData));
+    boot[13] = 1;
+    block_on(disk.write_sectors(0, &boot)).unwrap();
+
+    let fs = FatFs::new(disk.clone()).unwrap();
+    assert_eq!(fs.name(), "fat12");
+    let free = fs.statfs().unwrap().free_bytes();
+    assert_eq!(free, 60 * 512);
+
+    fs.create_dir("/Docs").unwrap();
+    fs.write("/Docs/A long file name.txt", b"hello").unwrap();
+    fs.write("/readme.txt", &[7u8; 1300]).unwrap();
+    assert_eq!(fs.list_dir("/").unwrap(), ["Docs", "readme.txt"]);
+    assert_eq!(fs.read("/docs/a LONG file name.TXT").unwrap(), b"hello");
+    assert_eq!(fs.create_dir("/DOCS"), Err(FsError::AlreadyExists));
+
+    fs.write_at("/Docs/A long file name.txt", 600, b"!").unwrap();
+    let mut buf = [1u8; 8];
+    assert_eq!(fs.read_at("/Docs/A long file name.txt", 596, &mut buf).unwrap(), 5);
+    assert_eq!(&buf[..5], b"\0\0\0\0!");
+    assert_eq!(fs.write_at("/readme.txt", u64::MAX, b"!"), Err(FsError::InvalidArgument));
+    fs.truncate("/readme.txt", 10).unwrap();
+    assert_eq!(fs.stat("/readme.txt").unwrap().size, 10);
+
+    fs.rename("/Docs/A long file name.txt", "/moved.txt").unwrap();
+    assert_eq!(fs.remove_dir("/"), Err(FsError::InvalidPath));
+    fs.rename("/Docs", "/Empty").unwrap();
+    fs.remove_dir("/Empty").unwrap();
+
+    // Everything reached the disk: mount it again
+    let fs = FatFs::new(disk).unwrap();
+    assert_eq!(fs.list_dir("/").unwrap(), ["readme.txt", "moved.txt"]);
+    assert_eq!(fs.read("/moved.txt").unwrap().len(), 601);
+    assert_eq!(fs.read("/readme.txt").unwrap(), [7u8; 10]);
+    fs.remove_file("/moved.txt").unwrap();
+    fs.remove_file("/readme.txt").unwrap();
+    assert_eq!(fs.statfs().unwrap().free_bytes(), free);
+}
@@ -0,0 +1,199 @@
+extern crate alloc;
+use alloc::{string::{String, ToString}, sync::Arc, vec::Vec};
+use core::ops::BitOr;
+use super::{lock::FsLock, vfs, watch, FileSystem, FsError};
+
+/// Index into the open file table
+pub type Fd = usize;
+
+/// Maximum number of files open at the same time
+pub const MAX_OPEN_FILES: usize = 32;
+
+/// How a file is opened, combine with `|`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct OpenFlags(u8);
+
+impl OpenFlags {
+    pub const READ: OpenFlags = OpenFlags(1 << 0);
+    pub const WRITE: OpenFlags = OpenFlags(1 << 1);
+    /// Every write goes to the current end of the file
+    pub const APPEND: OpenFlags = OpenFlags(1 << 2);
+    /// Create the file if it does not exist
+    pub const CREATE: OpenFlags = OpenFlags(1 << 3);
+    /// Empty the file when opening it for writing
+    pub const TRUNCATE: OpenFlags = OpenFlags(1 << 4);
+
+    pub const fn contains(self, other: OpenFlags) -> bool {
+        self.0 & other.0 == other.0
+    }
+}
+
+impl BitOr for OpenFlags {
+    type Output = OpenFlags;
+
+    fn bitor(self, rhs: OpenFlags) -> OpenFlags {
+        OpenFlags(self.0 | rhs.0)
+    }
+}
+
+/// Where `seek` measures the new offset from
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SeekFrom {
+    Start(u64),
+    End(i64),
+    Current(i64),
+}
+
+#[derive(Clone)]
+struct OpenFile {
+    fs: Arc<dyn FileSystem>,
+    /// Path relative to `fs`
+    path: String,
+    /// Path the file was opened with, for change notifications
+    full_path: String,
+    offset: u64,
+    flags: OpenFlags,
+}
+
+/// Open file table, `None` marks a free descriptor.
+/// Unlike `fs::read`/`fs::write` descriptors stream through a caller
+/// supplied buffer instead of copying whole files on the heap.
+static OPEN_FILES: FsLock<Vec<Option<OpenFile>>> = FsLock::new(Vec::new());
+
+/// Copies out the entry of `fd`, so no table lock is held while the
+/// filesystem works
+fn lookup(fd: Fd, needed: OpenFlags) -> Result<OpenFile, FsError> {
+    let files = OPEN_FILES.read()?;
+    let file = files
+        .get(fd)
+        .and_then(|f| f.as_ref())
+        .ok_or(FsError::BadDescriptor)?;
+    if !file.flags.contains(needed) {
+        return Err(FsError::BadDescriptor);
+    }
+    Ok(file.clone())
+}
+
+fn set_offset(fd: Fd, offset: u64) -> Result<(), FsError> {
+    if let Some(Some(file)) = OPEN_FILES.write()?.get_mut(fd) {
+        file.offset = offset;
+    }
+    Ok(())
+}
+
+/// Open the file at `path` and return its descriptor
+pub fn open(path: &str, flags: OpenFlags) -> Result<Fd, FsError> {
+    let (fs, rel) = vfs::resolve(path)?;
+
+    match fs.stat(&rel) {
+        Ok(meta) if meta.is_dir() => return Err(FsError::IsADirectory),
+        Ok(_) => {}
+        Err(FsError::NotFound) if flags.contains(OpenFlags::CREATE) => {
+            fs.touch(&rel)?;
+            watch::created(path);
+        }
+        Err(e) => return Err(e),
+    }
+    if flags.contains(OpenFlags::TRUNCATE) && flags.contains(OpenFlags::WRITE) {
+        fs.truncate(&rel, 0)?;
+        watch::modified(path);
+    }
+
+    let file = OpenFile {
+        fs,
+        path: rel,
+        full_path: path.to_string(),
+        offset: 0,
+        flags,
+    };
+
+    let mut files = OPEN_FILES.write()?;
+    match files.iter().position(|f| f.is_none()) {
+        Some(fd) => {
+            files[fd] = Some(file);
+            Ok(fd)
+        }
+        None if files.len() < MAX_OPEN_FILES => {
+            files.push(Some(file));
+            Ok(files.len() - 1)
+        }
+        None => Err(FsError::TooManyOpenFiles),
+    }
+}
+
+/// Read from the current offset into `buf`, returns 0 at the end of the file
+pub fn read(fd: Fd, buf: &mut [u8]) -> Result<usize, FsError> {
+    let file = lookup(fd, OpenFlags::READ)?;
+    let count = file.fs.read_at(&file.path, file.offset, buf)?;
+    set_offset(fd, file.offset + count as u64)?;
+    Ok(count)
+}
+
+/// Write `buf` at the current offset (or the end in append mode)
+pub fn write(fd: Fd, buf: &[u8]) -> Result<usize, FsError> {
+    let file = lookup(fd, OpenFlags::WRITE)?;
+    let offset = if file.flags.contains(OpenFlags::APPEND) {
+        file.fs.stat(&file.path)?.size
+    } else {
+        file.offset
+    };
+    let count = file.fs.write_at(&file.path, offset, buf)?;
+    set_offset(fd, offset + count as u64)?;
+    watch::modified(&file.full_path);
+    Ok(count)
+}
+
+/// Move the offset of `fd`, returns the new offset from the start
+pub fn seek(fd: Fd, pos: SeekFrom) -> Result<u64, FsError> {
+    let file = lookup(fd, OpenFlags(0))?;
+    let (base, delta) = match pos {
+        SeekFrom::Start(n) => (n, 0),
+        SeekFrom::Current(n) => (file.offset, n),
+        SeekFrom::End(n) => (file.fs.stat(&file.path)?.size, n),
+    };
+    let new = base.checked_add_signed(delta).ok_or(FsError::InvalidArgument)?;
+    set_offset(fd, new)?;
+    Ok(new)
+}
+
+/// Release `fd` so it can be handed out again
+pub fn close(fd: Fd) -> Result<(), FsError> {
+    let mut files = OPEN_FILES.write()?;
+    match files.get_mut(fd) {
+        Some(slot @ Some(_)) => {
+            *slot = None;
+            Ok(())
+        }
+        _ => Err(FsError::BadDescriptor),
+    }
+}
+
+#[test_case]
+fn test_descriptors() {
+    super::mount_test_root();
+
+    let fd = open("/fd_test", OpenFlags::WRITE | OpenFlags::CREATE | OpenFlags::TRUNCATE).unwrap();
+    assert_eq!(write(fd, b"0123456789").unwrap(), 10);
+    assert_eq!(read(fd, &mut [0u8; 4]), Err(FsError::BadDescriptor));
+    close(fd).unwrap();
+    assert_eq!(close(fd), Err(FsError::BadDescriptor));
+
+    let fd = open("/fd_test", OpenFlags::READ | OpenFlags::WRITE | OpenFlags::APPEND).unwrap();
+    write(fd, b"ab").unwrap();
+    assert_eq!(seek(fd, SeekFrom::End(-4)).unwrap(), 8);
+    let mut buf = [0u8; 8];
+    assert_eq!(read(fd, &mut buf).unwrap(), 4);
+    assert_eq!(&buf[..4], b"89ab");
+    assert_eq!(read(fd, &mut buf).unwrap(), 0);
+    close(fd).unwrap();
+
+    // Writing past the largest offset fails instead of wrapping around
+    let fd = open("/fd_test", OpenFlags::WRITE).unwrap();
+    assert_eq!(seek(fd, SeekFrom::Start(u64::MAX)).unwrap(), u64::MAX);
+    assert_eq!(seek(fd, SeekFrom::Current(1)), Err(FsError::InvalidArgument));
+    assert_eq!(seek(fd, SeekFrom::Current(-1)).unwrap(), u64::MAX - 1);
+    assert_eq!(write(fd, b"xyz"), Err(FsError::InvalidArgument));
+    close(fd).unwrap();
+
+    assert_eq!(open("/missing", OpenFlags::READ), Err(FsError::NotFound));
+}
@@ -0,0 +1,134 @@
+extern crate alloc;
+use alloc::{string::String, vec::Vec};
+use core::{future::Future, pin::Pin, task::{Context, Poll}};
+use super::{file, lock::without_blocking, Fd, FsError, Metadata, OpenFlags, SeekFrom};
+
+/// Future running a filesystem operation without waiting on its locks
+///
+/// Every poll tries the operation once. While a lock is taken it wakes
+/// itself and returns `Pending`, so the executor runs other tasks (which
+/// may be the lock holder) before trying again.
+///
+/// Only lock waits yield. FAT and ext2 mounts wait for their disk inside
+/// the poll, with `block::block_on`, so an operation on them stalls every
+/// task until the device answers. RamFs and the pseudo filesystems never
+/// wait for a device.
+pub struct FsFuture<F> {
+    op: F,
+}
+
+impl<F> FsFuture<F> {
+    fn new(op: F) -> Self {
+        FsFuture { op }
+    }
+}
+
+impl<F, R> Future for FsFuture<F>
+where
+    F: FnMut() -> Result<R, FsError> + Unpin,
+{
+    type Output = Result<R, FsError>;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<Self::Output> {
+        match without_blocking(&mut self.op) {
+            Err(FsError::WouldBlock) => {
+                cx.waker().wake_by_ref();
+                Poll::Pending
+            }
+            result => Poll::Ready(result),
+        }
+    }
+}
+
+/// Async version of `fs::read`
+pub fn read_async(path: &str) -> FsFuture<impl FnMut() -> Result<Vec<u8>, FsError> + '_> {
+    FsFuture::new(move || super::read(path))
+}
+
+/// Async version of `fs::write`
+pub fn write_async<'a>(path: &'a str, data: &'a [u8]) -> FsFuture<impl FnMut() -> Result<(), FsError> + 'a> {
+    FsFuture::new(move || super::write(path, data))
+}
+
+/// Async version of `fs::touch`
+pub fn touch_async(path: &str) -> FsFuture<impl FnMut() -> Result<(), FsError> + '_> {
+    FsFuture::new(move || super::touch(path))
+}
+
+/// Async version of `fs::stat`
+pub fn stat_async(path: &str) -> FsFuture<impl FnMut() -> Result<Metadata, FsError> + '_> {
+    FsFuture::new(move || super::stat(path))
+}
+
+/// Async version of `fs::create_dir`
+pub fn create_dir_async(path: &str) -> FsFuture<impl FnMut() -> Result<(), FsError> + '_> {
+    FsFuture::new(move || super::create_dir(path))
+}
+
+/// Async version of `fs::list_dir`
+pub fn list_dir_async(path: &str) -> FsFuture<impl FnMut() -> Result<Vec<String>, FsError> + '_> {
+    FsFuture::new(move || super::list_dir(path))
+}
+
+/// Async version of `fs::remove_file`
+pub fn remove_file_async(path: &str) -> FsFuture<impl FnMut() -> Result<(), FsError> + '_> {
+    FsFuture::new(move || super::remove_file(path))
+}
+
+/// Async version of `fs::remove_dir`
+pub fn remove_dir_async(path: &str) -> FsFuture<impl FnMut() -> Result<(), FsError> + '_> {
+    FsFuture::new(move || super::remove_dir(path))
+}
+
+/// Async version of `fs::remove_dir_all`
+pub fn remove_dir_all_async(path: &str) -> FsFuture<impl FnMut() -> Result<(), FsError> + '_> {
+    FsFuture::new(move || super::remove_dir_all(path))
+}
+
+/// Async version of `fs::rename`
+pub fn rename_async<'a>(from: &'a str, to: &'a str) -> FsFuture<impl FnMut() -> Result<(), FsError> + 'a> {
+    FsFuture::new(move || super::rename(from, to))
+}
+
+/// Async version of `fs::open`
+pub fn open_async(path: &str, flags: OpenFlags) -> FsFuture<impl FnMut() -> Result<Fd, FsError> + '_> {
+    FsFuture::new(move || file::open(path, flags))
+}
+
+/// Async version of `fs::file::read`
+pub fn read_fd_async(fd: Fd, buf: &mut [u8]) -> FsFuture<impl FnMut() -> Result<usize, FsError> + '_> {
+    FsFuture::new(move || file::read(fd, &mut *buf))
+}
+
+/// Async version of `fs::file::write`
+pub fn write_fd_async(fd: Fd, buf: &[u8]) -> FsFuture<impl FnMut() -> Result<usize, FsError> + '_> {
+    FsFuture::new(move || file::write(fd, buf))
+}
+
+/// Async version of `fs::seek`
+pub fn seek_async(fd: Fd, pos: SeekFrom) -> FsFuture<impl FnMut() -> Result<u64, FsError>> {
+    FsFuture::new(move || file::seek(fd, pos))
+}
+
+/// Async version of `fs::close`
+pub fn close_async(fd: Fd) -> FsFuture<impl FnMut() -> Result<(), FsError>> {
+    FsFuture::new(move || file::close(fd))
+}
+
+#[test_case]
+fn test_fs_future() {
+    use futures_util::task::noop_waker_ref;
+    use super::lock::FsLock;
+
+    let lock = FsLock::new(0);
+    let mut cx = Context::from_waker(noop_waker_ref());
+    let mut future = FsFuture::new(|| lock.write().map(|mut value| {
+        *value += 1;
+        *value
+    }));
+
+    let reader = lock.read().unwrap();
+    assert_eq!(Pin::new(&mut future).poll(&mut cx), Poll::Pending);
+    drop(reader);
+    assert_eq!(Pin::new(&mut future).poll(&mut cx), Poll::Ready(Ok(1)));
+}
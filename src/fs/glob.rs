@@ -0,0 +1,187 @@
+extern crate alloc;
+use alloc::{collections::BTreeSet, string::String, vec::Vec};
+use super::{path::{Component, Path}, FsError};
+
+/// Length of the class starting at `pattern[0] == '['` and whether `c` is
+/// in it, `None` if the class is never closed
+fn match_class(pattern: &[char], c: char) -> Option<(bool, usize)> {
+    let mut i = 1;
+    let negate = matches!(pattern.get(i), Some('!' | '^'));
+    if negate {
+        i += 1;
+    }
+    let start = i;
+    let mut hit = false;
+    while i < pattern.len() {
+        // A `]` right after the opening bracket is a plain character
+        if pattern[i] == ']' && i > start {
+            return Some((hit != negate, i + 1));
+        }
+        let low = pattern[i];
+        match (pattern.get(i + 1), pattern.get(i + 2)) {
+            (Some('-'), Some(&high)) if high != ']' => {
+                hit |= (low..=high).contains(&c);
+                i += 3;
+            }
+            _ => {
+                hit |= low == c;
+                i += 1;
+            }
+        }
+    }
+    None
+}
+
+/// Pattern length consumed if the token at the start of `pattern` matches
+/// the single character `c`
+fn match_one(pattern: &[char], c: char) -> Option<usize> {
+    match pattern[0] {
+        '?' => Some(1),
+        '[' => match match_class(pattern, c) {
+            Some((hit, len)) => hit.then_some(len),
+            None => (c == '[').then_some(1),
+        },
+        '\\' if pattern.len() > 1 => (pattern[1] == c).then_some(2),
+        p => (p == c).then_some(1),
+    }
+}
+
+/// Matches one path component against a pattern with `*`, `?`, `[abc]`,
+/// `[a-z]`, `[!abc]` and `\` escapes
+pub fn matches(pattern: &str, name: &str) -> bool {
+    let pattern: Vec<char> = pattern.chars().collect();
+    let name: Vec<char> = name.chars().collect();
+    let (mut p, mut n) = (0, 0);
+    // Position after the last `*` and the name position it was tried at
+    let mut star: Option<(usize, usize)> = None;
+
+    while n < name.len() {
+        if p < pattern.len() && pattern[p] == '*' {
+            p += 1;
+            star = Some((p, n));
+        } else if p < pattern.len()
+            && let Some(len) = match_one(&pattern[p..], name[n])
+        {
+            p += len;
+            n += 1;
+        } else if let Some((after_star, tried)) = star {
+            // Let the last `*` swallow one more character
+            p = after_star;
+            n = tried + 1;
+            star = Some((after_star, n));
+        } else {
+            return false;
+        }
+    }
+    pattern[p..].iter().all(|&c| c == '*')
+}
+
+fn has_wildcards(part: &str) -> bool {
+    part.contains(['*', '?', '[', '\\'])
+}
+
+/// Names starting with a dot only match patterns that start with one too
+fn visible(pattern: &str, name: &str) -> bool {
+    !name.starts_with('.') || pattern.starts_with('.')
+}
+
+/// Everything `**` may stand for below `dir`: the directories, `dir`
+/// included, and also the files when `**` ends the pattern
+fn subdirs(dir: &str, out: &mut BTreeSet<String>, with_files: bool) -> Result<(), FsError> {
+    let mut walk = super::walk(dir);
+    while let Some(entry) = walk.next() {
+        let entry = entry?;
+        if entry.depth > 0 && entry.file_name().starts_with('.') {
+            // Nothing below a hidden directory is visible either
+            walk.skip_current_dir();
+            continue;
+        }
+        if with_files || entry.metadata.is_dir() {
+            out.insert(entry.path);
+        }
+    }
+    Ok(())
+}
+
+pub(crate) fn glob(pattern: &str) -> Result<Vec<String>, FsError> {
+    if !Path::new(pattern).is_absolute() {
+        return Err(FsError::InvalidPath);
+    }
+    let parts: Vec<&str> = Path::new(pattern)
+        .components()
+        .filter_map(|c| match c {
+            Component::RootDir => None,
+            c => Some(c.as_str()),
+        })
+        .collect();
+
+    let mut current: BTreeSet<String> = BTreeSet::new();
+    current.insert(String::from("/"));
+    for (i, &part) in parts.iter().enumerate() {
+        let last = i == parts.len() - 1;
+        let mut next = BTreeSet::new();
+        for dir in &current {
+            if part == "**" {
+                subdirs(dir, &mut next, last)?;
+            } else if !has_wildcards(part) {
+                let path = Path::new(dir).join(part).canonicalize(Path::new("/")).into_string();
+                if super::symlink_metadata(&path).is_ok() {
+                    next.insert(path);
+                }
+            } else {
+                // Anything that is not a directory simply has no matches
+                let Ok(names) = super::list_dir(dir) else {
+                    continue;
+                };
+                for name in names.iter().filter(|name| visible(part, name) && matches(part, name)) {
+                    next.insert(Path::new(dir).join(name).into_string());
+                }
+            }
+        }
+        current = next;
+    }
+    Ok(current.into_iter().collect())
+}
+
+#[test_case]
+fn test_matches() {
+    assert!(matches("*.rs", "main.rs"));
+    assert!(!matches("*.rs", "main.rsx"));
+    assert!(matches("a*b*c", "aXbYbZc"));
+    assert!(matches("?at", "cat"));
+    assert!(!matches("?at", "at"));
+    assert!(matches("[bc]at", "bat"));
+    assert!(matches("[a-c]at", "cat"));
+    assert!(!matches("[!a-c]at", "cat"));
+    assert!(matches("[]]", "]"));
+    assert!(matches("\\*", "*"));
+    assert!(!matches("\\*", "x"));
+    assert!(matches("[", "["));
+    assert!(matches("*", ""));
+}
+
+#[test_case]
+fn test_glob() {
+    super::mount_test_root();
+    super::create_dir("/glob").unwrap();
+    super::create_dir("/glob/src").unwrap();
+    super::create_dir("/glob/src/fs").unwrap();
+    super::write("/glob/src/main.rs", b"").unwrap();
+    super::write("/glob/src/fs/mod.rs", b"").unwrap();
+    super::write("/glob/src/.hidden.rs", b"").unwrap();
+    super::write("/glob/readme.md", b"").unwrap();
+    super::create_dir("/glob/.git").unwrap();
+    super::create_dir("/glob/.git/objects").unwrap();
+    super::write("/glob/.git/objects/pack.rs", b"").unwrap();
+
+    assert_eq!(glob("/glob/*").unwrap(), ["/glob/readme.md", "/glob/src"]);
+    assert_eq!(glob("/glob/src/*.rs").unwrap(), ["/glob/src/main.rs"]);
+    assert_eq!(glob("/glob/**/*.rs").unwrap(), ["/glob/src/fs/mod.rs", "/glob/src/main.rs"]);
+    assert_eq!(glob("/glob/src/.*").unwrap(), ["/glob/src/.hidden.rs"]);
+    assert_eq!(glob("/glob/s?c/f[a-z]").unwrap(), ["/glob/src/fs"]);
+    assert_eq!(glob("/glob/**").unwrap().len(), 6);
+    assert_eq!(glob("/glob/.git/**/*.rs").unwrap(), ["/glob/.git/objects/pack.rs"]);
+    assert_eq!(glob("/glob/readme.md").unwrap(), ["/glob/readme.md"]);
+    assert!(glob("/glob/*.txt").unwrap().is_empty());
+    assert_eq!(glob("glob/*"), Err(FsError::InvalidPath));
+}
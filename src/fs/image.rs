@@ -0,0 +1,336 @@
+extern crate alloc;
+use alloc::{collections::BTreeMap, format, string::String, vec::Vec};
+use core::{sync::atomic::{AtomicBool, Ordering}, task::Poll};
+use futures_util::{future::poll_fn, task::AtomicWaker};
+use spin::Mutex;
+use crate::{interrupts, println, serial};
+use super::{tar::{padded, EntryKind, Header, Unpacker, BLOCK_SIZE}, vfs, FileSystem, FileType, FsError};
+
+/// Lines framing an image on the serial line, everything outside them is
+/// ignored when loading
+const BEGIN_LINE: &str = "-----BEGIN RAMFS IMAGE-----";
+const END_LINE: &str = "-----END RAMFS IMAGE-----";
+
+/// Archive bytes per base64 line, 76 characters like `base64` writes them
+const LINE_BYTES: usize = 57;
+
+/// Longest line `load` keeps, the rest of a longer one is dropped
+const MAX_LINE: usize = 128;
+
+const ALPHABET: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+
+fn encode(data: &[u8], out: &mut String) {
+    for chunk in data.chunks(3) {
+        let b = [chunk[0], *chunk.get(1).unwrap_or(&0), *chunk.get(2).unwrap_or(&0)];
+        let n = (b[0] as u32) << 16 | (b[1] as u32) << 8 | b[2] as u32;
+        for i in 0..4 {
+            if i <= chunk.len() {
+                out.push(ALPHABET[(n >> (18 - 6 * i)) as usize & 63] as char);
+            } else {
+                out.push('=');
+            }
+        }
+    }
+}
+
+/// Base64 decoder that keeps partial groups between lines
+#[derive(Default)]
+struct Decoder {
+    bits: u32,
+    count: u8,
+    /// Set by `=`, only padding and whitespace may follow
+    ended: bool,
+}
+
+impl Decoder {
+    fn decode(&mut self, text: &[u8], out: &mut Vec<u8>) -> Result<(), FsError> {
+        for &c in text {
+            let value = match c {
+                b'A'..=b'Z' => c - b'A',
+                b'a'..=b'z' => c - b'a' + 26,
+                b'0'..=b'9' => c - b'0' + 52,
+                b'+' => 62,
+                b'/' => 63,
+                b'=' => {
+                    self.ended = true;
+                    continue;
+                }
+                b' ' | b'\t' | b'\r' => continue,
+                _ => return Err(FsError::InvalidData),
+            };
+            if self.ended {
+                return Err(FsError::InvalidData);
+            }
+            self.bits = self.bits << 6 | value as u32;
+            self.count += 1;
+            if self.count == 4 {
+                out.extend_from_slice(&self.bits.to_be_bytes()[1..]);
+                self.count = 0;
+                self.bits = 0;
+            }
+        }
+        if self.ended {
+            // A trailing group of 2 or 3 characters holds 1 or 2 bytes
+            match self.count {
+                0 => {}
+                2 => out.push((self.bits >> 4) as u8),
+                3 => out.extend_from_slice(&((self.bits >> 2) as u16).to_be_bytes()),
+                _ => return Err(FsError::InvalidData),
+            }
+            self.count = 0;
+            self.bits = 0;
+        }
+        Ok(())
+    }
+}
+
+/// Buffers archive bytes and sends them as base64 lines
+struct SerialWriter {
+    pending: Vec<u8>,
+}
+
+impl SerialWriter {
+    fn write(&mut self, mut data: &[u8]) {
+        while !data.is_empty() {
+            let n = (LINE_BYTES - self.pending.len()).min(data.len());
+            self.pending.extend_from_slice(&data[..n]);
+            data = &data[n..];
+            if self.pending.len() == LINE_BYTES {
+                self.flush();
+            }
+        }
+    }
+
+    fn flush(&mut self) {
+        if self.pending.is_empty() {
+            return;
+        }
+        let mut line = String::new();
+        encode(&self.pending, &mut line);
+        line.push('\n');
+        serial::send_bytes(line.as_bytes());
+        self.pending.clear();
+    }
+}
+
+/// Adds `path` and everything below it to the archive, returns the number
+/// of entries written
+fn save_entry(
+    fs: &dyn FileSystem,
+    path: &str,
+    name: &str,
+    out: &mut SerialWriter,
+    links: &mut BTreeMap<u64, String>,
+) -> Result<usize, FsError> {
+    let meta = fs.symlink_metadata(path)?;
+    let mut header = Header {
+        path: String::from(name),
+        kind: EntryKind::File,
+        size: 0,
+        link: String::new(),
+    };
+    let mut data = Vec::new();
+
+    match meta.kind {
+        FileType::Directory => header.kind = EntryKind::Directory,
+        FileType::Symlink => {
+            header.kind = EntryKind::Symlink;
+            header.link = fs.read_link(path)?;
+        }
+        FileType::File => match links.get(&meta.ino) {
+            Some(first) => {
+                header.kind = EntryKind::HardLink;
+                header.link = first.clone();
+            }
+            None => {
+                if meta.nlink > 1 {
+                    links.insert(meta.ino, String::from(name));
+                }
+                data = fs.read(path)?;
+                header.size = data.len() as u64;
+            }
+        },
+        // Devices and the like only exist while the kernel runs
+        _ => return Ok(0),
+    }
+
+    out.write(&header.to_block(meta.mode)?);
+    out.write(&data);
+    out.write(&[0u8; BLOCK_SIZE][..padded(header.size) - data.len()]);
+    let mut count = 1;
+
+    if meta.kind == FileType::Directory {
+        for child in fs.list_dir(path)? {
+            let child_path = format!("{}/{}", path.trim_end_matches('/'), child);
+            let child_name = format!("{}/{}", name.trim_end_matches('/'), child);
+            count += save_entry(fs, &child_path, &child_name, out, links)?;
+        }
+    }
+    Ok(count)
+}
+
+/// Sends the tree below the directory `path` over COM1 as a base64 encoded
+/// ustar archive between `BEGIN_LINE` and `END_LINE`
+///
+/// Only the filesystem holding `path` is saved, other filesystems mounted
+/// below it are skipped. Returns the number of entries written.
+pub fn save(path: &str) -> Result<usize, FsError> {
+    let (fs, rel) = vfs::resolve(path)?;
+    if !fs.stat(&rel)?.is_dir() {
+        return Err(FsError::NotADirectory);
+    }
+
+    let mut out = SerialWriter { pending: Vec::new() };
+    serial::send_bytes(format!("\n{}\n", BEGIN_LINE).as_bytes());
+    let result = save_entry(&*fs, &rel, "/", &mut out, &mut BTreeMap::new());
+    if result.is_ok() {
+        out.write(&[0u8; 2 * BLOCK_SIZE]);
+    }
+    out.flush();
+    // The end line goes out even on errors, the truncated archive then
+    // fails to load instead of swallowing the rest of the log
+    serial::send_bytes(format!("{}\n", END_LINE).as_bytes());
+    result
+}
+
+/// Reads one line from COM1 into `line`, without the line break
+///
+/// Fails with `TimedOut` if no byte arrives for `timeout` timer ticks.
+async fn read_line(line: &mut Vec<u8>, timeout: u64) -> Result<(), FsError> {
+    line.clear();
+    loop {
+        let byte = serial::receive(interrupts::ticks() + timeout).await;
+        match byte.ok_or(FsError::TimedOut)? {
+            b'\n' => return Ok(()),
+            b'\r' => {}
+            byte => {
+                if line.len() < MAX_LINE {
+                    line.push(byte);
+                }
+            }
+        }
+    }
+}
+
+/// Set while a load reads the serial line, two would split its lines
+static LOADING: AtomicBool = AtomicBool::new(false);
+
+/// Holds `LOADING` until dropped
+struct LoadGuard;
+
+impl LoadGuard {
+    fn take() -> Result<Self, FsError> {
+        match LOADING.swap(true, Ordering::Acquire) {
+            true => Err(FsError::Busy),
+            false => Ok(LoadGuard),
+        }
+    }
+}
+
+impl Drop for LoadGuard {
+    fn drop(&mut self) {
+        LOADING.store(false, Ordering::Release);
+    }
+}
+
+/// Waits for an image sent over COM1 and extracts it into the directory
+/// `path`, merging with what is already there
+///
+/// Gives up with `TimedOut` once the line stays quiet for `timeout` timer
+/// ticks, and with `Busy` if another load is running. Returns the number
+/// of entries extracted.
+pub async fn load(path: &str, timeout: u64) -> Result<usize, FsError> {
+    let _guard = LoadGuard::take()?;
+    let (fs, rel) = vfs::resolve(path)?;
+    if !fs.stat(&rel)?.is_dir() {
+        return Err(FsError::NotADirectory);
+    }
+
+    let mut line = Vec::new();
+    loop {
+        read_line(&mut line, timeout).await?;
+        if line.trim_ascii() == BEGIN_LINE.as_bytes() {
+            break;
+        }
+    }
+
+    let mut unpacker = Unpacker::new(&rel);
+    let mut decoder = Decoder::default();
+    let mut bytes = Vec::new();
+    loop {
+        read_line(&mut line, timeout).await?;
+        if line.trim_ascii() == END_LINE.as_bytes() {
+            break;
+        }
+        if line.len() == MAX_LINE {
+            return Err(FsError::InvalidData);
+        }
+        bytes.clear();
+        decoder.decode(&line, &mut bytes)?;
+        unpacker.feed(&*fs, &bytes)?;
+    }
+
+    if !unpacker.is_done() {
+        return Err(FsError::InvalidData);
+    }
+    Ok(unpacker.entries())
+}
+
+/// The directory and timeout of a load `load_task` has yet to start
+static LOAD_REQUEST: Mutex<Option<(String, u64)>> = Mutex::new(None);
+static LOAD_WAKER: AtomicWaker = AtomicWaker::new();
+
+/// Has `load_task` wait for an image and extract it into the directory
+/// `path`, like `load` but without waiting for it
+pub fn request_load(path: &str, timeout: u64) -> Result<(), FsError> {
+    let (fs, rel) = vfs::resolve(path)?;
+    if !fs.stat(&rel)?.is_dir() {
+        return Err(FsError::NotADirectory);
+    }
+    let mut request = LOAD_REQUEST.lock();
+    if request.is_some() || LOADING.load(Ordering::Acquire) {
+        return Err(FsError::Busy);
+    }
+    *request = Some((path.into(), timeout));
+    LOAD_WAKER.wake();
+    Ok(())
+}
+
+/// Runs the loads asked for with `request_load` and reports how they went,
+/// spawned once on the executor so the shell keeps working meanwhile
+pub async fn load_task() {
+    loop {
+        let (path, timeout) = poll_fn(|cx| {
+            LOAD_WAKER.register(cx.waker());
+            match LOAD_REQUEST.lock().take() {
+                Some(request) => Poll::Ready(request),
+                None => Poll::Pending,
+            }
+        })
+        .await;
+        match load(&path, timeout).await {
+            Ok(count) => println!("\nloaded {} entries into {}", count, path),
+            Err(e) => println!("\nfs-load: {}: {}", path, e),
+        }
+    }
+}
+
+#[test_case]
+fn test_base64() {
+    for data in [&b""[..], b"f", b"fo", b"foo", b"foob", b"fooba", b"foobar"] {
+        let mut text = String::new();
+        encode(data, &mut text);
+        let mut decoded = Vec::new();
+        let mut decoder = Decoder::default();
+        // Feed it in two pieces to check groups spanning lines
+        let (first, second) = text.as_bytes().split_at(text.len() / 2);
+        decoder.decode(first, &mut decoded).unwrap();
+        decoder.decode(second, &mut decoded).unwrap();
+        assert_eq!(decoded, data);
+    }
+
+    let mut text = String::new();
+    encode(b"foobar", &mut text);
+    assert_eq!(text, "Zm9vYmFy");
+    assert_eq!(Decoder::default().decode(b"Zm9v!", &mut Vec::new()), Err(FsError::InvalidData));
+}
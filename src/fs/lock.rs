@@ -0,0 +1,69 @@
+use core::sync::atomic::{AtomicBool, Ordering};
+use spin::{RwLock, RwLockReadGuard, RwLockWriteGuard};
+use x86_64::instructions::interrupts;
+use super::FsError;
+
+/// Set while `without_blocking` runs
+static NON_BLOCKING: AtomicBool = AtomicBool::new(false);
+
+/// Spinning is only safe with interrupts enabled: inside a handler (or a
+/// `without_interrupts` block) the lock holder may be the very code that
+/// got interrupted, and it will never run again to release the lock.
+fn may_spin() -> bool {
+    interrupts::are_enabled() && !NON_BLOCKING.load(Ordering::Relaxed)
+}
+
+/// Runs `f` with every filesystem lock in try mode: instead of waiting for
+/// a busy lock, operations fail with `FsError::WouldBlock`.
+///
+/// Interrupt handlers get this behaviour automatically.
+pub fn without_blocking<R>(f: impl FnOnce() -> R) -> R {
+    let previous = NON_BLOCKING.swap(true, Ordering::Relaxed);
+    let result = f();
+    NON_BLOCKING.store(previous, Ordering::Relaxed);
+    result
+}
+
+/// Reader/writer lock used by the filesystem code, many readers or one
+/// writer at a time
+pub(crate) struct FsLock<T> {
+    inner: RwLock<T>,
+}
+
+impl<T> FsLock<T> {
+    pub(crate) const fn new(value: T) -> Self {
+        FsLock {
+            inner: RwLock::new(value),
+        }
+    }
+
+    pub(crate) fn read(&self) -> Result<RwLockReadGuard<'_, T>, FsError> {
+        if may_spin() {
+            Ok(self.inner.read())
+        } else {
+            self.inner.try_read().ok_or(FsError::WouldBlock)
+        }
+    }
+
+    pub(crate) fn write(&self) -> Result<RwLockWriteGuard<'_, T>, FsError> {
+        if may_spin() {
+            Ok(self.inner.write())
+        } else {
+            self.inner.try_write().ok_or(FsError::WouldBlock)
+        }
+    }
+}
+
+#[test_case]
+fn test_fs_lock() {
+    let lock = FsLock::new(0);
+    {
+        let first = lock.read().unwrap();
+        let second = lock.read().unwrap();
+        assert_eq!(*first + *second, 0);
+        assert!(without_blocking(|| lock.read()).is_ok());
+        assert!(matches!(without_blocking(|| lock.write()), Err(FsError::WouldBlock)));
+    }
+    *lock.write().unwrap() = 1;
+    assert_eq!(*without_blocking(|| lock.write()).unwrap(), 1);
+}
@@ -0,0 +1,59 @@
+/// Inode number, unique within one filesystem
+pub type Ino = u64;
+
+/// What kind of object an inode is
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FileType {
+    File,
+    Directory,
+    Symlink,
+    /// A device file under `/dev`
+    CharDevice,
+}
+
+/// Information about a file or directory as returned by `fs::stat`
+///
+/// Timestamps are timer ticks since boot, see `interrupts::ticks`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Metadata {
+    pub ino: Ino,
+    pub kind: FileType,
+    /// Length in bytes for files, number of entries for directories,
+    /// length of the target path for symlinks
+    pub size: u64,
+    pub created: u64,
+    pub modified: u64,
+    pub accessed: u64,
+    /// Unix style permission bits, e.g. `0o644`
+    pub mode: u16,
+    pub nlink: u32,
+}
+
+impl Metadata {
+    pub fn is_dir(&self) -> bool {
+        self.kind == FileType::Directory
+    }
+
+    pub fn is_file(&self) -> bool {
+        self.kind == FileType::File
+    }
+
+    pub fn is_symlink(&self) -> bool {
+        self.kind == FileType::Symlink
+    }
+}
+
+/// Capacity and usage of a whole filesystem as returned by `fs::statfs`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FsStats {
+    pub total_bytes: u64,
+    pub used_bytes: u64,
+    pub total_inodes: u64,
+    pub used_inodes: u64,
+}
+
+impl FsStats {
+    pub fn free_bytes(&self) -> u64 {
+        self.total_bytes.saturating_sub(self.used_bytes)
+    }
+}
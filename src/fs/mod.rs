@@ -1,42 +1,312 @@
 extern crate alloc;
-use alloc::{string::{String, ToString}, vec::Vec};
-use spin::Mutex;
+use alloc::{string::String, sync::Arc, vec::Vec};
 
+mod devfs;
+mod error;
+mod ext2;
+mod fat;
+pub mod file;
+mod future;
+mod glob;
+mod image;
+mod lock;
+mod metadata;
+pub mod path;
+mod procfs;
 mod ramfs;
+mod snapshotfs;
+mod tar;
+mod vfs;
+mod walk;
+mod watch;
+pub use error::FsError;
+pub use file::{close, open, seek, Fd, OpenFlags, SeekFrom};
+pub use future::{
+    close_async, create_dir_async, list_dir_async, open_async, read_async, read_fd_async,
+    remove_dir_all_async, remove_dir_async, remove_file_async, rename_async, seek_async,
+    stat_async, touch_async, write_async, write_fd_async, FsFuture,
+};
+pub use glob::matches as glob_matches;
+pub use image::{load as load_image, load_task as image_load_task, request_load as request_image_load, save as save_image};
+pub use lock::without_blocking;
+pub use metadata::{FileType, FsStats, Ino, Metadata};
+pub use path::{Path, PathBuf};
+pub use vfs::{FileSystem, MountInfo};
+pub use walk::{DirEntry, Walk};
+pub use watch::{Event, WatchStream};
 use ramfs::RamFs;
+pub use ramfs::RamFsLimits;
+use crate::{block::BlockDevice, println};
 
-static ROOT_FS: Mutex<Option<RamFs>> = Mutex::new(None);
+/// The `initrd` directory of the source tree, packed by `build.rs`
+static INITRD: &[u8] = include_bytes!(concat!(env!("OUT_DIR"), "/initrd.tar"));
+
+/// Timer ticks a boot time image may pause before loading gives up
+const BOOT_IMAGE_TIMEOUT: u64 = 2 * crate::interrupts::TIMER_HZ;
 
 pub async fn init() {
-    let mut __guard__ = ROOT_FS.lock();
-    let fs = RamFs::new();
-    fs.create_dir("/welcome").unwrap();
-    fs.write("/welcome/hello.txt", b"hello! welcome to unsafeOS! this filesystem only runs on your memmory!").unwrap();
-    *__guard__ = Some(fs);
+    let fs: Arc<dyn FileSystem> = Arc::new(RamFs::new());
+    if let Err(e) = tar::unpack(&*fs, INITRD) {
+        println!("WARNING: failed to unpack initrd: {}", e);
+    }
+    vfs::mount("/", fs.clone()).unwrap();
+
+    // An image already waiting on the serial line replaces the initrd files
+    if crate::serial::data_ready() {
+        match image::load("/", BOOT_IMAGE_TIMEOUT).await {
+            Ok(count) => println!("loaded {} entries from the serial image", count),
+            Err(e) => println!("WARNING: failed to load serial image: {}", e),
+        }
+    }
+
+    mount_pseudo("/dev", Arc::new(devfs::DevFs::new())).await;
+    mount_pseudo("/proc", Arc::new(procfs::ProcFs::new())).await;
+    mount_pseudo("/.snapshots", Arc::new(snapshotfs::SnapshotFs::new(fs))).await;
+}
+
+/// Mounts a kernel provided filesystem, creating its directory first
+async fn mount_pseudo(path: &str, fs: Arc<dyn FileSystem>) {
+    match create_dir_async(path).await {
+        Ok(()) | Err(FsError::AlreadyExists) => {}
+        Err(e) => println!("WARNING: failed to create {}: {}", path, e),
+    }
+    if let Err(e) = vfs::mount(path, fs) {
+        println!("WARNING: failed to mount {}: {}", path, e);
+    }
 }
 
-fn with_fs<R>(f: impl FnOnce(&RamFs) -> Result<R, String>) -> Result<R, String> {
-    let guard = ROOT_FS.lock();
-    let fs = guard.as_ref().ok_or_else(|| "Filesystem not initialized".to_string())?;
-    f(fs)
+/// Runs `f` on the filesystem mounted at `path` with the path relative to it
+fn with_fs<R>(path: &str, f: impl FnOnce(&dyn FileSystem, &str) -> Result<R, FsError>) -> Result<R, FsError> {
+    let (fs, rel) = vfs::resolve(path)?;
+    f(&*fs, &rel)
 }
 
 /// Read a file;
-pub fn read(path: &str) -> Result<Vec<u8>, String> {
-    with_fs(|fs| fs.read(path))
+pub fn read(path: &str) -> Result<Vec<u8>, FsError> {
+    with_fs(path, |fs, path| fs.read(path))
 }
 
 /// Write contents to a existing file or create a new file
-pub fn write(path: &str, data: &[u8]) -> Result<(), String> {
-    with_fs(|fs| fs.write(path, data))
+pub fn write(path: &str, data: &[u8]) -> Result<(), FsError> {
+    let existed = symlink_metadata(path).is_ok();
+    with_fs(path, |fs, path| fs.write(path, data))?;
+    if existed {
+        watch::modified(path);
+    } else {
+        watch::created(path);
+    }
+    Ok(())
+}
+
+/// Create an empty file, or update the timestamps of an existing one
+pub fn touch(path: &str) -> Result<(), FsError> {
+    let existed = symlink_metadata(path).is_ok();
+    with_fs(path, |fs, path| fs.touch(path))?;
+    if existed {
+        watch::modified(path);
+    } else {
+        watch::created(path);
+    }
+    Ok(())
+}
+
+/// Get the metadata of a file or Dir
+pub fn stat(path: &str) -> Result<Metadata, FsError> {
+    with_fs(path, |fs, path| fs.stat(path))
+}
+
+/// Get the capacity and usage of the filesystem holding `path`
+pub fn statfs(path: &str) -> Result<FsStats, FsError> {
+    with_fs(path, |fs, _| fs.statfs())
+}
+
+/// Get the metadata of a symlink itself rather than of what it points to
+pub fn symlink_metadata(path: &str) -> Result<Metadata, FsError> {
+    with_fs(path, |fs, path| fs.symlink_metadata(path))
+}
+
+/// Create a symlink at `link` pointing to `target`
+///
+/// Absolute targets are resolved inside the filesystem holding the link
+pub fn symlink(target: &str, link: &str) -> Result<(), FsError> {
+    with_fs(link, |fs, link| fs.symlink(target, link))?;
+    watch::created(link);
+    Ok(())
+}
+
+/// Create a hard link `new` sharing the inode of `existing`
+pub fn link(existing: &str, new: &str) -> Result<(), FsError> {
+    let (existing_fs, existing_rel) = vfs::resolve(existing)?;
+    let (new_fs, new_rel) = vfs::resolve(new)?;
+    if !Arc::ptr_eq(&existing_fs, &new_fs) {
+        return Err(FsError::CrossDevice);
+    }
+    existing_fs.link(&existing_rel, &new_rel)?;
+    watch::created(new);
+    Ok(())
+}
+
+/// Get the target of a symlink
+pub fn read_link(path: &str) -> Result<String, FsError> {
+    with_fs(path, |fs, path| fs.read_link(path))
 }
 
 /// Create a Dir
-pub fn create_dir(path: &str) -> Result<(), String> {
-    with_fs(|fs| fs.create_dir(path))
+///
+/// Fails with `FsError::AlreadyExists` if something already lives at `path`
+pub fn create_dir(path: &str) -> Result<(), FsError> {
+    with_fs(path, |fs, path| fs.create_dir(path))?;
+    watch::created(path);
+    Ok(())
 }
 
 /// List the contents of a Dir
-pub fn list_dir(path: &str) -> Result<Vec<String>, String> {
-    with_fs(|fs| fs.list_dir(path))
-}
\ No newline at end of file
+pub fn list_dir(path: &str) -> Result<Vec<String>, FsError> {
+    with_fs(path, |fs, path| fs.list_dir(path))
+}
+
+/// Delete a file
+pub fn remove_file(path: &str) -> Result<(), FsError> {
+    with_fs(path, |fs, path| fs.remove_file(path))?;
+    watch::removed(path);
+    Ok(())
+}
+
+/// Delete an empty Dir
+pub fn remove_dir(path: &str) -> Result<(), FsError> {
+    if vfs::is_mount_point_within(path)? {
+        return Err(FsError::Busy);
+    }
+    with_fs(path, |fs, path| fs.remove_dir(path))?;
+    watch::removed(path);
+    Ok(())
+}
+
+/// Delete a Dir and everything in it
+pub fn remove_dir_all(path: &str) -> Result<(), FsError> {
+    if vfs::is_mount_point_within(path)? {
+        return Err(FsError::Busy);
+    }
+    with_fs(path, |fs, path| fs.remove_dir_all(path))?;
+    watch::removed(path);
+    Ok(())
+}
+
+/// Rename or move a file or Dir, both paths must be on the same filesystem
+pub fn rename(from: &str, to: &str) -> Result<(), FsError> {
+    if vfs::is_mount_point_within(from)? {
+        return Err(FsError::Busy);
+    }
+    let (from_fs, from_rel) = vfs::resolve(from)?;
+    let (to_fs, to_rel) = vfs::resolve(to)?;
+    if !Arc::ptr_eq(&from_fs, &to_fs) {
+        return Err(FsError::CrossDevice);
+    }
+    from_fs.rename(&from_rel, &to_rel)?;
+    watch::renamed(from, to);
+    Ok(())
+}
+
+/// Iterate over `path` and everything below it, depth first
+///
+/// Each directory is listed only when the walk reaches it, so entries
+/// created or removed meanwhile may or may not show up.
+pub fn walk(path: &str) -> Walk {
+    walk::walk(path)
+}
+
+/// Find the paths matching `pattern`, sorted
+///
+/// `*`, `?` and `[...]` match within one component, `**` as a whole
+/// component matches any number of directories without following
+/// symlinks. Names starting with a dot need a pattern starting with one.
+pub fn glob(pattern: &str) -> Result<Vec<String>, FsError> {
+    glob::glob(pattern)
+}
+
+/// Subscribe to changes of `path` and everything below it
+///
+/// Events are queued per stream, a stream that is not polled for a long
+/// time loses the oldest ones.
+pub fn watch(path: &str) -> Result<WatchStream, FsError> {
+    symlink_metadata(path)?;
+    watch::watch(path)
+}
+
+/// Save the root filesystem as the snapshot `name`, replacing an older one
+///
+/// Only changed files and directories are copied later on, the snapshot
+/// shows up read-only under `/.snapshots/<name>`. Each snapshot counts
+/// against the size limit of the filesystem with its inode table and
+/// whatever only it still holds.
+pub fn snapshot(name: &str) -> Result<(), FsError> {
+    with_fs("/", |fs, _| fs.snapshot(name))
+}
+
+/// Roll the root filesystem back to the snapshot `name`
+///
+/// Open files keep their paths, so reads and writes through them go to
+/// whatever the restored tree holds there.
+pub fn restore(name: &str) -> Result<(), FsError> {
+    for change in with_fs("/", |fs, _| fs.restore(name))? {
+        match change {
+            Event::Created(path) => watch::created(&path),
+            Event::Modified(path) => watch::modified(&path),
+            Event::Removed(path) => watch::removed(&path),
+            Event::Renamed { from, to } => watch::renamed(&from, &to),
+        }
+    }
+    Ok(())
+}
+
+/// Drop the snapshot `name` of the root filesystem, freeing what only it uses
+pub fn remove_snapshot(name: &str) -> Result<(), FsError> {
+    with_fs("/", |fs, _| fs.remove_snapshot(name))
+}
+
+/// List the snapshots of the root filesystem
+pub fn snapshots() -> Result<Vec<String>, FsError> {
+    with_fs("/", |fs, _| fs.snapshots())
+}
+
+/// Attach `fs` at the existing directory `path`, hiding what was there
+pub fn mount(path: &str, fs: Arc<dyn FileSystem>) -> Result<(), FsError> {
+    vfs::mount(path, fs)
+}
+
+/// Detach the filesystem mounted at `path`
+pub fn umount(path: &str) -> Result<(), FsError> {
+    vfs::umount(path)
+}
+
+/// List the mount table, in mount order
+pub fn mounts() -> Result<Vec<MountInfo>, FsError> {
+    vfs::mounts()
+}
+
+/// Create a new empty in-memory filesystem, ready to be mounted
+pub fn new_ramfs() -> Arc<dyn FileSystem> {
+    Arc::new(RamFs::new())
+}
+
+/// Mounts an empty RamFs at `/` unless one is there already, tests need
+/// it because the test kernel does not run `init`
+#[cfg(test)]
+pub(crate) fn mount_test_root() {
+    let _ = vfs::mount("/", new_ramfs());
+}
+
+/// Like `new_ramfs`, but with a custom size and inode limit
+pub fn new_ramfs_with_limits(limits: RamFsLimits) -> Arc<dyn FileSystem> {
+    Arc::new(RamFs::with_limits(limits))
+}
+
+/// Open the FAT12, FAT16 or FAT32 volume on `device`, ready to be mounted
+pub fn new_fat(device: Arc<dyn BlockDevice>) -> Result<Arc<dyn FileSystem>, FsError> {
+    Ok(Arc::new(fat::FatFs::new(device)?))
+}
+
+/// Open the ext2 volume on `device` read-only, ready to be mounted
+pub fn new_ext2(device: Arc<dyn BlockDevice>) -> Result<Arc<dyn FileSystem>, FsError> {
+    Ok(Arc::new(ext2::Ext2Fs::new(device)?))
+}
@@ -0,0 +1,286 @@
+extern crate alloc;
+use alloc::{borrow::ToOwned, string::String, vec::Vec};
+use core::{borrow::Borrow, fmt, ops::Deref};
+
+/// One piece of a path as yielded by `Path::components`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Component<'a> {
+    /// The leading `/` of an absolute path
+    RootDir,
+    /// `.`
+    CurDir,
+    /// `..`
+    ParentDir,
+    Normal(&'a str),
+}
+
+impl<'a> Component<'a> {
+    fn from_str(part: &'a str) -> Component<'a> {
+        match part {
+            "." => Component::CurDir,
+            ".." => Component::ParentDir,
+            _ => Component::Normal(part),
+        }
+    }
+
+    pub fn as_str(&self) -> &'a str {
+        match self {
+            Component::RootDir => "/",
+            Component::CurDir => ".",
+            Component::ParentDir => "..",
+            Component::Normal(name) => name,
+        }
+    }
+}
+
+/// A borrowed path, a `str` split on `/`
+///
+/// Repeated and trailing slashes are allowed and ignored by every method.
+#[derive(Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+#[repr(transparent)]
+pub struct Path {
+    inner: str,
+}
+
+/// An owned, growable path
+#[derive(Debug, Clone, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct PathBuf {
+    inner: String,
+}
+
+impl Path {
+    pub fn new<S: AsRef<str> + ?Sized>(s: &S) -> &Path {
+        // Safe because `Path` is a `repr(transparent)` wrapper around `str`
+        unsafe { &*(s.as_ref() as *const str as *const Path) }
+    }
+
+    pub fn as_str(&self) -> &str {
+        &self.inner
+    }
+
+    pub fn is_absolute(&self) -> bool {
+        self.inner.starts_with('/')
+    }
+
+    pub fn to_path_buf(&self) -> PathBuf {
+        PathBuf {
+            inner: String::from(&self.inner),
+        }
+    }
+
+    /// `RootDir` first for absolute paths, then every non-empty piece
+    pub fn components(&self) -> impl DoubleEndedIterator<Item = Component<'_>> {
+        let root = self.is_absolute().then_some(Component::RootDir);
+        root.into_iter().chain(
+            self.inner
+                .split('/')
+                .filter(|s| !s.is_empty())
+                .map(Component::from_str),
+        )
+    }
+
+    /// The path without its last component, `None` for the root and ""
+    pub fn parent(&self) -> Option<&Path> {
+        let trimmed = self.inner.trim_end_matches('/');
+        if trimmed.is_empty() {
+            return None;
+        }
+        match trimmed.rfind('/') {
+            Some(i) => {
+                let parent = trimmed[..i].trim_end_matches('/');
+                Some(Path::new(if parent.is_empty() { "/" } else { parent }))
+            }
+            None => Some(Path::new("")),
+        }
+    }
+
+    /// The last component, unless it is `.`, `..` or the root
+    pub fn file_name(&self) -> Option<&str> {
+        match self.components().next_back() {
+            Some(Component::Normal(name)) => Some(name),
+            _ => None,
+        }
+    }
+
+    /// `other` appended to this path, or `other` itself if it is absolute
+    pub fn join<P: AsRef<Path> + ?Sized>(&self, other: &P) -> PathBuf {
+        let mut buf = self.to_path_buf();
+        buf.push(other);
+        buf
+    }
+
+    /// Turns the path into an absolute one without `.`, `..` or repeated
+    /// slashes, relative paths start at `cwd`.
+    /// Purely textual, `..` after a symlink goes back to where it was.
+    pub fn canonicalize(&self, cwd: &Path) -> PathBuf {
+        let mut parts: Vec<&str> = Vec::new();
+        let base = if self.is_absolute() { None } else { Some(cwd) };
+        for component in base.into_iter().flat_map(Path::components).chain(self.components()) {
+            match component {
+                Component::RootDir => parts.clear(),
+                Component::CurDir => {}
+                Component::ParentDir => {
+                    parts.pop();
+                }
+                Component::Normal(name) => parts.push(name),
+            }
+        }
+
+        let mut inner = String::new();
+        for part in &parts {
+            inner.push('/');
+            inner.push_str(part);
+        }
+        if inner.is_empty() {
+            inner.push('/');
+        }
+        PathBuf { inner }
+    }
+}
+
+impl PathBuf {
+    pub fn new() -> PathBuf {
+        PathBuf::default()
+    }
+
+    pub fn as_path(&self) -> &Path {
+        Path::new(&self.inner)
+    }
+
+    pub fn into_string(self) -> String {
+        self.inner
+    }
+
+    /// Appends `path`, replacing everything if it is absolute
+    pub fn push<P: AsRef<Path> + ?Sized>(&mut self, path: &P) {
+        let path = path.as_ref();
+        if path.is_absolute() {
+            self.inner.clear();
+        } else if !self.inner.is_empty() && !self.inner.ends_with('/') {
+            self.inner.push('/');
+        }
+        self.inner.push_str(path.as_str());
+    }
+
+    /// Cuts off the last component, returns false if there was no parent
+    pub fn pop(&mut self) -> bool {
+        match self.parent().map(|p| p.inner.len()) {
+            Some(len) => {
+                self.inner.truncate(len);
+                true
+            }
+            None => false,
+        }
+    }
+}
+
+impl Deref for PathBuf {
+    type Target = Path;
+
+    fn deref(&self) -> &Path {
+        self.as_path()
+    }
+}
+
+impl Borrow<Path> for PathBuf {
+    fn borrow(&self) -> &Path {
+        self.as_path()
+    }
+}
+
+impl ToOwned for Path {
+    type Owned = PathBuf;
+
+    fn to_owned(&self) -> PathBuf {
+        self.to_path_buf()
+    }
+}
+
+impl AsRef<Path> for Path {
+    fn as_ref(&self) -> &Path {
+        self
+    }
+}
+
+impl AsRef<Path> for PathBuf {
+    fn as_ref(&self) -> &Path {
+        self.as_path()
+    }
+}
+
+impl AsRef<Path> for str {
+    fn as_ref(&self) -> &Path {
+        Path::new(self)
+    }
+}
+
+impl AsRef<Path> for String {
+    fn as_ref(&self) -> &Path {
+        Path::new(self)
+    }
+}
+
+impl AsRef<str> for Path {
+    fn as_ref(&self) -> &str {
+        &self.inner
+    }
+}
+
+impl From<&str> for PathBuf {
+    fn from(s: &str) -> PathBuf {
+        PathBuf { inner: String::from(s) }
+    }
+}
+
+impl From<String> for PathBuf {
+    fn from(inner: String) -> PathBuf {
+        PathBuf { inner }
+    }
+}
+
+impl fmt::Display for Path {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(&self.inner)
+    }
+}
+
+impl fmt::Display for PathBuf {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(&self.inner)
+    }
+}
+
+#[test_case]
+fn test_path() {
+    let path = Path::new("/usr//share/./doc/");
+    let parts: Vec<&str> = path.components().map(|c| c.as_str()).collect();
+    assert_eq!(parts, ["/", "usr", "share", ".", "doc"]);
+    assert_eq!(path.file_name(), Some("doc"));
+    assert_eq!(path.parent(), Some(Path::new("/usr//share/.")));
+    assert_eq!(Path::new("/etc").parent(), Some(Path::new("/")));
+    assert_eq!(Path::new("//etc").parent(), Some(Path::new("/")));
+    assert_eq!(Path::new("/").parent(), None);
+    assert_eq!(Path::new("a").parent(), Some(Path::new("")));
+    assert_eq!(Path::new("/a/..").file_name(), None);
+
+    assert_eq!(Path::new("/").join("etc").as_str(), "/etc");
+    assert_eq!(Path::new("/etc").join("motd").as_str(), "/etc/motd");
+    assert_eq!(Path::new("/etc").join("/dev").as_str(), "/dev");
+
+    let mut buf = PathBuf::from("/a/b");
+    assert!(buf.pop());
+    assert_eq!(buf.as_str(), "/a");
+    assert!(buf.pop());
+    assert!(!buf.pop());
+    assert_eq!(buf.as_str(), "/");
+}
+
+#[test_case]
+fn test_canonicalize() {
+    let cwd = Path::new("/home/user");
+    assert_eq!(Path::new("file").canonicalize(cwd).as_str(), "/home/user/file");
+    assert_eq!(Path::new("../x/./y//").canonicalize(cwd).as_str(), "/home/x/y");
+    assert_eq!(Path::new("../../../..").canonicalize(cwd).as_str(), "/");
+    assert_eq!(Path::new("/etc/../dev").canonicalize(cwd).as_str(), "/dev");
+    assert_eq!(Path::new(".").canonicalize(Path::new("/")).as_str(), "/");
+}
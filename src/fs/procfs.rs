@@ -0,0 +1,218 @@
+extern crate alloc;
+use alloc::{format, string::{String, ToString}, vec::Vec};
+use core::fmt::Write;
+use crate::{allocator, block, interrupts, memory, task};
+use super::{vfs, FileSystem, FileType, FsError, Metadata};
+
+type Generator = fn() -> Result<String, FsError>;
+
+const FILES: &[(&str, Generator)] = &[
+    ("meminfo", meminfo),
+    ("tasks", task_list),
+    ("interrupts", interrupt_counts),
+    ("uptime", uptime),
+    ("mounts", mounts),
+    ("blockcache", block_cache),
+];
+
+fn meminfo() -> Result<String, FsError> {
+    let heap = allocator::heap_stats();
+    let frames = memory::frame_stats();
+    let mut out = String::new();
+    let _ = writeln!(out, "HeapTotal:    {:>8} B", heap.size);
+    let _ = writeln!(out, "HeapUsed:     {:>8} B", heap.used);
+    let _ = writeln!(out, "HeapCached:   {:>8} B", heap.cached);
+    let _ = writeln!(out, "HeapFree:     {:>8} B", heap.size.saturating_sub(heap.used + heap.cached));
+    let _ = writeln!(out, "FramesTotal:  {:>8}", frames.usable);
+    let _ = writeln!(out, "FramesUsed:   {:>8}", frames.allocated);
+    let _ = writeln!(out, "FramesFree:   {:>8}", frames.usable.saturating_sub(frames.allocated));
+    Ok(out)
+}
+
+fn block_cache() -> Result<String, FsError> {
+    let stats = block::cache::CACHE.stats();
+    let mut out = String::new();
+    let _ = writeln!(out, "Budget:       {:>8} B", stats.budget);
+    let _ = writeln!(out, "Used:         {:>8} B", stats.used);
+    let _ = writeln!(out, "Dirty:        {:>8} B", stats.dirty);
+    let _ = writeln!(out, "Hits:         {:>8}", stats.hits);
+    let _ = writeln!(out, "Misses:       {:>8}", stats.misses);
+    let _ = writeln!(out, "Writebacks:   {:>8}", stats.writebacks);
+    Ok(out)
+}
+
+fn task_list() -> Result<String, FsError> {
+    let mut out = String::from("ID     STATE\n");
+    for info in task::tasks() {
+        let state = match info.state {
+            task::TaskState::Ready => "ready",
+            task::TaskState::Running => "running",
+            task::TaskState::Waiting => "waiting",
+        };
+        let _ = writeln!(out, "{:<6} {}", info.id, state);
+    }
+    Ok(out)
+}
+
+fn interrupt_counts() -> Result<String, FsError> {
+    let mut out = String::new();
+    for &(vector, name) in interrupts::HANDLED_VECTORS {
+        let _ = writeln!(out, "{:>3}: {:>10}  {}", vector, interrupts::interrupt_count(vector), name);
+    }
+    Ok(out)
+}
+
+fn uptime() -> Result<String, FsError> {
+    let ms = interrupts::uptime_ms();
+    Ok(format!("{}.{:02}\n", ms / 1000, ms % 1000 / 10))
+}
+
+fn mounts() -> Result<String, FsError> {
+    let mut out = String::new();
+    for mount in vfs::mounts()? {
+        let _ = writeln!(out, "{} {}", mount.fs_name, mount.path);
+    }
+    Ok(out)
+}
+
+/// Kernel state rendered as text on every read, mounted at `/proc`
+///
+/// Files report a size of 0 since their contents only exist while read.
+pub(crate) struct ProcFs;
+
+impl ProcFs {
+    pub(crate) fn new() -> Self {
+        ProcFs
+    }
+
+    fn generate(path: &str) -> Result<String, FsError> {
+        let name = path.trim_matches('/');
+        if name.is_empty() {
+            return Err(FsError::IsADirectory);
+        }
+        FILES
+            .iter()
+            .find(|&&(n, _)| n == name)
+            .map(|&(_, generate)| generate())
+            .ok_or(FsError::NotFound)?
+    }
+
+    fn ino(path: &str) -> Result<u64, FsError> {
+        let name = path.trim_matches('/');
+        if name.is_empty() {
+            return Ok(1);
+        }
+        FILES
+            .iter()
+            .position(|&(n, _)| n == name)
+            .map(|index| index as u64 + 2)
+            .ok_or(FsError::NotFound)
+    }
+
+    fn is_root(path: &str) -> bool {
+        path.trim_matches('/').is_empty()
+    }
+
+    /// Checks that `path` exists before refusing to modify it
+    fn read_only(path: &str) -> Result<(), FsError> {
+        Self::ino(path)?;
+        Err(FsError::ReadOnly)
+    }
+}
+
+impl FileSystem for ProcFs {
+    fn name(&self) -> &'static str {
+        "procfs"
+    }
+
+    fn read(&self, path: &str) -> Result<Vec<u8>, FsError> {
+        Ok(Self::generate(path)?.into_bytes())
+    }
+
+    fn write(&self, path: &str, _data: &[u8]) -> Result<(), FsError> {
+        Self::read_only(path)
+    }
+
+    fn read_at(&self, path: &str, offset: u64, buf: &mut [u8]) -> Result<usize, FsError> {
+        let content = Self::generate(path)?;
+        let bytes = content.as_bytes();
+        let start = (offset as usize).min(bytes.len());
+        let count = buf.len().min(bytes.len() - start);
+        buf[..count].copy_from_slice(&bytes[start..start + count]);
+        Ok(count)
+    }
+
+    fn write_at(&self, path: &str, _offset: u64, _data: &[u8]) -> Result<usize, FsError> {
+        Self::read_only(path).map(|_| 0)
+    }
+
+    fn truncate(&self, path: &str, _len: u64) -> Result<(), FsError> {
+        Self::read_only(path)
+    }
+
+    fn touch(&self, _path: &str) -> Result<(), FsError> {
+        Err(FsError::ReadOnly)
+    }
+
+    fn create_dir(&self, _path: &str) -> Result<(), FsError> {
+        Err(FsError::ReadOnly)
+    }
+
+    fn list_dir(&self, path: &str) -> Result<Vec<String>, FsError> {
+        if !Self::is_root(path) {
+            Self::ino(path)?;
+            return Err(FsError::NotADirectory);
+        }
+        Ok(FILES.iter().map(|&(name, _)| name.to_string()).collect())
+    }
+
+    fn stat(&self, path: &str) -> Result<Metadata, FsError> {
+        let ino = Self::ino(path)?;
+        let (kind, size, mode, nlink) = if Self::is_root(path) {
+            (FileType::Directory, FILES.len() as u64, 0o555, 2)
+        } else {
+            (FileType::File, 0, 0o444, 1)
+        };
+        let now = interrupts::ticks();
+        Ok(Metadata {
+            ino,
+            kind,
+            size,
+            created: 0,
+            modified: now,
+            accessed: now,
+            mode,
+            nlink,
+        })
+    }
+
+    fn remove_file(&self, path: &str) -> Result<(), FsError> {
+        Self::read_only(path)
+    }
+
+    fn remove_dir(&self, path: &str) -> Result<(), FsError> {
+        Self::read_only(path)
+    }
+
+    fn rename(&self, from: &str, _to: &str) -> Result<(), FsError> {
+        Self::read_only(from)
+    }
+}
+
+#[test_case]
+fn test_procfs() {
+    let fs = ProcFs::new();
+    assert!(fs.list_dir("/").unwrap().iter().any(|name| name == "meminfo"));
+    assert!(fs.stat("/").unwrap().is_dir());
+    assert!(fs.read("/uptime").unwrap().ends_with(b"\n"));
+
+    let meminfo = String::from_utf8(fs.read("/meminfo").unwrap()).unwrap();
+    assert!(meminfo.starts_with("HeapTotal:"));
+    let mut buf = [0u8; 4];
+    assert_eq!(fs.read_at("/meminfo", 0, &mut buf).unwrap(), 4);
+    assert_eq!(&buf, b"Heap");
+
+    assert_eq!(fs.write("/uptime", b"0"), Err(FsError::ReadOnly));
+    assert_eq!(fs.write("/missing", b"0"), Err(FsError::NotFound));
+    assert_eq!(fs.list_dir("/tasks"), Err(FsError::NotADirectory));
+}
@@ -1,120 +1,1278 @@
 extern crate alloc;
 use alloc::{
-    collections::BTreeMap, format, string::{String, ToString}, vec::Vec
+    collections::BTreeMap, format, string::{String, ToString}, sync::Arc, vec::Vec
 };
-use spin::Mutex;
+use core::sync::atomic::{AtomicU64, Ordering};
+use spin::RwLockWriteGuard;
+use crate::{allocator, interrupts};
+use super::{lock::FsLock, path::{Component, Path}, Event, FileSystem, FileType, FsError, FsStats, Ino, Metadata};
+
+const ROOT_INO: Ino = 1;
+const FILE_MODE: u16 = 0o644;
+const DIR_MODE: u16 = 0o755;
+const SYMLINK_MODE: u16 = 0o777;
+
+/// Maximum number of symlinks followed while resolving one path
+const MAX_SYMLINK_FOLLOWS: usize = 8;
+
+/// Bytes charged per slot of an inode table with room for the nodes, the
+/// live tree and every snapshot have a table of their own
+const TABLE_ENTRY_COST: u64 = 2 * size_of::<(Ino, Arc<Inode>)>() as u64;
+
+/// Bytes charged per inode besides its data: the `Inode` with its `Arc`
+/// counters, and its slot in the inode table
+const INODE_COST: u64 = (size_of::<Inode>() + 2 * size_of::<usize>()) as u64 + TABLE_ENTRY_COST;
+
+/// Bytes charged per snapshot besides its name and table: the frozen
+/// `RamFs` with its `Arc` counters, and its slot in the snapshot map
+const SNAPSHOT_COST: u64 = (size_of::<RamFs>() + 2 * size_of::<usize>() + 2 * size_of::<(String, Arc<RamFs>)>()) as u64;
+
+/// Bytes charged per directory entry besides its name, its slot in the
+/// directory's map with room for the nodes
+const ENTRY_COST: u64 = 2 * size_of::<(String, Ino)>() as u64;
+
+fn entry_cost(name: &str) -> u64 {
+    ENTRY_COST + name.len() as u64
+}
+
+/// How much a RamFs may hold, fixed when it is created
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RamFsLimits {
+    /// Bytes of file contents and symlink targets, plus a fixed cost per
+    /// inode and per directory entry with its name
+    pub max_bytes: u64,
+    pub max_inodes: u64,
+}
+
+impl Default for RamFsLimits {
+    /// Half the kernel heap, so a full filesystem leaves room for everything else
+    fn default() -> Self {
+        RamFsLimits {
+            max_bytes: allocator::HEAP_SIZE as u64 / 2,
+            max_inodes: 256,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+enum InodeData {
+    /// Shared with snapshots until one side changes it
+    File(Arc<Vec<u8>>),
+    Directory(BTreeMap<String, Ino>),
+    /// Path of the target, resolved inside this filesystem
+    Symlink(String),
+}
 
 #[derive(Debug)]
-enum Node {
-    File(Vec<u8>),
-    Directory(BTreeMap<String, Node>),
+struct Inode {
+    /// `meta.accessed` is stale, the real value lives in `accessed`
+    meta: Metadata,
+    data: InodeData,
+    /// Bumped by reads, which only hold the lock shared
+    accessed: AtomicU64,
+}
+
+impl InodeData {
+    /// Bytes counted against `RamFsLimits::max_bytes`
+    fn charge(&self) -> u64 {
+        match self {
+            InodeData::File(__data__) => __data__.len() as u64,
+            InodeData::Symlink(__target__) => __target__.len() as u64,
+            InodeData::Directory(_) => 0,
+        }
+    }
+}
+
+impl Inode {
+    fn new(ino: Ino, data: InodeData) -> Self {
+        let now = interrupts::ticks();
+        let (kind, mode, nlink) = match data {
+            InodeData::File(_) => (FileType::File, FILE_MODE, 1),
+            InodeData::Directory(_) => (FileType::Directory, DIR_MODE, 2),
+            InodeData::Symlink(_) => (FileType::Symlink, SYMLINK_MODE, 1),
+        };
+        let mut inode = Inode {
+            meta: Metadata {
+                ino,
+                kind,
+                size: 0,
+                created: now,
+                modified: now,
+                accessed: now,
+                mode,
+                nlink,
+            },
+            data,
+            accessed: AtomicU64::new(now),
+        };
+        inode.update_size();
+        inode
+    }
+
+    fn update_size(&mut self) {
+        self.meta.size = match &self.data {
+            InodeData::File(__data__) => __data__.len() as u64,
+            InodeData::Directory(__children__) => __children__.len() as u64,
+            InodeData::Symlink(__target__) => __target__.len() as u64,
+        };
+    }
+
+    fn touch_modified(&mut self) {
+        let now = interrupts::ticks();
+        self.meta.modified = now;
+        self.accessed.store(now, Ordering::Relaxed);
+        self.update_size();
+    }
+
+    fn touch_accessed(&self) {
+        self.accessed.store(interrupts::ticks(), Ordering::Relaxed);
+    }
+
+    fn metadata(&self) -> Metadata {
+        Metadata {
+            accessed: self.accessed.load(Ordering::Relaxed),
+            ..self.meta
+        }
+    }
+
+    /// Bytes a copy of the inode allocates, file contents stay shared
+    fn copy_cost(&self) -> u64 {
+        INODE_COST
+            + match &self.data {
+                InodeData::Directory(__children__) => __children__.keys().map(|name| entry_cost(name)).sum(),
+                InodeData::Symlink(__target__) => __target__.len() as u64,
+                InodeData::File(_) => 0,
+            }
+    }
+
+    /// Copies the inode with its names and symlink target, without aborting
+    /// on a full heap
+    fn try_clone(&self) -> Result<Inode, FsError> {
+        let data = match &self.data {
+            InodeData::File(__data__) => InodeData::File(__data__.clone()),
+            InodeData::Directory(__children__) => {
+                let mut copy = BTreeMap::new();
+                for (name, &ino) in __children__ {
+                    copy.insert(copy_str(name)?, ino);
+                }
+                InodeData::Directory(copy)
+            }
+            InodeData::Symlink(__target__) => InodeData::Symlink(copy_str(__target__)?),
+        };
+        Ok(Inode {
+            meta: self.meta,
+            data,
+            accessed: AtomicU64::new(self.accessed.load(Ordering::Relaxed)),
+        })
+    }
+}
+
+/// Bytes of `inode` that only a snapshot holds once the live tree lets go
+/// of it: the inode itself if it is shared, its contents if they are
+fn shared_charge(inode: &Arc<Inode>) -> u64 {
+    let shared = Arc::strong_count(inode) > 1;
+    let contents = match &inode.data {
+        InodeData::File(__data__) if shared || Arc::strong_count(__data__) > 1 => __data__.len() as u64,
+        _ => 0,
+    };
+    if shared { inode.copy_cost() + contents } else { contents }
+}
+
+/// Every inode of the filesystem, keyed by inode number.
+/// Directories refer to their children by number only.
+///
+/// Snapshots only copy the table, inodes are shared until `get_mut`.
+struct Inodes {
+    table: BTreeMap<Ino, Arc<Inode>>,
+    next_ino: Ino,
+    limits: RamFsLimits,
+    /// Sum of `InodeData::charge` and `INODE_COST` over the table, plus
+    /// `entry_cost` of every directory entry
+    used_bytes: u64,
+    /// What snapshots hold that the live tree copied or dropped since,
+    /// counted against the limit as well
+    snapshot_bytes: u64,
+}
+
+impl Inodes {
+    fn get(&self, ino: Ino) -> &Inode {
+        self.table.get(&ino).expect("dangling inode number")
+    }
+
+    /// Bytes a snapshot named `name` of this tree is charged besides the
+    /// inodes it ends up holding on its own
+    fn snapshot_cost(&self, name: &str) -> u64 {
+        SNAPSHOT_COST + name.len() as u64 + self.table.len() as u64 * TABLE_ENTRY_COST
+    }
+
+    /// Copies the table, sharing every inode. The entries are gathered
+    /// without aborting on a full heap, the map nodes are what callers
+    /// charge `TABLE_ENTRY_COST` for beforehand.
+    fn share(&self) -> Result<Inodes, FsError> {
+        let mut entries = Vec::new();
+        entries.try_reserve_exact(self.table.len())
+            .map_err(|_| FsError::NoSpace)?;
+        entries.extend(self.table.iter().map(|(&ino, inode)| (ino, inode.clone())));
+        Ok(Inodes {
+            table: entries.into_iter().collect(),
+            next_ino: self.next_ino,
+            limits: self.limits,
+            used_bytes: self.used_bytes,
+            snapshot_bytes: 0,
+        })
+    }
+
+    /// Every allocation of the tree a snapshot can share, with the bytes it
+    /// is charged
+    fn allocations(&self, out: &mut Vec<(*const (), u64)>) -> Result<(), FsError> {
+        out.try_reserve(2 * self.table.len())
+            .map_err(|_| FsError::NoSpace)?;
+        for __inode__ in self.table.values() {
+            out.push((Arc::as_ptr(__inode__).cast(), __inode__.copy_cost()));
+            if let InodeData::File(__data__) = &__inode__.data {
+                out.push((Arc::as_ptr(__data__).cast(), __data__.len() as u64));
+            }
+        }
+        Ok(())
+    }
+
+    /// Copies the inode first if a snapshot still shares it, the snapshot's
+    /// version is charged from then on
+    fn get_mut(&mut self, ino: Ino) -> Result<&mut Inode, FsError> {
+        let __inode__ = self.table.get(&ino).expect("dangling inode number");
+        if Arc::strong_count(__inode__) > 1 {
+            let cost = __inode__.copy_cost();
+            self.check_space(0, cost)?;
+            let copy = Arc::new(__inode__.try_clone()?);
+            self.table.insert(ino, copy);
+            self.snapshot_bytes += cost;
+        }
+        Ok(Arc::get_mut(self.table.get_mut(&ino).unwrap()).unwrap())
+    }
+
+    /// Length of the contents of `ino` that a snapshot still shares, which a
+    /// change has to copy first. Copies the inode itself right away.
+    fn shared_contents(&mut self, ino: Ino) -> Result<u64, FsError> {
+        match &self.get_mut(ino)?.data {
+            InodeData::File(__data__) if Arc::strong_count(__data__) > 1 => Ok(__data__.len() as u64),
+            _ => Ok(0),
+        }
+    }
+
+    /// Fails with `NoSpace` if something growing from `old` to `new` bytes
+    /// would not fit under the limit
+    fn check_space(&self, old: u64, new: u64) -> Result<(), FsError> {
+        if self.used_bytes + self.snapshot_bytes - old + new > self.limits.max_bytes {
+            return Err(FsError::NoSpace);
+        }
+        Ok(())
+    }
+
+    fn account(&mut self, old: u64, new: u64) {
+        self.used_bytes = self.used_bytes - old + new;
+    }
+
+    /// Length of the file `ino`, directories are refused
+    fn file_len(&self, ino: Ino) -> Result<u64, FsError> {
+        match &self.get(ino).data {
+            InodeData::File(__data__) => Ok(__data__.len() as u64),
+            _ => Err(FsError::IsADirectory),
+        }
+    }
+
+    /// Walks `path` from the root. Symlinks met on the way are followed, the
+    /// last component only if `follow_last` is set.
+    fn resolve<'a>(&'a self, path: &[Component<'a>], follow_last: bool) -> Result<Ino, FsError> {
+        // Directories walked so far, so `..` can step back out of them
+        let mut stack: Vec<Ino> = Vec::new();
+        let mut pending: Vec<Component<'a>> = path.iter().rev().copied().collect();
+        let mut follows = 0;
+        let mut __ino__ = ROOT_INO;
+
+        while let Some(component) = pending.pop() {
+            let part = match component {
+                Component::RootDir => {
+                    stack.clear();
+                    __ino__ = ROOT_INO;
+                    continue;
+                }
+                Component::CurDir => continue,
+                Component::ParentDir => {
+                    __ino__ = stack.pop().unwrap_or(ROOT_INO);
+                    continue;
+                }
+                Component::Normal(part) => part,
+            };
+
+            let child = match &self.get(__ino__).data {
+                InodeData::Directory(__children__) => {
+                    *__children__.get(part).ok_or(FsError::NotFound)?
+                }
+                _ => return Err(FsError::NotADirectory),
+            };
+
+            if let InodeData::Symlink(__target__) = &self.get(child).data
+                && (follow_last || !pending.is_empty())
+            {
+                follows += 1;
+                if follows > MAX_SYMLINK_FOLLOWS {
+                    return Err(FsError::TooManySymlinks);
+                }
+                // An absolute target starts with `RootDir`, which restarts the walk
+                pending.extend(Path::new(__target__).components().rev());
+                continue;
+            }
+
+            stack.push(__ino__);
+            __ino__ = child;
+        }
+        Ok(__ino__)
+    }
+
+    fn lookup(&self, path: &[Component]) -> Result<Ino, FsError> {
+        self.resolve(path, true)
+    }
+
+    /// Like `lookup`, but a symlink in the last component is returned itself
+    fn lookup_nofollow(&self, path: &[Component]) -> Result<Ino, FsError> {
+        self.resolve(path, false)
+    }
+
+    /// Looks up the directory that should hold the last component of `path`
+    fn lookup_parent(&self, dirs: &[Component]) -> Result<Ino, FsError> {
+        let ino = self.lookup(dirs)?;
+        match self.get(ino).data {
+            InodeData::Directory(_) => Ok(ino),
+            _ => Err(FsError::NotADirectory),
+        }
+    }
+
+    fn children(&self, dir: Ino) -> &BTreeMap<String, Ino> {
+        match &self.get(dir).data {
+            InodeData::Directory(__children__) => __children__,
+            _ => unreachable!("parent checked to be a directory"),
+        }
+    }
+
+    fn children_mut(&mut self, dir: Ino) -> Result<&mut BTreeMap<String, Ino>, FsError> {
+        match &mut self.get_mut(dir)?.data {
+            InodeData::Directory(__children__) => Ok(__children__),
+            _ => unreachable!("parent checked to be a directory"),
+        }
+    }
+
+    /// Links a freshly allocated inode into `dir` under `name`
+    fn insert_child(&mut self, dir: Ino, name: &str, data: InodeData) -> Result<Ino, FsError> {
+        if self.table.len() as u64 >= self.limits.max_inodes {
+            return Err(FsError::NoSpace);
+        }
+        // Copy the parent away from snapshots before anything changes
+        self.get_mut(dir)?;
+        let charge = INODE_COST + data.charge() + entry_cost(name);
+        self.check_space(0, charge)?;
+        let is_dir = matches!(data, InodeData::Directory(_));
+        let ino = self.next_ino;
+        self.next_ino += 1;
+        self.table.insert(ino, Arc::new(Inode::new(ino, data)));
+        self.children_mut(dir)?.insert(name.to_string(), ino);
+        self.account(0, charge);
+        let __parent__ = self.get_mut(dir)?;
+        if is_dir {
+            __parent__.meta.nlink += 1;
+        }
+        __parent__.touch_modified();
+        Ok(ino)
+    }
+
+    /// Whether `target` is the directory `ancestor` or lies somewhere below it
+    fn dir_contains(&self, ancestor: Ino, target: Ino) -> bool {
+        if ancestor == target {
+            return true;
+        }
+        match &self.get(ancestor).data {
+            InodeData::Directory(__children__) => __children__
+                .values()
+                .any(|&child| self.dir_contains(child, target)),
+            _ => false,
+        }
+    }
+
+    /// `path`, the inode `ino`, and every path below it, children before
+    /// their parents
+    fn subtree(&self, ino: Ino, path: String, paths: &mut Vec<String>) {
+        if let InodeData::Directory(__children__) = &self.get(ino).data {
+            for (name, &child) in __children__ {
+                self.subtree(child, child_path(&path, name), paths);
+            }
+        }
+        paths.push(path);
+    }
+
+    /// Appends what changes below the directory `dir` at `path` when `self`
+    /// is replaced by `new`
+    fn changes(&self, new: &Inodes, dir: Ino, path: &str, changes: &mut Vec<Event>) {
+        let (before, after) = (self.children(dir), new.children(dir));
+        for (name, &ino) in before {
+            let path = child_path(path, name);
+            match after.get(name) {
+                // Numbers are never reused, so the same number is the same
+                // file. A shared directory can still have changed children.
+                Some(&same) if same == ino => match self.get(ino).data {
+                    InodeData::Directory(_) => self.changes(new, ino, &path, changes),
+                    _ if Arc::ptr_eq(&self.table[&ino], &new.table[&ino]) => {}
+                    _ => changes.push(Event::Modified(path)),
+                },
+                replacement => {
+                    let mut paths = Vec::new();
+                    self.subtree(ino, path.clone(), &mut paths);
+                    changes.extend(paths.into_iter().map(Event::Removed));
+                    if let Some(&other) = replacement {
+                        let mut paths = Vec::new();
+                        new.subtree(other, path, &mut paths);
+                        changes.extend(paths.into_iter().rev().map(Event::Created));
+                    }
+                }
+            }
+        }
+        for (name, &ino) in after {
+            if !before.contains_key(name) {
+                let mut paths = Vec::new();
+                new.subtree(ino, child_path(path, name), &mut paths);
+                changes.extend(paths.into_iter().rev().map(Event::Created));
+            }
+        }
+    }
+
+    fn is_empty_dir(&self, ino: Ino) -> bool {
+        matches!(&self.get(ino).data, InodeData::Directory(c) if c.is_empty())
+    }
+
+    /// Removes the entry `name` from `dir` and frees its inode once no
+    /// directory entry refers to it any more
+    fn unlink(&mut self, dir: Ino, name: &str) -> Result<(), FsError> {
+        let ino = *self.children(dir).get(name).expect("unlinking a missing entry");
+        let is_dir = self.get(ino).meta.kind == FileType::Directory;
+        let nlink = self.get(ino).meta.nlink - if is_dir { 2 } else { 1 };
+        // Copy whatever changes away from snapshots before anything changes
+        self.get_mut(dir)?;
+        if nlink > 0 {
+            self.get_mut(ino)?.meta.nlink = nlink;
+        }
+
+        self.children_mut(dir)?.remove(name);
+        let __parent__ = self.get_mut(dir)?;
+        if is_dir {
+            __parent__.meta.nlink -= 1;
+        }
+        __parent__.touch_modified();
+        self.account(entry_cost(name), 0);
+
+        if nlink == 0 {
+            let __inode__ = self.table.remove(&ino).unwrap();
+            self.account(INODE_COST + __inode__.data.charge(), 0);
+            self.snapshot_bytes += shared_charge(&__inode__);
+        }
+        Ok(())
+    }
+}
+
+fn child_path(dir: &str, name: &str) -> String {
+    match dir {
+        "/" => format!("/{name}"),
+        _ => format!("{dir}/{name}"),
+    }
+}
+
+/// Grows `data` to `len` bytes with zeros without aborting on a full heap
+fn grow_zeroed(data: &mut Vec<u8>, len: usize) -> Result<(), FsError> {
+    if len > data.len() {
+        data.try_reserve(len - data.len())
+            .map_err(|_| FsError::NoSpace)?;
+        data.resize(len, 0);
+    }
+    Ok(())
+}
+
+fn copy_bytes(data: &[u8]) -> Result<Vec<u8>, FsError> {
+    let mut copy = Vec::new();
+    copy.try_reserve_exact(data.len())
+        .map_err(|_| FsError::NoSpace)?;
+    copy.extend_from_slice(data);
+    Ok(copy)
+}
+
+fn copy_str(text: &str) -> Result<String, FsError> {
+    let mut copy = String::new();
+    copy.try_reserve_exact(text.len())
+        .map_err(|_| FsError::NoSpace)?;
+    copy.push_str(text);
+    Ok(copy)
+}
+
+/// File contents ready to be changed in place, copied first if a snapshot
+/// still shares them
+fn unshare(data: &mut Arc<Vec<u8>>) -> Result<&mut Vec<u8>, FsError> {
+    if Arc::get_mut(data).is_none() {
+        *data = Arc::new(copy_bytes(data)?);
+    }
+    Ok(Arc::get_mut(data).unwrap())
 }
 
 pub(crate) struct RamFs {
-    root: Mutex<Node>,
+    inodes: FsLock<Inodes>,
+    /// Frozen copies of the tree by name, see `FileSystem::snapshot`
+    snapshots: FsLock<BTreeMap<String, Arc<RamFs>>>,
+    /// Set for snapshots, which refuse every change
+    read_only: bool,
 }
 
 impl RamFs {
     pub(crate) fn new() -> Self {
+        Self::with_limits(RamFsLimits::default())
+    }
+
+    pub(crate) fn with_limits(limits: RamFsLimits) -> Self {
+        let mut table = BTreeMap::new();
+        table.insert(ROOT_INO, Arc::new(Inode::new(ROOT_INO, InodeData::Directory(BTreeMap::new()))));
         Self {
-            root: Mutex::new(Node::Directory(BTreeMap::new())),
+            inodes: FsLock::new(Inodes {
+                table,
+                next_ino: ROOT_INO + 1,
+                limits,
+                used_bytes: INODE_COST,
+                snapshot_bytes: 0,
+            }),
+            snapshots: FsLock::new(BTreeMap::new()),
+            read_only: false,
         }
     }
 
-    fn split_path(path: &str) -> Vec<&str> {
-        path.trim_matches('/')
-            .split('/')
-            .filter(|s| !s.is_empty())
-            .collect()
+    /// Takes the inode lock for a change, snapshots refuse with `ReadOnly`
+    fn write_inodes(&self) -> Result<RwLockWriteGuard<'_, Inodes>, FsError> {
+        if self.read_only {
+            return Err(FsError::ReadOnly);
+        }
+        self.inodes.write()
     }
 
-    fn traverse_mut<'a>(
-        mut __node__: &'a mut Node,
-        path: &[&str],
-    ) -> Result<&'a mut Node, String> {
-        for part in path {
-            match __node__ {
-                Node::Directory(__children__) => {
-                    __node__ = __children__
-                        .get_mut(*part)
-                        .ok_or_else(|| format!("Path not found: {}", part))?;
-                }
-                _ => return Err("Not a directory".to_string()),
+    /// Counts `Inodes::snapshot_bytes` of `live` again, after snapshots
+    /// were dropped or the live tree was replaced
+    fn recount_snapshot_bytes(&self, live: &mut Inodes) -> Result<(), FsError> {
+        let mut shared = Vec::new();
+        live.allocations(&mut shared)?;
+        shared.sort_unstable_by_key(|&(ptr, _)| ptr);
+
+        let mut held = Vec::new();
+        let mut bytes = 0;
+        for (name, frozen) in self.snapshots.read()?.iter() {
+            let __frozen__ = frozen.inodes.read()?;
+            bytes += __frozen__.snapshot_cost(name);
+            __frozen__.allocations(&mut held)?;
+        }
+        // Counted once however many snapshots hold it, and not at all while
+        // the live tree still uses it
+        held.sort_unstable_by_key(|&(ptr, _)| ptr);
+        held.dedup_by_key(|&mut (ptr, _)| ptr);
+        bytes += held
+            .iter()
+            .filter(|&&(ptr, _)| shared.binary_search_by_key(&ptr, |&(p, _)| p).is_err())
+            .map(|&(_, cost)| cost)
+            .sum::<u64>();
+        live.snapshot_bytes = bytes;
+        Ok(())
+    }
+
+    fn open_snapshot_ramfs(&self, name: &str) -> Result<Arc<RamFs>, FsError> {
+        self.snapshots.read()?.get(name).cloned().ok_or(FsError::NotFound)
+    }
+
+    /// Snapshot names are single path components
+    fn check_snapshot_name(name: &str) -> Result<(), FsError> {
+        match Path::new(name).components().collect::<Vec<_>>().as_slice() {
+            [Component::Normal(n)] if *n == name => Ok(()),
+            _ => Err(FsError::InvalidPath),
+        }
+    }
+
+    fn split_path(path: &str) -> Vec<Component<'_>> {
+        Path::new(path).components().collect()
+    }
+
+    /// Splits a path into its parent components and the final name.
+    /// Fails for the root, `.` and `..`, which have no name to create.
+    fn split_parent(path: &str) -> Result<(Vec<Component<'_>>, &str), FsError> {
+        let mut parts = Self::split_path(path);
+        match parts.pop() {
+            Some(Component::Normal(name)) => Ok((parts, name)),
+            _ => Err(FsError::InvalidPath),
+        }
+    }
+}
+
+impl FileSystem for RamFs {
+    fn name(&self) -> &'static str {
+        "ramfs"
+    }
+
+    fn read(&self, path: &str) -> Result<Vec<u8>, FsError> {
+        let parts = Self::split_path(path);
+        let __guard__ = self.inodes.read()?;
+        let ino = __guard__.lookup(&parts)?;
+        let __inode__ = __guard__.get(ino);
+
+        match &__inode__.data {
+            InodeData::File(__data__) => {
+                let data = copy_bytes(__data__)?;
+                __inode__.touch_accessed();
+                Ok(data)
             }
+            _ => Err(FsError::IsADirectory),
         }
-        Ok(__node__)
     }
 
-    pub(crate) fn read(&self, path: &str) -> Result<Vec<u8>, String> {
+    fn read_at(&self, path: &str, offset: u64, buf: &mut [u8]) -> Result<usize, FsError> {
         let parts = Self::split_path(path);
-        let mut __guard__ = self.root.lock();
-        let mut __node__: &mut Node = &mut *__guard__;
-
-        for part in parts {
-            __node__ = match __node__ {
-                Node::Directory(__children__) => __children__
-                    .get_mut(part)
-                    .ok_or_else(|| format!("Path not found: {}", part))?,
-                _ => return Err("Not a directory".to_string()),
-            };
+        let __guard__ = self.inodes.read()?;
+        let ino = __guard__.lookup(&parts)?;
+        let __inode__ = __guard__.get(ino);
+
+        match &__inode__.data {
+            InodeData::File(__data__) => {
+                let start = (offset as usize).min(__data__.len());
+                let count = buf.len().min(__data__.len() - start);
+                buf[..count].copy_from_slice(&__data__[start..start + count]);
+                __inode__.touch_accessed();
+                Ok(count)
+            }
+            _ => Err(FsError::IsADirectory),
         }
+    }
+
+    fn write_at(&self, path: &str, offset: u64, data: &[u8]) -> Result<usize, FsError> {
+        let parts = Self::split_path(path);
+        let mut __guard__ = self.write_inodes()?;
+        let ino = __guard__.lookup(&parts)?;
+        let old_len = __guard__.file_len(ino)?;
+        let end = offset.checked_add(data.len() as u64).ok_or(FsError::InvalidArgument)?;
+        let new_len = old_len.max(end);
+        let shared = __guard__.shared_contents(ino)?;
+        __guard__.check_space(old_len, new_len + shared)?;
+        let __inode__ = __guard__.get_mut(ino)?;
+
+        if let InodeData::File(__data__) = &mut __inode__.data {
+            let __data__ = unshare(__data__)?;
+            let start = offset as usize;
+            grow_zeroed(__data__, start + data.len())?;
+            __data__[start..start + data.len()].copy_from_slice(data);
+        }
+        __inode__.touch_modified();
+        __guard__.account(old_len, new_len);
+        __guard__.snapshot_bytes += shared;
+        Ok(data.len())
+    }
+
+    fn truncate(&self, path: &str, len: u64) -> Result<(), FsError> {
+        let parts = Self::split_path(path);
+        let mut __guard__ = self.write_inodes()?;
+        let ino = __guard__.lookup(&parts)?;
+        let old_len = __guard__.file_len(ino)?;
+        let shared = __guard__.shared_contents(ino)?;
+        __guard__.check_space(old_len, len + shared)?;
+        let __inode__ = __guard__.get_mut(ino)?;
 
-        match __node__ {
-            Node::File(__data__) => Ok(__data__.clone()),
-            _ => Err("Path is not a file".to_string()),
+        if let InodeData::File(__data__) = &mut __inode__.data {
+            let __data__ = unshare(__data__)?;
+            grow_zeroed(__data__, len as usize)?;
+            __data__.truncate(len as usize);
+            __data__.shrink_to_fit();
         }
+        __inode__.touch_modified();
+        __guard__.account(old_len, len);
+        __guard__.snapshot_bytes += shared;
+        Ok(())
     }
 
-    pub(crate) fn write(&self, path: &str, data: &[u8]) -> Result<(), String> {
+    fn write(&self, path: &str, data: &[u8]) -> Result<(), FsError> {
         let parts = Self::split_path(path);
-        let (dirs, file) = parts.split_at(parts.len() - 1);
-        let mut __guard__ = self.root.lock();
-        let __parent__ = Self::traverse_mut(&mut __guard__, dirs)?;
+        let (dirs, file) = Self::split_parent(path)?;
+        let mut __guard__ = self.write_inodes()?;
+        let parent = __guard__.lookup_parent(&dirs)?;
+
+        // Writing through a symlink replaces the target's contents
+        let existing = if __guard__.children(parent).contains_key(file) {
+            Some(__guard__.lookup(&parts)?)
+        } else {
+            None
+        };
+        let (old_len, shared) = match existing {
+            Some(ino) => (__guard__.file_len(ino)?, __guard__.shared_contents(ino)?),
+            None => (0, 0),
+        };
+        // Check before copying, the copy itself could exhaust the heap
+        let new_len = data.len() as u64;
+        __guard__.check_space(old_len, new_len + shared)?;
+        let contents = copy_bytes(data)?;
 
-        match __parent__ {
-            Node::Directory(__children__) => {
-                __children__.insert(file[0].to_string(), Node::File(data.to_vec()));
-                Ok(())
+        match existing {
+            Some(ino) => {
+                let __inode__ = __guard__.get_mut(ino)?;
+                __inode__.data = InodeData::File(Arc::new(contents));
+                __inode__.touch_modified();
+                __guard__.account(old_len, new_len);
+                __guard__.snapshot_bytes += shared;
+            }
+            None => {
+                __guard__.insert_child(parent, file, InodeData::File(Arc::new(contents)))?;
             }
-            _ => Err("Parent is not a directory".to_string()),
         }
+        Ok(())
     }
 
-    pub(crate) fn create_dir(&self, path: &str) -> Result<(), String> {
+    fn touch(&self, path: &str) -> Result<(), FsError> {
         let parts = Self::split_path(path);
-        let (dirs, new) = parts.split_at(parts.len() - 1);
-        let mut __guard__ = self.root.lock();
-        let __parent__ = Self::traverse_mut(&mut __guard__, dirs)?;
+        let (dirs, file) = Self::split_parent(path)?;
+        let mut __guard__ = self.write_inodes()?;
+        let parent = __guard__.lookup_parent(&dirs)?;
 
-        match __parent__ {
-            Node::Directory(__children__) => {
-                __children__.insert(
-                    new[0].to_string(),
-                    Node::Directory(BTreeMap::new()),
-                );
-                Ok(())
+        match __guard__.children(parent).get(file).copied() {
+            Some(_) => {
+                let ino = __guard__.lookup(&parts)?;
+                __guard__.get_mut(ino)?.touch_modified();
+            }
+            None => {
+                __guard__.insert_child(parent, file, InodeData::File(Arc::new(Vec::new())))?;
             }
-            _ => Err("Parent is not a directory".to_string()),
         }
+        Ok(())
     }
 
-    pub(crate) fn list_dir(&self, path: &str) -> Result<Vec<String>, String> {
+    fn create_dir(&self, path: &str) -> Result<(), FsError> {
+        let (dirs, new) = Self::split_parent(path)?;
+        let mut __guard__ = self.write_inodes()?;
+        let parent = __guard__.lookup_parent(&dirs)?;
+
+        if __guard__.children(parent).contains_key(new) {
+            return Err(FsError::AlreadyExists);
+        }
+        __guard__.insert_child(parent, new, InodeData::Directory(BTreeMap::new()))?;
+        Ok(())
+    }
+
+    fn remove_file(&self, path: &str) -> Result<(), FsError> {
+        let (dirs, name) = Self::split_parent(path)?;
+        let mut __guard__ = self.write_inodes()?;
+        let parent = __guard__.lookup_parent(&dirs)?;
+        let ino = *__guard__.children(parent).get(name).ok_or(FsError::NotFound)?;
+
+        if __guard__.get(ino).meta.kind == FileType::Directory {
+            return Err(FsError::IsADirectory);
+        }
+        __guard__.unlink(parent, name)
+    }
+
+    fn remove_dir(&self, path: &str) -> Result<(), FsError> {
+        let (dirs, name) = Self::split_parent(path)?;
+        let mut __guard__ = self.write_inodes()?;
+        let parent = __guard__.lookup_parent(&dirs)?;
+        let ino = *__guard__.children(parent).get(name).ok_or(FsError::NotFound)?;
+
+        if __guard__.get(ino).meta.kind != FileType::Directory {
+            return Err(FsError::NotADirectory);
+        }
+        if !__guard__.is_empty_dir(ino) {
+            return Err(FsError::DirectoryNotEmpty);
+        }
+        __guard__.unlink(parent, name)
+    }
+
+    fn rename(&self, from: &str, to: &str) -> Result<(), FsError> {
+        let (from_dirs, from_name) = Self::split_parent(from)?;
+        let (to_dirs, to_name) = Self::split_parent(to)?;
+        let mut __guard__ = self.write_inodes()?;
+
+        let from_parent = __guard__.lookup_parent(&from_dirs)?;
+        let ino = *__guard__.children(from_parent).get(from_name).ok_or(FsError::NotFound)?;
+        let is_dir = __guard__.get(ino).meta.kind == FileType::Directory;
+
+        let to_parent = __guard__.lookup_parent(&to_dirs)?;
+        if is_dir && __guard__.dir_contains(ino, to_parent) {
+            return Err(FsError::InvalidArgument);
+        }
+        __guard__.check_space(entry_cost(from_name), entry_cost(to_name))?;
+        // Copy both parents away from snapshots before anything changes
+        __guard__.get_mut(from_parent)?;
+        __guard__.get_mut(to_parent)?;
+
+        if let Some(existing) = __guard__.children(to_parent).get(to_name).copied() {
+            if existing == ino {
+                return Ok(());
+            }
+            match (is_dir, __guard__.get(existing).meta.kind) {
+                (false, FileType::Directory) => return Err(FsError::IsADirectory),
+                (true, FileType::File | FileType::Symlink) => return Err(FsError::NotADirectory),
+                (true, _) if !__guard__.is_empty_dir(existing) => {
+                    return Err(FsError::DirectoryNotEmpty);
+                }
+                _ => __guard__.unlink(to_parent, to_name)?,
+            }
+        }
+
+        __guard__.children_mut(from_parent)?.remove(from_name);
+        __guard__.children_mut(to_parent)?.insert(to_name.to_string(), ino);
+        __guard__.account(entry_cost(from_name), entry_cost(to_name));
+        if is_dir {
+            __guard__.get_mut(from_parent)?.meta.nlink -= 1;
+            __guard__.get_mut(to_parent)?.meta.nlink += 1;
+        }
+        __guard__.get_mut(from_parent)?.touch_modified();
+        __guard__.get_mut(to_parent)?.touch_modified();
+        Ok(())
+    }
+
+    fn list_dir(&self, path: &str) -> Result<Vec<String>, FsError> {
         let parts = Self::split_path(path);
-        let mut __guard__ = self.root.lock();
-        let mut __node__: &mut Node = &mut *__guard__;
-
-        for part in parts {
-            __node__ = match __node__ {
-                Node::Directory(__children__) => __children__
-                    .get_mut(part)
-                    .ok_or_else(|| format!("Path not found: {}", part))?,
-                _ => return Err("Not a directory".to_string()),
-            };
+        let __guard__ = self.inodes.read()?;
+        let ino = __guard__.lookup(&parts)?;
+        let __inode__ = __guard__.get(ino);
+
+        match &__inode__.data {
+            InodeData::Directory(__children__) => {
+                let names = __children__.keys().cloned().collect();
+                __inode__.touch_accessed();
+                Ok(names)
+            }
+            _ => Err(FsError::NotADirectory),
         }
+    }
+
+    fn stat(&self, path: &str) -> Result<Metadata, FsError> {
+        let parts = Self::split_path(path);
+        let __guard__ = self.inodes.read()?;
+        let ino = __guard__.lookup(&parts)?;
+        Ok(__guard__.get(ino).metadata())
+    }
 
-        match __node__ {
-            Node::Directory(__children__) => Ok(__children__.keys().cloned().collect()),
-            _ => Err("Path is not a directory".to_string()),
+    fn statfs(&self) -> Result<FsStats, FsError> {
+        let __guard__ = self.inodes.read()?;
+        Ok(FsStats {
+            total_bytes: __guard__.limits.max_bytes,
+            used_bytes: __guard__.used_bytes + __guard__.snapshot_bytes,
+            total_inodes: __guard__.limits.max_inodes,
+            used_inodes: __guard__.table.len() as u64,
+        })
+    }
+
+    fn symlink_metadata(&self, path: &str) -> Result<Metadata, FsError> {
+        let parts = Self::split_path(path);
+        let __guard__ = self.inodes.read()?;
+        let ino = __guard__.lookup_nofollow(&parts)?;
+        Ok(__guard__.get(ino).metadata())
+    }
+
+    fn symlink(&self, target: &str, link: &str) -> Result<(), FsError> {
+        if target.is_empty() {
+            return Err(FsError::InvalidPath);
+        }
+        let (dirs, name) = Self::split_parent(link)?;
+        let mut __guard__ = self.write_inodes()?;
+        let parent = __guard__.lookup_parent(&dirs)?;
+
+        if __guard__.children(parent).contains_key(name) {
+            return Err(FsError::AlreadyExists);
         }
+        __guard__.insert_child(parent, name, InodeData::Symlink(target.to_string()))?;
+        Ok(())
     }
-}
\ No newline at end of file
+
+    fn link(&self, existing: &str, new: &str) -> Result<(), FsError> {
+        let existing_parts = Self::split_path(existing);
+        let (dirs, name) = Self::split_parent(new)?;
+        let mut __guard__ = self.write_inodes()?;
+        let ino = __guard__.lookup_nofollow(&existing_parts)?;
+        if __guard__.get(ino).meta.kind == FileType::Directory {
+            return Err(FsError::IsADirectory);
+        }
+
+        let parent = __guard__.lookup_parent(&dirs)?;
+        if __guard__.children(parent).contains_key(name) {
+            return Err(FsError::AlreadyExists);
+        }
+        // Copy both inodes away from snapshots before linking
+        __guard__.get_mut(ino)?;
+        __guard__.get_mut(parent)?;
+        __guard__.check_space(0, entry_cost(name))?;
+        __guard__.children_mut(parent)?.insert(name.to_string(), ino);
+        __guard__.account(0, entry_cost(name));
+        __guard__.get_mut(ino)?.meta.nlink += 1;
+        __guard__.get_mut(parent)?.touch_modified();
+        Ok(())
+    }
+
+    fn read_link(&self, path: &str) -> Result<String, FsError> {
+        let parts = Self::split_path(path);
+        let __guard__ = self.inodes.read()?;
+        let ino = __guard__.lookup_nofollow(&parts)?;
+        match &__guard__.get(ino).data {
+            InodeData::Symlink(__target__) => Ok(__target__.clone()),
+            _ => Err(FsError::InvalidArgument),
+        }
+    }
+
+    fn snapshot(&self, name: &str) -> Result<(), FsError> {
+        Self::check_snapshot_name(name)?;
+        if self.read_only {
+            return Err(FsError::ReadOnly);
+        }
+        let mut __guard__ = self.write_inodes()?;
+        __guard__.check_space(0, __guard__.snapshot_cost(name))?;
+        let frozen = RamFs {
+            inodes: FsLock::new(__guard__.share()?),
+            snapshots: FsLock::new(BTreeMap::new()),
+            read_only: true,
+        };
+        self.snapshots.write()?.insert(copy_str(name)?, Arc::new(frozen));
+        // An older snapshot of that name may have been the last to hold something
+        self.recount_snapshot_bytes(&mut __guard__)
+    }
+
+    fn restore(&self, name: &str) -> Result<Vec<Event>, FsError> {
+        let frozen = self.open_snapshot_ramfs(name)?;
+        let mut __guard__ = self.write_inodes()?;
+        let saved = {
+            let __frozen__ = frozen.inodes.read()?;
+            // Both tables exist until the discarded one is dropped
+            __guard__.check_space(0, __frozen__.table.len() as u64 * TABLE_ENTRY_COST)?;
+            __frozen__.share()?
+        };
+        // Never hand out a number again that the discarded tree used
+        let next_ino = __guard__.next_ino.max(saved.next_ino);
+        let limits = __guard__.limits;
+        let discarded = core::mem::replace(&mut *__guard__, Inodes {
+            next_ino,
+            limits,
+            ..saved
+        });
+
+        let mut changes = Vec::new();
+        discarded.changes(&__guard__, ROOT_INO, "/", &mut changes);
+        drop(discarded);
+        self.recount_snapshot_bytes(&mut __guard__)?;
+        Ok(changes)
+    }
+
+    fn remove_snapshot(&self, name: &str) -> Result<(), FsError> {
+        let mut __guard__ = self.write_inodes()?;
+        self.snapshots.write()?.remove(name).ok_or(FsError::NotFound)?;
+        self.recount_snapshot_bytes(&mut __guard__)
+    }
+
+    fn snapshots(&self) -> Result<Vec<String>, FsError> {
+        Ok(self.snapshots.read()?.keys().cloned().collect())
+    }
+
+    fn open_snapshot(&self, name: &str) -> Result<Arc<dyn FileSystem>, FsError> {
+        Ok(self.open_snapshot_ramfs(name)?)
+    }
+}
+
+#[test_case]
+fn test_write_then_read() {
+    let fs = RamFs::new();
+    fs.create_dir("/docs").unwrap();
+    fs.write("/docs/a.txt", b"abc").unwrap();
+    assert_eq!(fs.read("/docs/a.txt").unwrap(), b"abc");
+    assert_eq!(fs.list_dir("/docs").unwrap(), ["a.txt"]);
+}
+
+#[test_case]
+fn test_error_kinds() {
+    let fs = RamFs::new();
+    fs.create_dir("/docs").unwrap();
+    fs.write("/file", b"").unwrap();
+    assert_eq!(fs.create_dir("/docs"), Err(FsError::AlreadyExists));
+    assert_eq!(fs.create_dir("/missing/sub"), Err(FsError::NotFound));
+    assert_eq!(fs.create_dir("/file/sub"), Err(FsError::NotADirectory));
+    assert_eq!(fs.read("/docs"), Err(FsError::IsADirectory));
+    assert_eq!(fs.write("/docs", b"x"), Err(FsError::IsADirectory));
+    assert_eq!(fs.list_dir("/file"), Err(FsError::NotADirectory));
+    assert_eq!(fs.create_dir("/"), Err(FsError::InvalidPath));
+    assert_eq!(fs.create_dir("/docs/.."), Err(FsError::InvalidPath));
+    assert_eq!(fs.read("/docs/./../file").unwrap(), b"");
+}
+
+#[test_case]
+fn test_stat() {
+    let fs = RamFs::new();
+    fs.create_dir("/docs").unwrap();
+    fs.write("/docs/a.txt", b"hello").unwrap();
+    let file = fs.stat("/docs/a.txt").unwrap();
+    assert_eq!(file.kind, FileType::File);
+    assert_eq!(file.size, 5);
+    assert_eq!(file.nlink, 1);
+    assert_eq!(file.mode, FILE_MODE);
+
+    let ino = file.ino;
+    fs.write("/docs/a.txt", b"hi").unwrap();
+    fs.touch("/docs/a.txt").unwrap();
+    let file = fs.stat("/docs/a.txt").unwrap();
+    assert_eq!(file.ino, ino);
+    assert_eq!(file.size, 2);
+
+    assert_eq!(fs.stat("/").unwrap().nlink, 3);
+    assert_eq!(fs.stat("/docs").unwrap().size, 1);
+}
+
+#[test_case]
+fn test_partial_io() {
+    let fs = RamFs::new();
+    fs.write("/log", b"hello").unwrap();
+    assert_eq!(fs.write_at("/log", 5, b" world").unwrap(), 6);
+    assert_eq!(fs.write_at("/log", 0, b"J").unwrap(), 1);
+
+    let mut buf = [0u8; 4];
+    assert_eq!(fs.read_at("/log", 7, &mut buf).unwrap(), 4);
+    assert_eq!(&buf, b"orld");
+    assert_eq!(fs.read_at("/log", 20, &mut buf).unwrap(), 0);
+
+    fs.truncate("/log", 5).unwrap();
+    assert_eq!(fs.read("/log").unwrap(), b"Jello");
+    fs.write_at("/log", 7, b"!").unwrap();
+    assert_eq!(fs.read("/log").unwrap(), b"Jello\0\0!");
+}
+
+#[test_case]
+fn test_remove_and_rename() {
+    let fs = RamFs::new();
+    fs.create_dir("/a").unwrap();
+    fs.create_dir("/a/b").unwrap();
+    fs.write("/a/f", b"data").unwrap();
+
+    assert_eq!(fs.remove_dir("/a"), Err(FsError::DirectoryNotEmpty));
+    assert_eq!(fs.remove_file("/a/b"), Err(FsError::IsADirectory));
+    assert_eq!(fs.remove_dir("/a/f"), Err(FsError::NotADirectory));
+
+    // Moves across directories keep the inode
+    let ino = fs.stat("/a/f").unwrap().ino;
+    fs.rename("/a/f", "/g").unwrap();
+    assert_eq!(fs.stat("/g").unwrap().ino, ino);
+    assert_eq!(fs.stat("/a/f"), Err(FsError::NotFound));
+
+    assert_eq!(fs.rename("/a", "/a/b/c"), Err(FsError::InvalidArgument));
+    assert_eq!(fs.rename("/g", "/a/b"), Err(FsError::IsADirectory));
+    fs.write("/a/b/x", b"").unwrap();
+    fs.create_dir("/c").unwrap();
+    assert_eq!(fs.rename("/c", "/a/b"), Err(FsError::DirectoryNotEmpty));
+    fs.rename("/a/b", "/c").unwrap();
+    assert_eq!(fs.list_dir("/c").unwrap(), ["x"]);
+    assert_eq!(fs.stat("/").unwrap().nlink, 4);
+
+    fs.remove_file("/c/x").unwrap();
+    fs.remove_dir("/c").unwrap();
+    fs.remove_file("/g").unwrap();
+    assert_eq!(fs.list_dir("/").unwrap(), ["a"]);
+    assert_eq!(fs.inodes.read().unwrap().table.len(), 2);
+}
+
+#[test_case]
+fn test_links() {
+    let fs = RamFs::new();
+    fs.create_dir("/bundle").unwrap();
+    fs.create_dir("/bundle/bin").unwrap();
+    fs.write("/bundle/bin/tool", b"#!").unwrap();
+    fs.symlink("/bundle/bin", "/bin").unwrap();
+    fs.symlink("../bundle/bin/tool", "/bundle/alias").unwrap();
+
+    assert_eq!(fs.read("/bin/tool").unwrap(), b"#!");
+    assert_eq!(fs.read("/bundle/alias").unwrap(), b"#!");
+    assert_eq!(fs.read_link("/bin").unwrap(), "/bundle/bin");
+    assert_eq!(fs.stat("/bin").unwrap().kind, FileType::Directory);
+    assert_eq!(fs.symlink_metadata("/bin").unwrap().kind, FileType::Symlink);
+
+    fs.symlink("/loop_b", "/loop_a").unwrap();
+    fs.symlink("/loop_a", "/loop_b").unwrap();
+    assert_eq!(fs.read("/loop_a"), Err(FsError::TooManySymlinks));
+    fs.remove_file("/loop_a").unwrap();
+    assert_eq!(fs.read("/loop_b"), Err(FsError::NotFound));
+
+    fs.link("/bundle/bin/tool", "/hard").unwrap();
+    let meta = fs.stat("/hard").unwrap();
+    assert_eq!(meta.nlink, 2);
+    assert_eq!(meta.ino, fs.stat("/bin/tool").unwrap().ino);
+    fs.write("/hard", b"new").unwrap();
+    assert_eq!(fs.read("/bin/tool").unwrap(), b"new");
+    fs.remove_file("/bin/tool").unwrap();
+    assert_eq!(fs.read("/hard").unwrap(), b"new");
+    assert_eq!(fs.stat("/hard").unwrap().nlink, 1);
+    assert_eq!(fs.link("/bundle", "/dir_link"), Err(FsError::IsADirectory));
+}
+
+#[test_case]
+fn test_locking() {
+    use super::lock::without_blocking;
+
+    let fs = RamFs::new();
+    fs.write("/file", b"data").unwrap();
+    let reader = fs.inodes.read().unwrap();
+    // Readers share the lock, a writer has to wait for them
+    assert_eq!(fs.read("/file").unwrap(), b"data");
+    assert_eq!(without_blocking(|| fs.write("/file", b"")), Err(FsError::WouldBlock));
+    drop(reader);
+    without_blocking(|| fs.write("/file", b"")).unwrap();
+}
+
+#[test_case]
+fn test_limits() {
+    // Room for the root, two more inodes named with one letter and 10 bytes
+    let overhead = INODE_COST + entry_cost("a");
+    let fs = RamFs::with_limits(RamFsLimits {
+        max_bytes: INODE_COST + 2 * overhead + 10,
+        max_inodes: 3,
+    });
+    fs.create_dir("/d").unwrap();
+    fs.write("/a", b"123456").unwrap();
+    assert_eq!(fs.write("/b", b"123456"), Err(FsError::NoSpace));
+    assert_eq!(fs.write_at("/a", 8, b"1234"), Err(FsError::NoSpace));
+    assert_eq!(fs.truncate("/a", 11), Err(FsError::NoSpace));
+    fs.write("/a", b"1234567890").unwrap();
+    assert_eq!(fs.statfs().unwrap().used_bytes, INODE_COST + 2 * overhead + 10);
+
+    assert_eq!(fs.touch("/e"), Err(FsError::NoSpace));
+    fs.remove_file("/a").unwrap();
+    fs.touch("/e").unwrap();
+
+    let stats = fs.statfs().unwrap();
+    assert_eq!((stats.used_bytes, stats.used_inodes), (INODE_COST + 2 * overhead, 3));
+
+    // Names count too
+    assert_eq!(fs.rename("/e", "/a_name_longer_than_ten_bytes"), Err(FsError::NoSpace));
+    fs.rename("/e", "/0123456789e").unwrap();
+    assert_eq!(fs.statfs().unwrap().used_bytes, INODE_COST + 2 * overhead + 10);
+    assert_eq!(fs.link("/0123456789e", "/f"), Err(FsError::NoSpace));
+}
+
+#[test_case]
+fn test_snapshots() {
+    let fs = RamFs::new();
+    fs.create_dir("/etc").unwrap();
+    fs.write("/etc/motd", b"hello").unwrap();
+    fs.write("/big", &[7u8; 64]).unwrap();
+    fs.snapshot("clean").unwrap();
+
+    fs.write_at("/etc/motd", 0, b"J").unwrap();
+    fs.remove_file("/big").unwrap();
+    fs.write("/new", b"x").unwrap();
+
+    // The snapshot still sees the old tree and refuses changes
+    let frozen = fs.open_snapshot("clean").unwrap();
+    assert_eq!(frozen.read("/etc/motd").unwrap(), b"hello");
+    assert_eq!(frozen.read("/big").unwrap().len(), 64);
+    assert_eq!(frozen.stat("/new"), Err(FsError::NotFound));
+    assert_eq!(frozen.write("/etc/motd", b""), Err(FsError::ReadOnly));
+    assert_eq!(fs.snapshots().unwrap(), ["clean"]);
+
+    // Unchanged inodes are shared, changed ones are copies
+    {
+        let live = fs.inodes.read().unwrap();
+        let saved = fs.open_snapshot_ramfs("clean").unwrap();
+        let saved = saved.inodes.read().unwrap();
+        let etc = live.lookup(&RamFs::split_path("/etc")).unwrap();
+        let motd = live.lookup(&RamFs::split_path("/etc/motd")).unwrap();
+        assert!(Arc::ptr_eq(&live.table[&etc], &saved.table[&etc]));
+        assert!(!Arc::ptr_eq(&live.table[&motd], &saved.table[&motd]));
+    }
+
+    let changes = fs.restore("clean").unwrap();
+    assert_eq!(changes, [
+        Event::Modified("/etc/motd".to_string()),
+        Event::Removed("/new".to_string()),
+        Event::Created("/big".to_string()),
+    ]);
+    assert_eq!(fs.read("/etc/motd").unwrap(), b"hello");
+    assert_eq!(fs.read("/big").unwrap().len(), 64);
+    assert_eq!(fs.stat("/new"), Err(FsError::NotFound));
+    let overhead = |name: &str| INODE_COST + entry_cost(name);
+    let used = INODE_COST + overhead("etc") + overhead("motd") + overhead("big") + 69;
+    let snapshot = SNAPSHOT_COST + "clean".len() as u64 + 4 * TABLE_ENTRY_COST;
+    assert_eq!(fs.statfs().unwrap().used_bytes, used + snapshot);
+    // Restoring leaves the snapshot usable for the next reset
+    fs.write("/etc/motd", b"again").unwrap();
+    fs.restore("clean").unwrap();
+    assert_eq!(fs.read("/etc/motd").unwrap(), b"hello");
+
+    assert_eq!(fs.snapshot("a/b"), Err(FsError::InvalidPath));
+    assert_eq!(fs.snapshot(".."), Err(FsError::InvalidPath));
+    fs.remove_snapshot("clean").unwrap();
+    assert_eq!(fs.restore("clean"), Err(FsError::NotFound));
+}
+
+#[test_case]
+fn test_snapshot_charges() {
+    let fs = RamFs::new();
+    fs.create_dir("/d").unwrap();
+    fs.write("/d/f", &[1; 100]).unwrap();
+    let used = fs.statfs().unwrap().used_bytes;
+    fs.snapshot("s").unwrap();
+    // The snapshot's own table is charged right away
+    let snapshot = SNAPSHOT_COST + 1 + 3 * TABLE_ENTRY_COST;
+    let used = used + snapshot;
+    assert_eq!(fs.statfs().unwrap().used_bytes, used);
+
+    // Changes copy the inode and contents, the snapshot keeps the originals
+    fs.write_at("/d/f", 0, b"x").unwrap();
+    let used = used + INODE_COST + 100;
+    assert_eq!(fs.statfs().unwrap().used_bytes, used);
+    fs.touch("/d/g").unwrap();
+    let used = used + INODE_COST + entry_cost("f") + INODE_COST + entry_cost("g");
+    assert_eq!(fs.statfs().unwrap().used_bytes, used);
+    // What only the snapshot holds stays charged until it is dropped
+    fs.remove_file("/d/g").unwrap();
+    fs.remove_file("/d/f").unwrap();
+    let used = used - (INODE_COST + entry_cost("g")) - (INODE_COST + entry_cost("f") + 100);
+    assert_eq!(fs.statfs().unwrap().used_bytes, used);
+    fs.remove_snapshot("s").unwrap();
+    assert_eq!(fs.statfs().unwrap().used_bytes, 2 * INODE_COST + entry_cost("d"));
+
+    // Copies that do not fit fail instead of exhausting the heap
+    let tree = 3 * INODE_COST + entry_cost("d") + entry_cost("f") + 100;
+    let fs = RamFs::with_limits(RamFsLimits {
+        max_bytes: tree + snapshot + INODE_COST + 50,
+        max_inodes: 16,
+    });
+    fs.create_dir("/d").unwrap();
+    fs.write("/d/f", &[1; 100]).unwrap();
+    fs.snapshot("s").unwrap();
+    assert_eq!(fs.write_at("/d/f", 0, b"x"), Err(FsError::NoSpace));
+    assert_eq!(fs.write("/d/f", &[2; 100]), Err(FsError::NoSpace));
+    assert_eq!(fs.read("/d/f").unwrap(), [1; 100]);
+    fs.remove_snapshot("s").unwrap();
+    fs.write_at("/d/f", 0, b"x").unwrap();
+}
+
+#[test_case]
+fn test_snapshot_limit() {
+    let fs = RamFs::with_limits(RamFsLimits {
+        max_bytes: 4096,
+        max_inodes: 64,
+    });
+    for i in 0..8 {
+        fs.write(&format!("/f{i}"), b"x").unwrap();
+    }
+
+    // Every snapshot copies the table, so they run out of room eventually
+    let mut taken = 0;
+    let error = loop {
+        assert!(taken < 100, "snapshots are not charged");
+        match fs.snapshot(&format!("s{taken}")) {
+            Ok(()) => taken += 1,
+            Err(e) => break e,
+        }
+    };
+    assert_eq!(error, FsError::NoSpace);
+    assert!(taken > 0);
+    let stats = fs.statfs().unwrap();
+    assert!(stats.used_bytes <= stats.total_bytes);
+
+    fs.remove_snapshot("s0").unwrap();
+    fs.snapshot("s0").unwrap();
+    fs.restore("s0").unwrap();
+}
@@ -0,0 +1,151 @@
+extern crate alloc;
+use alloc::{format, string::String, sync::Arc, vec::Vec};
+use super::{FileSystem, FileType, FsError, Metadata};
+
+enum Target {
+    /// The directory listing every snapshot
+    Root,
+    /// A path inside one snapshot, relative to its root
+    Inside(Arc<dyn FileSystem>, String),
+}
+
+/// Read-only views of the snapshots of another filesystem, mounted at
+/// `/.snapshots` with one directory per snapshot
+pub(crate) struct SnapshotFs {
+    source: Arc<dyn FileSystem>,
+}
+
+impl SnapshotFs {
+    pub(crate) fn new(source: Arc<dyn FileSystem>) -> Self {
+        SnapshotFs { source }
+    }
+
+    fn target(&self, path: &str) -> Result<Target, FsError> {
+        let path = path.trim_start_matches('/');
+        if path.trim_end_matches('/').is_empty() {
+            return Ok(Target::Root);
+        }
+        let (name, rest) = path.split_once('/').unwrap_or((path, ""));
+        let snapshot = self.source.open_snapshot(name)?;
+        Ok(Target::Inside(snapshot, format!("/{}", rest)))
+    }
+
+    /// Checks that `path` exists before refusing to modify it
+    fn read_only(&self, path: &str) -> Result<(), FsError> {
+        match self.target(path)? {
+            Target::Root => Err(FsError::ReadOnly),
+            Target::Inside(fs, rel) => fs.symlink_metadata(&rel).and(Err(FsError::ReadOnly)),
+        }
+    }
+
+    fn root_metadata(&self) -> Result<Metadata, FsError> {
+        let count = self.source.snapshots()?.len() as u64;
+        Ok(Metadata {
+            ino: 1,
+            kind: FileType::Directory,
+            size: count,
+            created: 0,
+            modified: 0,
+            accessed: 0,
+            mode: 0o555,
+            nlink: 2 + count as u32,
+        })
+    }
+}
+
+impl FileSystem for SnapshotFs {
+    fn name(&self) -> &'static str {
+        "snapshotfs"
+    }
+
+    fn read(&self, path: &str) -> Result<Vec<u8>, FsError> {
+        match self.target(path)? {
+            Target::Root => Err(FsError::IsADirectory),
+            Target::Inside(fs, rel) => fs.read(&rel),
+        }
+    }
+
+    fn write(&self, path: &str, _data: &[u8]) -> Result<(), FsError> {
+        self.read_only(path)
+    }
+
+    fn read_at(&self, path: &str, offset: u64, buf: &mut [u8]) -> Result<usize, FsError> {
+        match self.target(path)? {
+            Target::Root => Err(FsError::IsADirectory),
+            Target::Inside(fs, rel) => fs.read_at(&rel, offset, buf),
+        }
+    }
+
+    fn write_at(&self, path: &str, _offset: u64, _data: &[u8]) -> Result<usize, FsError> {
+        self.read_only(path).map(|_| 0)
+    }
+
+    fn truncate(&self, path: &str, _len: u64) -> Result<(), FsError> {
+        self.read_only(path)
+    }
+
+    fn touch(&self, _path: &str) -> Result<(), FsError> {
+        Err(FsError::ReadOnly)
+    }
+
+    fn create_dir(&self, _path: &str) -> Result<(), FsError> {
+        Err(FsError::ReadOnly)
+    }
+
+    fn list_dir(&self, path: &str) -> Result<Vec<String>, FsError> {
+        match self.target(path)? {
+            Target::Root => self.source.snapshots(),
+            Target::Inside(fs, rel) => fs.list_dir(&rel),
+        }
+    }
+
+    fn stat(&self, path: &str) -> Result<Metadata, FsError> {
+        match self.target(path)? {
+            Target::Root => self.root_metadata(),
+            Target::Inside(fs, rel) => fs.stat(&rel),
+        }
+    }
+
+    fn symlink_metadata(&self, path: &str) -> Result<Metadata, FsError> {
+        match self.target(path)? {
+            Target::Root => self.root_metadata(),
+            Target::Inside(fs, rel) => fs.symlink_metadata(&rel),
+        }
+    }
+
+    fn read_link(&self, path: &str) -> Result<String, FsError> {
+        match self.target(path)? {
+            Target::Root => Err(FsError::InvalidArgument),
+            Target::Inside(fs, rel) => fs.read_link(&rel),
+        }
+    }
+
+    fn remove_file(&self, path: &str) -> Result<(), FsError> {
+        self.read_only(path)
+    }
+
+    fn remove_dir(&self, path: &str) -> Result<(), FsError> {
+        self.read_only(path)
+    }
+
+    fn rename(&self, from: &str, _to: &str) -> Result<(), FsError> {
+        self.read_only(from)
+    }
+}
+
+#[test_case]
+fn test_snapshotfs() {
+    let source = super::new_ramfs();
+    source.write("/file", b"old").unwrap();
+    source.snapshot("first").unwrap();
+    source.write("/file", b"new").unwrap();
+
+    let fs = SnapshotFs::new(source);
+    assert_eq!(fs.list_dir("/").unwrap(), ["first"]);
+    assert_eq!(fs.stat("/").unwrap().nlink, 3);
+    assert_eq!(fs.list_dir("/first").unwrap(), ["file"]);
+    assert_eq!(fs.read("/first/file").unwrap(), b"old");
+    assert_eq!(fs.write("/first/file", b"x"), Err(FsError::ReadOnly));
+    assert_eq!(fs.remove_file("/first/missing"), Err(FsError::NotFound));
+    assert_eq!(fs.read("/second/file"), Err(FsError::NotFound));
+}
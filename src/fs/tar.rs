@@ -0,0 +1,536 @@
+extern crate alloc;
+use alloc::{format, string::{String, ToString}, vec::Vec};
+use super::{FileSystem, FileType, FsError};
+
+/// Archives are made of 512 byte blocks, headers take one block each
+pub(crate) const BLOCK_SIZE: usize = 512;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum EntryKind {
+    File,
+    Directory,
+    Symlink,
+    HardLink,
+    /// Device nodes, FIFOs, pax headers and other things RamFs cannot hold
+    Other,
+}
+
+/// A parsed ustar header
+#[derive(Debug)]
+pub(crate) struct Header {
+    /// Absolute path without a trailing slash
+    pub path: String,
+    pub kind: EntryKind,
+    /// Number of data bytes following the header
+    pub size: u64,
+    /// Target of symlinks and hard links
+    pub link: String,
+}
+
+fn field_str(field: &[u8]) -> Result<&str, FsError> {
+    let end = field.iter().position(|&b| b == 0).unwrap_or(field.len());
+    core::str::from_utf8(&field[..end]).map_err(|_| FsError::InvalidData)
+}
+
+fn parse_octal(field: &[u8]) -> Result<u64, FsError> {
+    let mut value: u64 = 0;
+    for &b in field.iter().skip_while(|&&b| b == b' ') {
+        match b {
+            b'0'..=b'7' => value = value * 8 + (b - b'0') as u64,
+            b' ' | 0 => break,
+            _ => return Err(FsError::InvalidData),
+        }
+    }
+    Ok(value)
+}
+
+/// Turns "./a/b/" or "a/b" into "/a/b"
+fn clean_path(path: &str) -> String {
+    let path = path.trim_start_matches("./").trim_matches('/');
+    format!("/{}", path)
+}
+
+/// True if `path` has a `..` component, which could reach outside the
+/// directory an archive is extracted into. Only for paths inside the
+/// archive, symlink targets are stored as they are.
+fn escapes(path: &str) -> bool {
+    path.split('/').any(|component| component == "..")
+}
+
+impl Header {
+    /// Parses one header block, `None` marks the zero block ending the archive
+    pub(crate) fn parse(block: &[u8]) -> Result<Option<Header>, FsError> {
+        if block.len() < BLOCK_SIZE {
+            return Err(FsError::InvalidData);
+        }
+        if block[..BLOCK_SIZE].iter().all(|&b| b == 0) {
+            return Ok(None);
+        }
+
+        let stored = parse_octal(&block[148..156])?;
+        let checksum: u64 = block[..BLOCK_SIZE]
+            .iter()
+            .enumerate()
+            .map(|(i, &b)| if (148..156).contains(&i) { b' ' as u64 } else { b as u64 })
+            .sum();
+        if stored != checksum {
+            return Err(FsError::InvalidData);
+        }
+
+        let name = field_str(&block[0..100])?;
+        let prefix = if &block[257..262] == b"ustar" {
+            field_str(&block[345..500])?
+        } else {
+            ""
+        };
+        let path = if prefix.is_empty() {
+            clean_path(name)
+        } else {
+            clean_path(&format!("{}/{}", prefix, name))
+        };
+
+        let kind = match block[156] {
+            b'0' | 0 | b'7' => EntryKind::File,
+            b'5' => EntryKind::Directory,
+            b'2' => EntryKind::Symlink,
+            b'1' => EntryKind::HardLink,
+            _ => EntryKind::Other,
+        };
+
+        // Hard link targets name another entry of the archive
+        let link = field_str(&block[157..257])?.to_string();
+        if escapes(&path) || (kind == EntryKind::HardLink && escapes(&link)) {
+            return Err(FsError::InvalidPath);
+        }
+
+        Ok(Some(Header {
+            path,
+            kind,
+            size: parse_octal(&block[124..136])?,
+            link,
+        }))
+    }
+}
+
+/// Writes `value` as zero padded octal filling all but the last byte of `field`
+fn put_octal(field: &mut [u8], value: u64) {
+    let digits = field.len() - 1;
+    let text = format!("{:0width$o}", value, width = digits);
+    field[..digits].copy_from_slice(text.as_bytes());
+}
+
+impl Header {
+    /// Builds the ustar header block for this entry, the inverse of `parse`
+    pub(crate) fn to_block(&self, mode: u16) -> Result<[u8; BLOCK_SIZE], FsError> {
+        let path = match self.path.trim_start_matches('/') {
+            "" => "./",
+            path => path,
+        };
+        let (prefix, name) = split_name(path).ok_or(FsError::InvalidPath)?;
+        if self.link.len() > 100 {
+            return Err(FsError::InvalidPath);
+        }
+
+        let mut block = [0u8; BLOCK_SIZE];
+        block[..name.len()].copy_from_slice(name.as_bytes());
+        put_octal(&mut block[100..108], mode as u64);
+        put_octal(&mut block[108..116], 0);
+        put_octal(&mut block[116..124], 0);
+        put_octal(&mut block[124..136], self.size);
+        put_octal(&mut block[136..148], 0);
+        block[156] = match self.kind {
+            EntryKind::File | EntryKind::Other => b'0',
+            EntryKind::HardLink => b'1',
+            EntryKind::Symlink => b'2',
+            EntryKind::Directory => b'5',
+        };
+        block[157..157 + self.link.len()].copy_from_slice(self.link.as_bytes());
+        block[257..263].copy_from_slice(b"ustar\0");
+        block[263..265].copy_from_slice(b"00");
+        block[345..345 + prefix.len()].copy_from_slice(prefix.as_bytes());
+
+        block[148..156].fill(b' ');
+        let checksum: u64 = block.iter().map(|&b| b as u64).sum();
+        put_octal(&mut block[148..155], checksum);
+        Ok(block)
+    }
+}
+
+/// Splits a path into the ustar prefix (155 bytes) and name (100 bytes)
+fn split_name(path: &str) -> Option<(&str, &str)> {
+    if path.len() <= 100 {
+        return Some(("", path));
+    }
+    path.match_indices('/')
+        .map(|(i, _)| (&path[..i], &path[i + 1..]))
+        .find(|(prefix, name)| prefix.len() <= 155 && name.len() <= 100 && !name.is_empty())
+}
+
+/// Number of bytes `size` bytes of data take up including block padding
+pub(crate) fn padded(size: u64) -> usize {
+    (size as usize).div_ceil(BLOCK_SIZE) * BLOCK_SIZE
+}
+
+/// Iterator over the headers and data of an archive held in memory
+pub(crate) struct Entries<'a> {
+    archive: &'a [u8],
+    offset: usize,
+    done: bool,
+}
+
+pub(crate) fn entries(archive: &[u8]) -> Entries<'_> {
+    Entries {
+        archive,
+        offset: 0,
+        done: false,
+    }
+}
+
+impl<'a> Iterator for Entries<'a> {
+    type Item = Result<(Header, &'a [u8]), FsError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.done || self.offset + BLOCK_SIZE > self.archive.len() {
+            return None;
+        }
+
+        let header = match Header::parse(&self.archive[self.offset..]) {
+            Ok(Some(header)) => header,
+            Ok(None) => {
+                self.done = true;
+                return None;
+            }
+            Err(e) => {
+                self.done = true;
+                return Some(Err(e));
+            }
+        };
+
+        let start = self.offset + BLOCK_SIZE;
+        let end = start + header.size as usize;
+        if end > self.archive.len() {
+            self.done = true;
+            return Some(Err(FsError::InvalidData));
+        }
+        self.offset = start + padded(header.size);
+        Some(Ok((header, &self.archive[start..end])))
+    }
+}
+
+/// Creates every missing directory between `root` and `path`. Fails with
+/// `InvalidPath` if one of them is a symlink, whatever lands below it
+/// could end up outside `root`.
+fn create_parents(fs: &dyn FileSystem, root: &str, path: &str) -> Result<(), FsError> {
+    for (i, _) in path.match_indices('/').filter(|&(i, _)| i > root.len()) {
+        let dir = &path[..i];
+        match fs.create_dir(dir) {
+            Ok(()) => {}
+            Err(FsError::AlreadyExists) if fs.symlink_metadata(dir)?.kind == FileType::Symlink => {
+                return Err(FsError::InvalidPath);
+            }
+            Err(FsError::AlreadyExists) => {}
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(())
+}
+
+/// Creates the file, directory or link described by `header` in `fs`,
+/// with `root` the directory the archive is extracted into
+///
+/// What is already there is replaced, or kept if it is the same, so an
+/// archive can be extracted over an earlier copy of itself. Existing
+/// symlinks are replaced rather than written through.
+pub(crate) fn extract(fs: &dyn FileSystem, root: &str, header: &Header, data: &[u8]) -> Result<(), FsError> {
+    let path = header.path.as_str();
+    // The root itself is left as it is
+    if path.len() <= root.len().max(1) {
+        return Ok(());
+    }
+    create_parents(fs, root, path)?;
+
+    let existing = fs.symlink_metadata(path).ok();
+    let is_dir = existing.is_some_and(|meta| meta.is_dir());
+    let is_symlink = existing.is_some_and(|meta| meta.kind == FileType::Symlink);
+    match header.kind {
+        EntryKind::Directory if is_dir => Ok(()),
+        EntryKind::Directory => {
+            if existing.is_some() {
+                fs.remove_file(path)?;
+            }
+            fs.create_dir(path)
+        }
+        EntryKind::File => {
+            if is_symlink {
+                fs.remove_file(path)?;
+            }
+            fs.write(path, data)
+        }
+        EntryKind::Symlink if is_symlink && fs.read_link(path)? == header.link => Ok(()),
+        EntryKind::Symlink => {
+            if existing.is_some() && !is_dir {
+                fs.remove_file(path)?;
+            }
+            fs.symlink(&header.link, path)
+        }
+        EntryKind::HardLink => {
+            let target = clean_path(&header.link);
+            let ino = fs.symlink_metadata(&target)?.ino;
+            match existing {
+                Some(meta) if meta.ino == ino => return Ok(()),
+                Some(meta) if !meta.is_dir() => fs.remove_file(path)?,
+                _ => {}
+            }
+            fs.link(&target, path)
+        }
+        EntryKind::Other => Ok(()),
+    }
+}
+
+/// Extracts a whole in-memory archive into `fs`
+pub(crate) fn unpack(fs: &dyn FileSystem, archive: &[u8]) -> Result<(), FsError> {
+    for entry in entries(archive) {
+        let (header, data) = entry?;
+        extract(fs, "", &header, data)?;
+    }
+    Ok(())
+}
+
+/// Extracts an archive that arrives in pieces of any size, e.g. from a
+/// serial line, without holding all of it in memory
+pub(crate) struct Unpacker {
+    /// Directory of `fs` the archive root is extracted into
+    root: String,
+    header: Vec<u8>,
+    /// Entry whose data is being collected
+    entry: Option<(Header, Vec<u8>)>,
+    /// Padding bytes left before the next header
+    skip: usize,
+    entries: usize,
+    done: bool,
+}
+
+impl Unpacker {
+    pub(crate) fn new(root: &str) -> Self {
+        Unpacker {
+            root: root.trim_end_matches('/').to_string(),
+            header: Vec::new(),
+            entry: None,
+            skip: 0,
+            entries: 0,
+            done: false,
+        }
+    }
+
+    /// True once the zero block ending the archive has been seen
+    pub(crate) fn is_done(&self) -> bool {
+        self.done
+    }
+
+    /// Number of entries extracted so far
+    pub(crate) fn entries(&self) -> usize {
+        self.entries
+    }
+
+    fn rooted(&self, path: &str) -> String {
+        if path == "/" && !self.root.is_empty() {
+            self.root.clone()
+        } else {
+            format!("{}{}", self.root, path)
+        }
+    }
+
+    /// Consumes the next piece of the archive, bytes after its end are ignored
+    pub(crate) fn feed(&mut self, fs: &dyn FileSystem, mut bytes: &[u8]) -> Result<(), FsError> {
+        while !bytes.is_empty() && !self.done {
+            let wanted = if self.skip > 0 {
+                self.skip
+            } else if let Some((header, data)) = &self.entry {
+                header.size as usize - data.len()
+            } else {
+                BLOCK_SIZE - self.header.len()
+            };
+            let (chunk, rest) = bytes.split_at(wanted.min(bytes.len()));
+            bytes = rest;
+
+            if self.skip > 0 {
+                self.skip -= chunk.len();
+            } else if let Some((_, data)) = &mut self.entry {
+                data.extend_from_slice(chunk);
+            } else {
+                self.header.extend_from_slice(chunk);
+                if self.header.len() == BLOCK_SIZE {
+                    let parsed = Header::parse(&self.header)?;
+                    self.header.clear();
+                    match parsed {
+                        Some(header) => {
+                            let mut data = Vec::new();
+                            data.try_reserve_exact(header.size as usize)
+                                .map_err(|_| FsError::NoSpace)?;
+                            self.entry = Some((header, data));
+                        }
+                        None => self.done = true,
+                    }
+                }
+            }
+            self.finish_entry(fs)?;
+        }
+        Ok(())
+    }
+
+    /// Extracts the current entry once all of its data is there
+    fn finish_entry(&mut self, fs: &dyn FileSystem) -> Result<(), FsError> {
+        let complete = self
+            .entry
+            .as_ref()
+            .is_some_and(|(header, data)| data.len() as u64 == header.size);
+        if !complete {
+            return Ok(());
+        }
+
+        let (mut header, data) = self.entry.take().unwrap();
+        self.skip = padded(header.size) - data.len();
+        header.path = self.rooted(&header.path);
+        if header.kind == EntryKind::HardLink {
+            header.link = self.rooted(&clean_path(&header.link));
+        }
+        extract(fs, &self.root, &header, &data)?;
+        self.entries += 1;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+fn test_header(name: &str, typeflag: u8, size: usize, link: &str) -> [u8; BLOCK_SIZE] {
+    let mut block = [0u8; BLOCK_SIZE];
+    block[..name.len()].copy_from_slice(name.as_bytes());
+    let size = format!("{:011o}", size);
+    block[124..135].copy_from_slice(size.as_bytes());
+    block[156] = typeflag;
+    block[157..157 + link.len()].copy_from_slice(link.as_bytes());
+    block[257..263].copy_from_slice(b"ustar\0");
+    block[148..156].copy_from_slice(b"        ");
+    let checksum: u32 = block.iter().map(|&b| b as u32).sum();
+    block[148..155].copy_from_slice(format!("{:06o}\0", checksum).as_bytes());
+    block
+}
+
+#[test_case]
+fn test_unpack() {
+    use alloc::vec::Vec;
+    use super::RamFs;
+
+    let mut archive = Vec::new();
+    archive.extend_from_slice(&test_header("./etc/", b'5', 0, ""));
+    archive.extend_from_slice(&test_header("etc/motd", b'0', 2, ""));
+    archive.extend_from_slice(b"hi");
+    archive.resize(archive.len() + BLOCK_SIZE - 2, 0);
+    archive.extend_from_slice(&test_header("usr/share/motd", b'2', 0, "/etc/motd"));
+    archive.extend_from_slice(&[0u8; 2 * BLOCK_SIZE]);
+
+    let fs = RamFs::new();
+    unpack(&fs, &archive).unwrap();
+    assert_eq!(fs.read("/etc/motd").unwrap(), b"hi");
+    assert_eq!(fs.read("/usr/share/motd").unwrap(), b"hi");
+
+    archive[0] ^= 1;
+    assert_eq!(unpack(&RamFs::new(), &archive), Err(FsError::InvalidData));
+
+    // Nothing may climb out of the directory it is extracted into
+    for block in [
+        test_header("../x", b'0', 0, ""),
+        test_header("etc/../../x", b'5', 0, ""),
+        test_header("x", b'1', 0, "etc/.."),
+    ] {
+        assert_eq!(Header::parse(&block).err(), Some(FsError::InvalidPath));
+    }
+    assert!(Header::parse(&test_header("a..b/..c", b'0', 0, "")).unwrap().is_some());
+    // Symlinks may point anywhere, they are never written through
+    assert!(Header::parse(&test_header("bin", b'2', 0, "../usr/bin")).unwrap().is_some());
+}
+
+#[test_case]
+fn test_unpack_over_existing() {
+    use super::RamFs;
+
+    let header = |path: &str, kind, size, link: &str| {
+        let header = Header { path: path.to_string(), kind, size, link: link.to_string() };
+        header.to_block(0o644).unwrap()
+    };
+    let file = |archive: &mut Vec<u8>, path: &str, data: &[u8]| {
+        archive.extend_from_slice(&header(path, EntryKind::File, data.len() as u64, ""));
+        archive.extend_from_slice(data);
+        archive.resize(archive.len() + padded(data.len() as u64) - data.len(), 0);
+    };
+    let unpack_into = |fs: &RamFs, root: &str, archive: &[u8]| {
+        let mut unpacker = Unpacker::new(root);
+        unpacker.feed(fs, archive).map(|()| unpacker.entries())
+    };
+
+    // Loading the same archive twice leaves the same tree
+    let mut archive = Vec::new();
+    file(&mut archive, "/usr/bin/tool", b"tool");
+    archive.extend_from_slice(&header("/bin", EntryKind::Symlink, 0, "usr/bin"));
+    archive.extend_from_slice(&header("/usr/lib/tool", EntryKind::Symlink, 0, "../bin/tool"));
+    archive.extend_from_slice(&header("/usr/bin/again", EntryKind::HardLink, 0, "/usr/bin/tool"));
+    archive.extend_from_slice(&[0u8; 2 * BLOCK_SIZE]);
+    let fs = RamFs::new();
+    fs.create_dir("/r").unwrap();
+    assert_eq!(unpack_into(&fs, "/r", &archive), Ok(4));
+    assert_eq!(unpack_into(&fs, "/r", &archive), Ok(4));
+    unpack(&fs, &archive).unwrap();
+    unpack(&fs, &archive).unwrap();
+    assert_eq!(fs.read("/r/usr/lib/tool").unwrap(), b"tool");
+    assert_eq!(fs.read_link("/r/bin").unwrap(), "usr/bin");
+    assert_eq!(fs.stat("/r/usr/bin/tool").unwrap().nlink, 2);
+
+    // A symlink in the archive is replaced, not written through
+    let fs = RamFs::new();
+    fs.write("/secret", b"keep").unwrap();
+    fs.create_dir("/r").unwrap();
+    let mut archive = Vec::new();
+    archive.extend_from_slice(&header("/motd", EntryKind::Symlink, 0, "/secret"));
+    file(&mut archive, "/motd", b"hi");
+    archive.extend_from_slice(&[0u8; 2 * BLOCK_SIZE]);
+    assert_eq!(unpack_into(&fs, "/r", &archive), Ok(2));
+    assert_eq!(fs.read("/r/motd").unwrap(), b"hi");
+    assert_eq!(fs.read("/secret").unwrap(), b"keep");
+
+    // Nothing is created below a symlink
+    let mut archive = Vec::new();
+    archive.extend_from_slice(&header("/etc", EntryKind::Symlink, 0, "/"));
+    file(&mut archive, "/etc/secret", b"gone");
+    archive.extend_from_slice(&[0u8; 2 * BLOCK_SIZE]);
+    assert_eq!(unpack_into(&fs, "/r", &archive), Err(FsError::InvalidPath));
+    assert_eq!(fs.read("/secret").unwrap(), b"keep");
+}
+
+#[test_case]
+fn test_unpacker() {
+    use super::RamFs;
+
+    let header = |path: &str, kind, size, link: &str| {
+        let header = Header { path: path.to_string(), kind, size, link: link.to_string() };
+        header.to_block(0o644).unwrap()
+    };
+    let mut archive = Vec::new();
+    archive.extend_from_slice(&header("/", EntryKind::Directory, 0, ""));
+    archive.extend_from_slice(&header("/etc/motd", EntryKind::File, 600, ""));
+    archive.resize(archive.len() + padded(600), b'x');
+    archive.extend_from_slice(&header("/motd", EntryKind::HardLink, 0, "/etc/motd"));
+    let long = format!("/{}/file", "d".repeat(120));
+    archive.extend_from_slice(&header(&long, EntryKind::File, 0, ""));
+    archive.extend_from_slice(&[0u8; 2 * BLOCK_SIZE]);
+
+    let fs = RamFs::new();
+    fs.create_dir("/restore").unwrap();
+    let mut unpacker = Unpacker::new("/restore/");
+    for piece in archive.chunks(100) {
+        unpacker.feed(&fs, piece).unwrap();
+    }
+    assert!(unpacker.is_done());
+    assert_eq!(unpacker.entries(), 4);
+    assert_eq!(fs.read("/restore/etc/motd").unwrap().len(), 600);
+    assert_eq!(fs.stat("/restore/motd").unwrap().nlink, 2);
+    assert!(fs.stat(&format!("/restore{}", long)).is_ok());
+}
@@ -0,0 +1,236 @@
+extern crate alloc;
+use alloc::{format, string::{String, ToString}, sync::Arc, vec::Vec};
+use super::{lock::FsLock, path::Path, Event, FsError, FsStats, Metadata};
+
+/// A filesystem that can be attached to the namespace with `fs::mount`
+///
+/// Paths handed to a filesystem are relative to its mount point but always
+/// start with `/`, so the mount point itself is `/`.
+pub trait FileSystem: Send + Sync {
+    /// Short type name shown by `mount`, e.g. "ramfs"
+    fn name(&self) -> &'static str;
+    fn read(&self, path: &str) -> Result<Vec<u8>, FsError>;
+    fn write(&self, path: &str, data: &[u8]) -> Result<(), FsError>;
+    /// Copies bytes starting at `offset` into `buf`, returns how many were read
+    fn read_at(&self, path: &str, offset: u64, buf: &mut [u8]) -> Result<usize, FsError>;
+    /// Writes `data` at `offset`, growing the file (zero filled) if needed
+    fn write_at(&self, path: &str, offset: u64, data: &[u8]) -> Result<usize, FsError>;
+    /// Cuts or zero extends a file to `len` bytes
+    fn truncate(&self, path: &str, len: u64) -> Result<(), FsError>;
+    /// Creates an empty file, or bumps the timestamps of an existing one
+    fn touch(&self, path: &str) -> Result<(), FsError>;
+    /// Creates a directory, failing with `AlreadyExists` if the name is taken
+    fn create_dir(&self, path: &str) -> Result<(), FsError>;
+    fn list_dir(&self, path: &str) -> Result<Vec<String>, FsError>;
+    fn stat(&self, path: &str) -> Result<Metadata, FsError>;
+    /// Removes a file, directories are refused with `IsADirectory`
+    fn remove_file(&self, path: &str) -> Result<(), FsError>;
+    /// Removes an empty directory
+    fn remove_dir(&self, path: &str) -> Result<(), FsError>;
+    /// Moves `from` to `to`, replacing a file or an empty directory there
+    fn rename(&self, from: &str, to: &str) -> Result<(), FsError>;
+
+    /// Capacity and usage of the whole filesystem
+    fn statfs(&self) -> Result<FsStats, FsError> {
+        Err(FsError::Unsupported)
+    }
+
+    /// Like `stat`, but describes a symlink itself instead of its target
+    fn symlink_metadata(&self, path: &str) -> Result<Metadata, FsError> {
+        self.stat(path)
+    }
+
+    /// Creates a symlink at `link` pointing to `target`
+    fn symlink(&self, _target: &str, _link: &str) -> Result<(), FsError> {
+        Err(FsError::Unsupported)
+    }
+
+    /// Adds a second name `new` for the file at `existing`
+    fn link(&self, _existing: &str, _new: &str) -> Result<(), FsError> {
+        Err(FsError::Unsupported)
+    }
+
+    /// Returns the target a symlink points to
+    fn read_link(&self, _path: &str) -> Result<String, FsError> {
+        Err(FsError::InvalidArgument)
+    }
+
+    /// Saves the current tree as the snapshot `name`, replacing an older
+    /// one of that name. Unchanged data is shared with the live tree.
+    fn snapshot(&self, _name: &str) -> Result<(), FsError> {
+        Err(FsError::Unsupported)
+    }
+
+    /// Rolls the whole tree back to the snapshot `name`, which is kept.
+    /// Returns what that changed, so watchers can be told.
+    fn restore(&self, _name: &str) -> Result<Vec<Event>, FsError> {
+        Err(FsError::Unsupported)
+    }
+
+    fn remove_snapshot(&self, _name: &str) -> Result<(), FsError> {
+        Err(FsError::Unsupported)
+    }
+
+    /// Names of the snapshots taken so far
+    fn snapshots(&self) -> Result<Vec<String>, FsError> {
+        Err(FsError::Unsupported)
+    }
+
+    /// The snapshot `name` as a read-only filesystem of its own
+    fn open_snapshot(&self, _name: &str) -> Result<Arc<dyn FileSystem>, FsError> {
+        Err(FsError::Unsupported)
+    }
+
+    /// Removes a directory and everything below it
+    fn remove_dir_all(&self, path: &str) -> Result<(), FsError> {
+        for name in self.list_dir(path)? {
+            let child = if path.ends_with('/') {
+                format!("{}{}", path, name)
+            } else {
+                format!("{}/{}", path, name)
+            };
+            if self.symlink_metadata(&child)?.is_dir() {
+                self.remove_dir_all(&child)?;
+            } else {
+                self.remove_file(&child)?;
+            }
+        }
+        self.remove_dir(path)
+    }
+}
+
+struct Mount {
+    /// Absolute path without a trailing slash, "/" for the root
+    path: String,
+    fs: Arc<dyn FileSystem>,
+}
+
+/// Entry of the mount table as reported by `fs::mounts`
+#[derive(Debug, Clone)]
+pub struct MountInfo {
+    pub path: String,
+    pub fs_name: &'static str,
+}
+
+static MOUNTS: FsLock<Vec<Mount>> = FsLock::new(Vec::new());
+
+/// Normalizes an absolute path so mount prefixes compare as plain strings
+fn clean_path(path: &str) -> Result<String, FsError> {
+    let path = Path::new(path);
+    if !path.is_absolute() {
+        return Err(FsError::InvalidPath);
+    }
+    Ok(path.canonicalize(Path::new("/")).into_string())
+}
+
+/// Returns the part of `path` below `mount`, or `None` if `mount` is not a prefix
+fn strip_mount<'a>(path: &'a str, mount: &str) -> Option<&'a str> {
+    if mount == "/" {
+        return Some(path);
+    }
+    let rest = path.strip_prefix(mount)?;
+    if rest.is_empty() || rest.starts_with('/') {
+        Some(rest)
+    } else {
+        None
+    }
+}
+
+/// Finds the filesystem responsible for `path` (longest mount prefix wins)
+/// and the path relative to that filesystem's root
+pub(crate) fn resolve(path: &str) -> Result<(Arc<dyn FileSystem>, String), FsError> {
+    let path = clean_path(path)?;
+    let mounts = MOUNTS.read()?;
+    if mounts.is_empty() {
+        return Err(FsError::NotInitialized);
+    }
+
+    let mut best: Option<(&Mount, &str)> = None;
+    for mount in mounts.iter() {
+        if let Some(rest) = strip_mount(&path, &mount.path)
+            && best.is_none_or(|(b, _)| mount.path.len() > b.path.len())
+        {
+            best = Some((mount, rest));
+        }
+    }
+
+    let (mount, rest) = best.ok_or(FsError::NotFound)?;
+    let rel = if rest.is_empty() {
+        "/".to_string()
+    } else {
+        rest.to_string()
+    };
+    Ok((mount.fs.clone(), rel))
+}
+
+pub(crate) fn mount(path: &str, fs: Arc<dyn FileSystem>) -> Result<(), FsError> {
+    let path = clean_path(path)?;
+
+    // Everything but the first root mount needs an existing directory to cover
+    let has_mounts = !MOUNTS.read()?.is_empty();
+    if has_mounts {
+        let (parent, rel) = resolve(&path)?;
+        if !parent.stat(&rel)?.is_dir() {
+            return Err(FsError::NotADirectory);
+        }
+    } else if path != "/" {
+        return Err(FsError::NotInitialized);
+    }
+
+    let mut mounts = MOUNTS.write()?;
+    if mounts.iter().any(|m| m.path == path) {
+        return Err(FsError::Busy);
+    }
+    mounts.push(Mount { path, fs });
+    Ok(())
+}
+
+pub(crate) fn umount(path: &str) -> Result<(), FsError> {
+    let path = clean_path(path)?;
+    if path == "/" {
+        return Err(FsError::Busy);
+    }
+
+    let mut mounts = MOUNTS.write()?;
+    let index = mounts
+        .iter()
+        .position(|m| m.path == path)
+        .ok_or(FsError::NotFound)?;
+
+    // Refuse to hide filesystems mounted below this one
+    let nested = format!("{}/", path);
+    if mounts.iter().any(|m| m.path.starts_with(&nested)) {
+        return Err(FsError::Busy);
+    }
+    mounts.remove(index);
+    Ok(())
+}
+
+/// Whether a filesystem is mounted at `path` or anywhere below it
+pub(crate) fn is_mount_point_within(path: &str) -> Result<bool, FsError> {
+    let path = clean_path(path)?;
+    Ok(MOUNTS
+        .read()?
+        .iter()
+        .any(|m| m.path != "/" && strip_mount(&m.path, &path).is_some()))
+}
+
+pub(crate) fn mounts() -> Result<Vec<MountInfo>, FsError> {
+    Ok(MOUNTS
+        .read()?
+        .iter()
+        .map(|m| MountInfo {
+            path: m.path.clone(),
+            fs_name: m.fs.name(),
+        })
+        .collect())
+}
+
+#[test_case]
+fn test_strip_mount() {
+    assert_eq!(strip_mount("/dev/null", "/"), Some("/dev/null"));
+    assert_eq!(strip_mount("/dev/null", "/dev"), Some("/null"));
+    assert_eq!(strip_mount("/dev", "/dev"), Some(""));
+    assert_eq!(strip_mount("/device", "/dev"), None);
+    assert_eq!(strip_mount("/mnt", "/dev"), None);
+}
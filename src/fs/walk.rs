@@ -0,0 +1,125 @@
+extern crate alloc;
+use alloc::{string::String, vec::Vec};
+use super::{path::Path, FileType, FsError, Metadata};
+
+/// One entry yielded by `fs::walk`
+#[derive(Debug, Clone)]
+pub struct DirEntry {
+    /// Absolute path of the entry
+    pub path: String,
+    /// 0 for the path the walk started at, 1 for its children and so on
+    pub depth: usize,
+    /// Metadata of the entry itself, symlinks are not followed
+    pub metadata: Metadata,
+}
+
+impl DirEntry {
+    pub fn kind(&self) -> FileType {
+        self.metadata.kind
+    }
+
+    /// The last component of the path, "/" for the root
+    pub fn file_name(&self) -> &str {
+        Path::new(&self.path).file_name().unwrap_or("/")
+    }
+}
+
+/// Depth first, pre-order iterator over a directory tree, created by
+/// `fs::walk`
+///
+/// Symlinks are reported but never followed, so loops cannot trap it.
+/// Mounted filesystems below the start are walked like any directory.
+/// A directory that cannot be listed yields one `Err` and is skipped.
+pub struct Walk {
+    /// Paths still to visit with their depth, the next one is on top
+    pending: Vec<(String, usize)>,
+    /// Directory yielded last, listed on the following call
+    descend: Option<(String, usize)>,
+    max_depth: usize,
+}
+
+pub(crate) fn walk(path: &str) -> Walk {
+    let path = if Path::new(path).is_absolute() {
+        Path::new(path).canonicalize(Path::new("/")).into_string()
+    } else {
+        // Fails with `InvalidPath` on the first call to `next`
+        String::from(path)
+    };
+    Walk {
+        pending: alloc::vec![(path, 0)],
+        descend: None,
+        max_depth: usize::MAX,
+    }
+}
+
+impl Walk {
+    /// Stops descending below `depth`, 1 only yields the direct children
+    pub fn max_depth(mut self, depth: usize) -> Self {
+        self.max_depth = depth;
+        self
+    }
+
+    /// Does not descend into the directory yielded last
+    pub fn skip_current_dir(&mut self) {
+        self.descend = None;
+    }
+}
+
+impl Iterator for Walk {
+    type Item = Result<DirEntry, FsError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if let Some((dir, depth)) = self.descend.take() {
+            let names = match super::list_dir(&dir) {
+                Ok(names) => names,
+                Err(e) => return Some(Err(e)),
+            };
+            for name in names.iter().rev() {
+                self.pending.push((Path::new(&dir).join(name).into_string(), depth + 1));
+            }
+        }
+
+        let (path, depth) = self.pending.pop()?;
+        let metadata = match super::symlink_metadata(&path) {
+            Ok(metadata) => metadata,
+            Err(e) => return Some(Err(e)),
+        };
+        if metadata.is_dir() && depth < self.max_depth {
+            self.descend = Some((path.clone(), depth));
+        }
+        Some(Ok(DirEntry { path, depth, metadata }))
+    }
+}
+
+#[test_case]
+fn test_walk() {
+    use alloc::format;
+
+    super::mount_test_root();
+    super::create_dir("/walk").unwrap();
+    super::create_dir("/walk/a").unwrap();
+    super::write("/walk/a/file", b"data").unwrap();
+    super::symlink("/walk", "/walk/a/loop").unwrap();
+    super::write("/walk/b", b"").unwrap();
+
+    let entries: Vec<_> = super::walk("/walk/").map(Result::unwrap).collect();
+    let listed: Vec<_> = entries
+        .iter()
+        .map(|e| format!("{} {} {:?}", e.depth, e.path, e.kind()))
+        .collect();
+    assert_eq!(
+        listed,
+        [
+            "0 /walk Directory",
+            "1 /walk/a Directory",
+            "2 /walk/a/file File",
+            "2 /walk/a/loop Symlink",
+            "1 /walk/b File",
+        ]
+    );
+    assert_eq!(entries[2].metadata.size, 4);
+    assert_eq!(entries[2].file_name(), "file");
+
+    assert_eq!(super::walk("/walk").max_depth(1).count(), 3);
+    assert!(matches!(super::walk("/missing").next(), Some(Err(FsError::NotFound))));
+}
@@ -0,0 +1,167 @@
+extern crate alloc;
+use alloc::{string::String, sync::{Arc, Weak}, vec::Vec};
+use core::{pin::Pin, sync::atomic::{AtomicUsize, Ordering}, task::{Context, Poll}};
+use crossbeam_queue::ArrayQueue;
+use futures_util::{stream::Stream, task::AtomicWaker};
+use super::{lock::FsLock, path::Path, FsError};
+
+/// Events kept per watcher until its stream is polled, older ones are
+/// dropped first
+const EVENT_QUEUE_SIZE: usize = 64;
+
+/// A change reported by `fs::watch`, paths are absolute and canonical
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Event {
+    Created(String),
+    /// Contents, size or timestamps changed
+    Modified(String),
+    Removed(String),
+    Renamed { from: String, to: String },
+}
+
+struct Watch {
+    /// Canonical path, events at or below it are delivered
+    path: String,
+    queue: ArrayQueue<Event>,
+    waker: AtomicWaker,
+}
+
+impl Watch {
+    fn covers(&self, path: &str) -> bool {
+        self.path == "/"
+            || path
+                .strip_prefix(self.path.as_str())
+                .is_some_and(|rest| rest.is_empty() || rest.starts_with('/'))
+    }
+}
+
+static WATCHES: FsLock<Vec<Weak<Watch>>> = FsLock::new(Vec::new());
+
+/// Number of live watches, lets `notify` skip all work when nobody listens
+static WATCH_COUNT: AtomicUsize = AtomicUsize::new(0);
+
+/// Stream of the changes at or below a path, created by `fs::watch`
+pub struct WatchStream {
+    watch: Arc<Watch>,
+}
+
+pub(crate) fn watch(path: &str) -> Result<WatchStream, FsError> {
+    let watch = Arc::new(Watch {
+        path: Path::new(path).canonicalize(Path::new("/")).into_string(),
+        queue: ArrayQueue::new(EVENT_QUEUE_SIZE),
+        waker: AtomicWaker::new(),
+    });
+    WATCHES.write()?.push(Arc::downgrade(&watch));
+    WATCH_COUNT.fetch_add(1, Ordering::Relaxed);
+    Ok(WatchStream { watch })
+}
+
+impl Stream for WatchStream {
+    type Item = Event;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context) -> Poll<Option<Event>> {
+        let watch = &self.watch;
+        if let Some(event) = watch.queue.pop() {
+            return Poll::Ready(Some(event));
+        }
+
+        watch.waker.register(cx.waker());
+
+        match watch.queue.pop() {
+            Some(event) => {
+                watch.waker.take();
+                Poll::Ready(Some(event))
+            }
+            None => Poll::Pending,
+        }
+    }
+}
+
+impl Drop for WatchStream {
+    fn drop(&mut self) {
+        WATCH_COUNT.fetch_sub(1, Ordering::Relaxed);
+        // Dead entries are skipped anyway, so a busy lock only delays cleanup
+        if let Ok(mut watches) = WATCHES.write() {
+            let this = Arc::as_ptr(&self.watch);
+            watches.retain(|w| w.strong_count() > 0 && w.as_ptr() != this);
+        }
+    }
+}
+
+fn canonical(path: &str) -> String {
+    Path::new(path).canonicalize(Path::new("/")).into_string()
+}
+
+/// Hands `event` to every watcher interested in one of its paths
+fn notify(make_event: impl FnOnce() -> Event) {
+    if WATCH_COUNT.load(Ordering::Relaxed) == 0 {
+        return;
+    }
+    // Events are best effort, a change made while the table is being
+    // modified goes unreported rather than waiting
+    let Ok(watches) = WATCHES.read() else {
+        return;
+    };
+
+    let event = make_event();
+    for watch in watches.iter().filter_map(Weak::upgrade) {
+        let interested = match &event {
+            Event::Created(path) | Event::Modified(path) | Event::Removed(path) => watch.covers(path),
+            Event::Renamed { from, to } => watch.covers(from) || watch.covers(to),
+        };
+        if interested {
+            watch.queue.force_push(event.clone());
+            watch.waker.wake();
+        }
+    }
+}
+
+pub(crate) fn created(path: &str) {
+    notify(|| Event::Created(canonical(path)));
+}
+
+pub(crate) fn modified(path: &str) {
+    notify(|| Event::Modified(canonical(path)));
+}
+
+pub(crate) fn removed(path: &str) {
+    notify(|| Event::Removed(canonical(path)));
+}
+
+pub(crate) fn renamed(from: &str, to: &str) {
+    notify(|| Event::Renamed {
+        from: canonical(from),
+        to: canonical(to),
+    });
+}
+
+#[test_case]
+fn test_watch() {
+    use alloc::string::ToString;
+    use futures_util::{task::noop_waker_ref, StreamExt};
+
+    super::mount_test_root();
+    super::create_dir("/watched").unwrap();
+
+    let mut cx = Context::from_waker(noop_waker_ref());
+    let mut stream = super::watch("/watched/").unwrap();
+    assert_eq!(stream.poll_next_unpin(&mut cx), Poll::Pending);
+
+    super::write("/watched/./log", b"a").unwrap();
+    super::write("/watched/log", b"b").unwrap();
+    super::write("/elsewhere", b"").unwrap();
+    super::rename("/watched/log", "/log.old").unwrap();
+
+    let expected = [
+        Event::Created("/watched/log".to_string()),
+        Event::Modified("/watched/log".to_string()),
+        Event::Renamed {
+            from: "/watched/log".to_string(),
+            to: "/log.old".to_string(),
+        },
+    ];
+    for event in expected {
+        assert_eq!(stream.poll_next_unpin(&mut cx), Poll::Ready(Some(event)));
+    }
+    assert_eq!(stream.poll_next_unpin(&mut cx), Poll::Pending);
+}
@@ -11,13 +11,21 @@ use x86_64::structures::idt::{InterruptDescriptorTable, InterruptStackFrame};
 use pic8259::ChainedPics;
 use spin;
 use ps2_mouse;
+use core::sync::atomic::{AtomicU64, Ordering};
 
 #[derive(Debug, Clone, Copy)]
 #[repr(u8)]
 pub enum InterruptIndex {
     Timer = PIC_1_OFFSET,
     Keyboard,
+    Com1 = PIC_1_OFFSET + 4,
+    Pci5,
+    Pci9 = PIC_2_OFFSET + 1,
+    Pci10,
+    Pci11,
     Mouse = PIC_2_OFFSET + 4,
+    PrimaryAta = PIC_2_OFFSET + 6,
+    SecondaryAta,
 }
 
 pub const PIC_1_OFFSET: u8 = 32;
@@ -30,6 +38,52 @@ pub static PICS: spin::Mutex<ChainedPics> =
 pub static MOUSE: Lazy<spin::Mutex<Mouse>> =
     Lazy::new(|| spin::Mutex::new(Mouse::new()));
 
+/// The PIT is left at its power-on rate of 1193182 / 65536 Hz
+pub const TIMER_HZ: u64 = 18;
+const PIT_FREQUENCY: u64 = 1_193_182;
+const PIT_DIVISOR: u64 = 65_536;
+
+/// Vectors with a handler installed and their names for `/proc/interrupts`
+pub const HANDLED_VECTORS: &[(u8, &str)] = &[
+    (3, "breakpoint"),
+    (8, "double fault"),
+    (14, "page fault"),
+    (InterruptIndex::Timer as u8, "timer"),
+    (InterruptIndex::Keyboard as u8, "keyboard"),
+    (InterruptIndex::Com1 as u8, "serial"),
+    (InterruptIndex::Mouse as u8, "mouse"),
+    (InterruptIndex::PrimaryAta as u8, "ata primary"),
+    (InterruptIndex::SecondaryAta as u8, "ata secondary"),
+    (InterruptIndex::Pci5 as u8, "pci irq 5"),
+    (InterruptIndex::Pci9 as u8, "pci irq 9"),
+    (InterruptIndex::Pci10 as u8, "pci irq 10"),
+    (InterruptIndex::Pci11 as u8, "pci irq 11"),
+];
+
+/// IRQ lines the firmware may route PCI interrupts to that have a handler
+pub const PCI_IRQS: &[u8] = &[5, 9, 10, 11];
+
+static INTERRUPT_COUNTS: [AtomicU64; 256] = [const { AtomicU64::new(0) }; 256];
+
+fn count_interrupt(vector: u8) {
+    INTERRUPT_COUNTS[vector as usize].fetch_add(1, Ordering::Relaxed);
+}
+
+/// Number of times `vector` fired since boot
+pub fn interrupt_count(vector: u8) -> u64 {
+    INTERRUPT_COUNTS[vector as usize].load(Ordering::Relaxed)
+}
+
+/// Number of timer interrupts since boot
+pub fn ticks() -> u64 {
+    interrupt_count(InterruptIndex::Timer.as_u8())
+}
+
+/// Milliseconds since interrupts were enabled, using the exact PIT rate
+pub fn uptime_ms() -> u64 {
+    ticks() * PIT_DIVISOR * 1000 / PIT_FREQUENCY
+}
+
 impl InterruptIndex {
     fn as_u8(self) -> u8 {
         self as u8
@@ -52,8 +106,22 @@ lazy_static! {
             .set_handler_fn(timer_interrupt_handler);
         idt[InterruptIndex::Keyboard.as_usize()]
             .set_handler_fn(keyboard_interrupt_handler);
+        idt[InterruptIndex::Com1.as_usize()]
+            .set_handler_fn(com1_interrupt_handler);
         idt[InterruptIndex::Mouse.as_usize()]
             .set_handler_fn(mouse_interrupt_handler);
+        idt[InterruptIndex::PrimaryAta.as_usize()]
+            .set_handler_fn(primary_ata_interrupt_handler);
+        idt[InterruptIndex::SecondaryAta.as_usize()]
+            .set_handler_fn(secondary_ata_interrupt_handler);
+        idt[InterruptIndex::Pci5.as_usize()]
+            .set_handler_fn(pci5_interrupt_handler);
+        idt[InterruptIndex::Pci9.as_usize()]
+            .set_handler_fn(pci9_interrupt_handler);
+        idt[InterruptIndex::Pci10.as_usize()]
+            .set_handler_fn(pci10_interrupt_handler);
+        idt[InterruptIndex::Pci11.as_usize()]
+            .set_handler_fn(pci11_interrupt_handler);
         idt.page_fault.set_handler_fn(page_fault_handler);
 
         idt
@@ -71,6 +139,7 @@ extern "x86-interrupt" fn page_fault_handler(
 ) {
     use x86_64::registers::control::Cr2;
 
+    count_interrupt(14);
     println!("EXCEPTION: PAGE FAULT");
     println!("Accessed Address: {:?}", Cr2::read());
     println!("Error Code: {:?}", error_code);
@@ -83,6 +152,7 @@ extern "x86-interrupt" fn page_fault_handler(
 extern "x86-interrupt" fn keyboard_interrupt_handler(_stack_frame: InterruptStackFrame) {
     use x86_64::instructions::port::Port;
 
+    count_interrupt(InterruptIndex::Keyboard.as_u8());
     let mut port = Port::new(0x60);
     let scancode: u8 = unsafe { port.read() };
     crate::task::keyboard::add_scancode(scancode);
@@ -94,12 +164,14 @@ extern "x86-interrupt" fn keyboard_interrupt_handler(_stack_frame: InterruptStac
 }
 
 extern "x86-interrupt" fn breakpoint_handler(stack_frame: InterruptStackFrame) {
+    count_interrupt(3);
     println!("EXCEPTION: BREAKPOINT\n{:#?}", stack_frame);
 }
 
 extern "x86-interrupt" fn double_fault_handler(
     stack_frame: InterruptStackFrame, _error_code: u64) -> !
 {
+    count_interrupt(8);
     panic!("EXCEPTION: DOUBLE FAULT\n{:#?}", stack_frame);
 }
 
@@ -107,6 +179,9 @@ extern "x86-interrupt" fn timer_interrupt_handler(
     _stack_frame: InterruptStackFrame)
 {
     //print!(".");
+    count_interrupt(InterruptIndex::Timer.as_u8());
+    crate::block::cache::timer_tick(ticks());
+    crate::serial::timer_tick();
 
     unsafe {
         PICS.lock()
@@ -122,6 +197,7 @@ fn on_complete(mouse_state: MouseState) {
     println!("{:?}", mouse_state);
 }
 extern "x86-interrupt" fn mouse_interrupt_handler(_stack_frame  : InterruptStackFrame) {
+    count_interrupt(InterruptIndex::Mouse.as_u8());
     let mut port = PortReadOnly::new(0x60);
     let packet = unsafe { port.read() };
     MOUSE.lock().process_packet(packet);
@@ -132,6 +208,106 @@ extern "x86-interrupt" fn mouse_interrupt_handler(_stack_frame  : InterruptStack
     }
 }
 
+/// Unmasks IRQ4, COM1's line
+pub fn enable_serial_irq() {
+    x86_64::instructions::interrupts::without_interrupts(|| {
+        let mut pics = PICS.lock();
+        unsafe {
+            let [master, slave] = pics.read_masks();
+            pics.write_masks(master & !(1 << 4), slave);
+        }
+    });
+}
+
+extern "x86-interrupt" fn com1_interrupt_handler(_stack_frame: InterruptStackFrame) {
+    count_interrupt(InterruptIndex::Com1.as_u8());
+    crate::serial::handle_irq();
+
+    unsafe {
+        PICS.lock()
+            .notify_end_of_interrupt(InterruptIndex::Com1.as_u8());
+    }
+}
+
+/// Unmasks IRQ14 and IRQ15, and IRQ2 which cascades to them
+pub fn enable_ata_irqs() {
+    x86_64::instructions::interrupts::without_interrupts(|| {
+        let mut pics = PICS.lock();
+        unsafe {
+            let [master, slave] = pics.read_masks();
+            pics.write_masks(master & !(1 << 2), slave & !(1 << 6 | 1 << 7));
+        }
+    });
+}
+
+extern "x86-interrupt" fn primary_ata_interrupt_handler(_stack_frame: InterruptStackFrame) {
+    count_interrupt(InterruptIndex::PrimaryAta.as_u8());
+    crate::block::ata::handle_irq(0);
+
+    unsafe {
+        PICS.lock()
+            .notify_end_of_interrupt(InterruptIndex::PrimaryAta.as_u8());
+    }
+}
+
+extern "x86-interrupt" fn secondary_ata_interrupt_handler(_stack_frame: InterruptStackFrame) {
+    count_interrupt(InterruptIndex::SecondaryAta.as_u8());
+    crate::block::ata::handle_irq(1);
+
+    unsafe {
+        PICS.lock()
+            .notify_end_of_interrupt(InterruptIndex::SecondaryAta.as_u8());
+    }
+}
+
+/// Unmasks `line` for a PCI device, and IRQ2 if it is on the slave PIC.
+/// Returns false if the line is not one of `PCI_IRQS`.
+pub fn enable_pci_irq(line: u8) -> bool {
+    if !PCI_IRQS.contains(&line) {
+        return false;
+    }
+    x86_64::instructions::interrupts::without_interrupts(|| {
+        let mut pics = PICS.lock();
+        unsafe {
+            let [master, slave] = pics.read_masks();
+            if line < 8 {
+                pics.write_masks(master & !(1 << line), slave);
+            } else {
+                pics.write_masks(master & !(1 << 2), slave & !(1 << (line - 8)));
+            }
+        }
+    });
+    true
+}
+
+/// PCI interrupts are level triggered and may be shared, every device on
+/// the line checks and acknowledges its own
+fn pci_interrupt(index: InterruptIndex, line: u8) {
+    count_interrupt(index.as_u8());
+    crate::block::virtio::handle_irq(line);
+
+    unsafe {
+        PICS.lock()
+            .notify_end_of_interrupt(index.as_u8());
+    }
+}
+
+extern "x86-interrupt" fn pci5_interrupt_handler(_stack_frame: InterruptStackFrame) {
+    pci_interrupt(InterruptIndex::Pci5, 5);
+}
+
+extern "x86-interrupt" fn pci9_interrupt_handler(_stack_frame: InterruptStackFrame) {
+    pci_interrupt(InterruptIndex::Pci9, 9);
+}
+
+extern "x86-interrupt" fn pci10_interrupt_handler(_stack_frame: InterruptStackFrame) {
+    pci_interrupt(InterruptIndex::Pci10, 10);
+}
+
+extern "x86-interrupt" fn pci11_interrupt_handler(_stack_frame: InterruptStackFrame) {
+    pci_interrupt(InterruptIndex::Pci11, 11);
+}
+
 #[test_case]
 fn test_breakpoint_exception() {
     // invoke a breakpoint exception
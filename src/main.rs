@@ -27,11 +27,17 @@ fn kernel_main(boot_info: &'static BootInfo) -> ! {
 
     allocator::init_heap(&mut mapper, &mut frame_allocator)
         .expect("heap init failed");
+    memory::install(mapper, frame_allocator);
+    os::serial::enable_receive_irq();
+
+    os::block::init();
 
     let mut executor = Executor::new();
     executor.spawn(Task::new(keyboard::init()));
     executor.spawn(Task::new(shell::init()));
     executor.spawn(Task::new(os::fs::init()));
+    executor.spawn(Task::new(os::block::cache::writeback_task()));
+    executor.spawn(Task::new(os::fs::image_load_task()));
 
     executor.run();
 
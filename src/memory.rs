@@ -1,8 +1,109 @@
 use x86_64::{
-    PhysAddr, VirtAddr, structures::paging::{FrameAllocator, Mapper, OffsetPageTable, Page, PageTable, PhysFrame, Size4KiB}
+    PhysAddr, VirtAddr, structures::paging::{FrameAllocator, Mapper, OffsetPageTable, Page, PageTable, PageTableFlags, PhysFrame, Size4KiB}
 };
 
 use bootloader::bootinfo::{MemoryMap, MemoryRegionType};
+use core::sync::atomic::{AtomicUsize, Ordering};
+use spin::Mutex;
+
+static USABLE_FRAMES: AtomicUsize = AtomicUsize::new(0);
+static ALLOCATED_FRAMES: AtomicUsize = AtomicUsize::new(0);
+
+/// Where `map_mmio` maps device registers, away from the heap
+pub const MMIO_START: u64 = 0x_5555_5555_0000;
+
+/// The page tables and frames handed over by `install`, for drivers
+struct Memory {
+    mapper: OffsetPageTable<'static>,
+    frames: BootInfoFrameAllocator,
+    /// Next unused page of the MMIO window
+    next_mmio: u64,
+}
+
+static MEMORY: Mutex<Option<Memory>> = Mutex::new(None);
+
+/// Keeps the mapper and frame allocator for `alloc_dma` and `map_mmio`,
+/// called once the heap is set up
+pub fn install(mapper: OffsetPageTable<'static>, frames: BootInfoFrameAllocator) {
+    *MEMORY.lock() = Some(Memory {
+        mapper,
+        frames,
+        next_mmio: MMIO_START,
+    });
+}
+
+/// Physically contiguous, zeroed memory that devices can access directly
+///
+/// It is never freed, drivers allocate it once when they find a device.
+pub struct DmaRegion {
+    phys: PhysAddr,
+    virt: VirtAddr,
+    len: usize,
+}
+
+impl DmaRegion {
+    /// The address to give the device
+    pub fn phys(&self) -> PhysAddr {
+        self.phys
+    }
+
+    pub fn as_ptr(&self) -> *mut u8 {
+        self.virt.as_mut_ptr()
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+}
+
+/// Takes `len` bytes of contiguous frames, `None` before `install` or when
+/// physical memory runs out
+pub fn alloc_dma(len: usize) -> Option<DmaRegion> {
+    let mut memory = MEMORY.lock();
+    let memory = memory.as_mut()?;
+    let first = memory.frames.allocate_contiguous(len.div_ceil(4096))?;
+    let phys = first.start_address();
+    // All of physical memory is mapped at the offset, no new mapping needed
+    let virt = memory.mapper.phys_offset() + phys.as_u64();
+    unsafe { core::ptr::write_bytes(virt.as_mut_ptr::<u8>(), 0, len) };
+    Some(DmaRegion { phys, virt, len })
+}
+
+/// Maps `len` bytes of device registers at `phys` uncached, returns the
+/// virtual address of `phys`
+pub fn map_mmio(phys: PhysAddr, len: usize) -> Option<VirtAddr> {
+    let mut memory = MEMORY.lock();
+    let memory = memory.as_mut()?;
+    let first = PhysFrame::<Size4KiB>::containing_address(phys);
+    let last = PhysFrame::<Size4KiB>::containing_address(phys + (len.max(1) - 1) as u64);
+    let start = VirtAddr::new(memory.next_mmio);
+    let flags = PageTableFlags::PRESENT | PageTableFlags::WRITABLE | PageTableFlags::NO_CACHE;
+
+    for (i, frame) in PhysFrame::range_inclusive(first, last).enumerate() {
+        let page = Page::<Size4KiB>::containing_address(start + i as u64 * 4096);
+        unsafe { memory.mapper.map_to(page, frame, flags, &mut memory.frames) }.ok()?.flush();
+        memory.next_mmio += 4096;
+    }
+    Some(start + (phys.as_u64() - first.start_address().as_u64()))
+}
+
+/// Physical frame counts as shown in `/proc/meminfo`
+#[derive(Debug, Clone, Copy)]
+pub struct FrameStats {
+    pub usable: usize,
+    pub allocated: usize,
+}
+
+pub fn frame_stats() -> FrameStats {
+    FrameStats {
+        usable: USABLE_FRAMES.load(Ordering::Relaxed),
+        allocated: ALLOCATED_FRAMES.load(Ordering::Relaxed),
+    }
+}
 
 pub struct EmptyFrameAllocator;
 
@@ -28,6 +129,9 @@ unsafe impl FrameAllocator<Size4KiB> for BootInfoFrameAllocator {
     fn allocate_frame(&mut self) -> Option<PhysFrame> {
         let frame = self.usable_frames().nth(self.next);
         self.next += 1;
+        if frame.is_some() {
+            ALLOCATED_FRAMES.fetch_add(1, Ordering::Relaxed);
+        }
         frame
     }
 }
@@ -39,10 +143,29 @@ pub struct BootInfoFrameAllocator {
 
 impl BootInfoFrameAllocator {
     pub unsafe fn init(memory_map: &'static MemoryMap) -> Self {
-        BootInfoFrameAllocator {
+        let allocator = BootInfoFrameAllocator {
             memory_map,
             next: 0,
+        };
+        USABLE_FRAMES.store(allocator.usable_frames().count(), Ordering::Relaxed);
+        allocator
+    }
+
+    /// Allocates `count` frames that follow each other in physical memory.
+    /// Frames skipped on the way to such a run are lost.
+    pub fn allocate_contiguous(&mut self, count: usize) -> Option<PhysFrame> {
+        let mut first = self.allocate_frame()?;
+        let mut len = 1;
+        while len < count {
+            let frame = self.allocate_frame()?;
+            if frame == first + len as u64 {
+                len += 1;
+            } else {
+                first = frame;
+                len = 1;
+            }
         }
+        Some(first)
     }
 
     /// Returns an iterator over the usable frames specified in the memory map.
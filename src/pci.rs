@@ -0,0 +1,180 @@
+extern crate alloc;
+use alloc::vec::Vec;
+use spin::Mutex;
+use x86_64::instructions::{interrupts, port::Port};
+
+const CONFIG_ADDRESS: u16 = 0xCF8;
+const CONFIG_DATA: u16 = 0xCFC;
+
+// Offsets into the configuration header
+const VENDOR_ID: u8 = 0x00;
+const DEVICE_ID: u8 = 0x02;
+const COMMAND: u8 = 0x04;
+const STATUS: u8 = 0x06;
+const CLASS: u8 = 0x08;
+const HEADER_TYPE: u8 = 0x0E;
+const BAR0: u8 = 0x10;
+const CAPABILITIES: u8 = 0x34;
+const INTERRUPT_LINE: u8 = 0x3C;
+
+const COMMAND_IO: u16 = 1 << 0;
+const COMMAND_MEMORY: u16 = 1 << 1;
+const COMMAND_BUS_MASTER: u16 = 1 << 2;
+const STATUS_CAPABILITIES: u16 = 1 << 4;
+const HEADER_MULTIFUNCTION: u8 = 0x80;
+
+/// Guards the address and data port pair, an access is two port writes
+static CONFIG: Mutex<()> = Mutex::new(());
+
+/// A base address register, decoded
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Bar {
+    Io(u16),
+    Memory(u64),
+}
+
+/// One function of a device on the PCI bus
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PciDevice {
+    pub bus: u8,
+    pub device: u8,
+    pub function: u8,
+    pub vendor_id: u16,
+    pub device_id: u16,
+    pub class: u8,
+    pub subclass: u8,
+}
+
+impl PciDevice {
+    fn at(bus: u8, device: u8, function: u8) -> Option<Self> {
+        let mut dev = PciDevice {
+            bus,
+            device,
+            function,
+            vendor_id: 0,
+            device_id: 0,
+            class: 0,
+            subclass: 0,
+        };
+        dev.vendor_id = dev.read_u16(VENDOR_ID);
+        if dev.vendor_id == 0xFFFF {
+            return None;
+        }
+        dev.device_id = dev.read_u16(DEVICE_ID);
+        let class = dev.read_u32(CLASS);
+        dev.class = (class >> 24) as u8;
+        dev.subclass = (class >> 16) as u8;
+        Some(dev)
+    }
+
+    fn address(&self, offset: u8) -> u32 {
+        1 << 31
+            | u32::from(self.bus) << 16
+            | u32::from(self.device) << 11
+            | u32::from(self.function) << 8
+            | u32::from(offset & 0xFC)
+    }
+
+    pub fn read_u32(&self, offset: u8) -> u32 {
+        interrupts::without_interrupts(|| {
+            let _guard = CONFIG.lock();
+            unsafe {
+                Port::new(CONFIG_ADDRESS).write(self.address(offset));
+                Port::<u32>::new(CONFIG_DATA).read()
+            }
+        })
+    }
+
+    pub fn read_u16(&self, offset: u8) -> u16 {
+        (self.read_u32(offset) >> ((offset & 2) * 8)) as u16
+    }
+
+    pub fn read_u8(&self, offset: u8) -> u8 {
+        (self.read_u32(offset) >> ((offset & 3) * 8)) as u8
+    }
+
+    pub fn write_u32(&self, offset: u8, value: u32) {
+        interrupts::without_interrupts(|| {
+            let _guard = CONFIG.lock();
+            unsafe {
+                Port::new(CONFIG_ADDRESS).write(self.address(offset));
+                Port::new(CONFIG_DATA).write(value);
+            }
+        })
+    }
+
+    /// Writes only the two bytes at `offset`, so that the write-one-to-clear
+    /// bits next to them are left alone
+    pub fn write_u16(&self, offset: u8, value: u16) {
+        interrupts::without_interrupts(|| {
+            let _guard = CONFIG.lock();
+            unsafe {
+                Port::new(CONFIG_ADDRESS).write(self.address(offset));
+                Port::new(CONFIG_DATA + u16::from(offset & 2)).write(value);
+            }
+        })
+    }
+
+    /// Base address register `index`, `None` if it is unused or `index`
+    /// is the upper half of a 64-bit one
+    pub fn bar(&self, index: u8) -> Option<Bar> {
+        if index > 5 {
+            return None;
+        }
+        let value = self.read_u32(BAR0 + index * 4);
+        if value & 1 != 0 {
+            let port = (value & !0x3) as u16;
+            return (port != 0).then_some(Bar::Io(port));
+        }
+        let mut addr = u64::from(value & !0xF);
+        // Type 2 takes the next register for the upper bits
+        if (value >> 1) & 0x3 == 2 && index < 5 {
+            addr |= u64::from(self.read_u32(BAR0 + (index + 1) * 4)) << 32;
+        }
+        (addr != 0).then_some(Bar::Memory(addr))
+    }
+
+    /// The legacy PIC line the firmware routed the interrupt pin to
+    pub fn interrupt_line(&self) -> u8 {
+        self.read_u8(INTERRUPT_LINE)
+    }
+
+    /// Turns on port and memory decoding and lets the device master the
+    /// bus, which it needs for DMA
+    pub fn enable(&self) {
+        let command = self.read_u16(COMMAND);
+        self.write_u16(COMMAND, command | COMMAND_IO | COMMAND_MEMORY | COMMAND_BUS_MASTER);
+    }
+
+    /// Capability ids and their offsets in the configuration space, in
+    /// list order
+    pub fn capabilities(&self) -> Vec<(u8, u8)> {
+        let mut caps = Vec::new();
+        if self.read_u16(STATUS) & STATUS_CAPABILITIES == 0 {
+            return caps;
+        }
+        let mut offset = self.read_u8(CAPABILITIES) & 0xFC;
+        // 48 capabilities fill the space, a longer list is a loop
+        while offset != 0 && caps.len() < 48 {
+            caps.push((self.read_u8(offset), offset));
+            offset = self.read_u8(offset + 1) & 0xFC;
+        }
+        caps
+    }
+}
+
+/// Every function on every bus, found by brute force
+pub fn scan() -> Vec<PciDevice> {
+    let mut devices = Vec::new();
+    for bus in 0..=255 {
+        for device in 0..32 {
+            let Some(first) = PciDevice::at(bus, device, 0) else {
+                continue;
+            };
+            let functions = if first.read_u8(HEADER_TYPE) & HEADER_MULTIFUNCTION != 0 { 8 } else { 1 };
+            devices.push(first);
+            devices.extend((1..functions).filter_map(|function| PciDevice::at(bus, device, function)));
+        }
+    }
+    devices
+}
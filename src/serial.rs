@@ -1,6 +1,28 @@
 use uart_16550::SerialPort;
 use spin::Mutex;
 use lazy_static::lazy_static;
+use conquer_once::spin::OnceCell;
+use core::task::Poll;
+use crossbeam_queue::ArrayQueue;
+use futures_util::{future::poll_fn, task::AtomicWaker};
+use x86_64::instructions::{interrupts, port::Port};
+
+/// COM1, the port behind `SERIAL1`
+const COM1_DATA: u16 = 0x3F8;
+const COM1_INTERRUPT_ENABLE: u16 = COM1_DATA + 1;
+const COM1_MODEM_CONTROL: u16 = COM1_DATA + 4;
+const COM1_LINE_STATUS: u16 = COM1_DATA + 5;
+
+/// Interrupt when a byte has been received
+const IER_RECEIVED_DATA: u8 = 1 << 0;
+/// DTR, RTS, and OUT2 which connects the UART's interrupt to the PIC
+const MCR_DTR_RTS_OUT2: u8 = 0x0B;
+
+/// Received bytes kept until read, the UART's own FIFO holds only 16
+const RX_BUFFER_SIZE: usize = 1024;
+
+static RX_QUEUE: OnceCell<ArrayQueue<u8>> = OnceCell::uninit();
+static RX_WAKER: AtomicWaker = AtomicWaker::new();
 
 lazy_static! {
     pub static ref SERIAL1: Mutex<SerialPort> = {
@@ -10,10 +32,108 @@ lazy_static! {
     };
 }
 
+/// Bit 0 of the line status register is set while a received byte waits
+fn byte_waiting() -> bool {
+    let mut line_status: Port<u8> = Port::new(COM1_LINE_STATUS);
+    let status = unsafe { line_status.read() };
+    status & 1 != 0
+}
+
+/// Buffers what COM1 receives from its interrupt on, so readers can wait
+/// for bytes instead of polling the port. Needs the heap.
+pub fn enable_receive_irq() {
+    if RX_QUEUE.try_init_once(|| ArrayQueue::new(RX_BUFFER_SIZE)).is_err() {
+        return;
+    }
+    interrupts::without_interrupts(|| {
+        let _serial = SERIAL1.lock();
+        unsafe {
+            Port::new(COM1_INTERRUPT_ENABLE).write(IER_RECEIVED_DATA);
+            Port::new(COM1_MODEM_CONTROL).write(MCR_DTR_RTS_OUT2);
+        }
+    });
+    crate::interrupts::enable_serial_irq();
+    // Bytes from before would keep the interrupt line up without an edge
+    handle_irq();
+}
+
+/// Called from the COM1 interrupt handler, moves the received bytes to
+/// the buffer, dropping the oldest ones when nobody reads them
+pub(crate) fn handle_irq() {
+    let Ok(queue) = RX_QUEUE.try_get() else {
+        return;
+    };
+    interrupts::without_interrupts(|| {
+        let _serial = SERIAL1.lock();
+        let mut data: Port<u8> = Port::new(COM1_DATA);
+        while byte_waiting() {
+            queue.force_push(unsafe { data.read() });
+        }
+    });
+    RX_WAKER.wake();
+}
+
+/// Called from the timer interrupt handler, so `receive` notices its
+/// deadline
+pub(crate) fn timer_tick() {
+    RX_WAKER.wake();
+}
+
+/// True if COM1 has received a byte nobody has read yet
+pub fn data_ready() -> bool {
+    interrupts::without_interrupts(|| {
+        let _serial = SERIAL1.lock();
+        RX_QUEUE.try_get().is_ok_and(|queue| !queue.is_empty()) || byte_waiting()
+    })
+}
+
+/// Takes the next received byte off COM1 if one is waiting, never blocks
+pub fn try_receive() -> Option<u8> {
+    interrupts::without_interrupts(|| {
+        // Hold the port so nobody prints halfway through
+        let _serial = SERIAL1.lock();
+        if let Some(byte) = RX_QUEUE.try_get().ok().and_then(|queue| queue.pop()) {
+            return Some(byte);
+        }
+        let mut data: Port<u8> = Port::new(COM1_DATA);
+        byte_waiting().then(|| unsafe { data.read() })
+    })
+}
+
+/// Waits for the next byte on COM1, `None` if the timer reaches `deadline`
+/// ticks first. Other tasks run meanwhile.
+pub async fn receive(deadline: u64) -> Option<u8> {
+    poll_fn(|cx| {
+        RX_WAKER.register(cx.waker());
+        if let Some(byte) = try_receive() {
+            return Poll::Ready(Some(byte));
+        }
+        if crate::interrupts::ticks() >= deadline {
+            return Poll::Ready(None);
+        }
+        // Without the interrupt no byte wakes us, poll again after the
+        // other tasks had their turn
+        if RX_QUEUE.try_get().is_err() {
+            cx.waker().wake_by_ref();
+        }
+        Poll::Pending
+    })
+    .await
+}
+
+/// Sends raw bytes, unlike `serial_print!` nothing needs to be UTF-8
+pub fn send_bytes(bytes: &[u8]) {
+    interrupts::without_interrupts(|| {
+        let mut serial = SERIAL1.lock();
+        for &byte in bytes {
+            serial.send(byte);
+        }
+    });
+}
+
 #[doc(hidden)]
 pub fn _print(args: ::core::fmt::Arguments) {
     use core::fmt::Write;
-    use x86_64::instructions::interrupts;
 
     interrupts::without_interrupts(|| {
         SERIAL1
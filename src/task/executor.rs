@@ -1,4 +1,4 @@
-use super::{Task, TaskId};
+use super::{Task, TaskId, TaskState, TaskStatus, TASKS};
 use alloc::{collections::BTreeMap, sync::Arc};
 use core::task::Waker;
 use crossbeam_queue::ArrayQueue;
@@ -14,16 +14,19 @@ pub struct Executor {
 struct TaskWaker {
     task_id: TaskId,
     task_queue: Arc<ArrayQueue<TaskId>>,
+    status: Arc<TaskStatus>,
 }
 
 impl TaskWaker {
-    fn new(task_id: TaskId, task_queue: Arc<ArrayQueue<TaskId>>) -> Waker {
+    fn new(task_id: TaskId, task_queue: Arc<ArrayQueue<TaskId>>, status: Arc<TaskStatus>) -> Waker {
         Waker::from(Arc::new(TaskWaker {
             task_id,
             task_queue,
+            status,
         }))
     }
     fn wake_task(&self) {
+        self.status.set(TaskState::Ready);
         self.task_queue.push(self.task_id).expect("task_queue full");
     }
 }
@@ -54,6 +57,7 @@ impl Executor {
     }
     pub fn spawn(&mut self, task: Task) {
         let task_id = task.id;
+        TASKS.lock().insert(task_id, task.status.clone());
         if self.tasks.insert(task.id, task).is_some() {
             panic!("task with same ID already in tasks");
         }
@@ -74,14 +78,17 @@ impl Executor {
             };
             let waker = waker_cache
                 .entry(task_id)
-                .or_insert_with(|| TaskWaker::new(task_id, task_queue.clone()));
+                .or_insert_with(|| TaskWaker::new(task_id, task_queue.clone(), task.status.clone()));
             let mut context = Context::from_waker(waker);
+            task.status.set(TaskState::Running);
             match task.poll(&mut context) {
                 Poll::Ready(()) => {
                     tasks.remove(&task_id);
                     waker_cache.remove(&task_id);
+                    TASKS.lock().remove(&task_id);
                 }
-                Poll::Pending => {}
+                // A wake during the poll already put it back to `Ready`
+                Poll::Pending => task.status.transition(TaskState::Running, TaskState::Waiting),
             }
         }
     }
@@ -1,7 +1,8 @@
 use core::{future::Future, pin::Pin};
 use core::task::{Context, Poll};
-use alloc::boxed::Box;
-use core::sync::atomic::{AtomicU64, Ordering};
+use alloc::{boxed::Box, collections::BTreeMap, sync::Arc, vec::Vec};
+use core::sync::atomic::{AtomicU8, AtomicU64, Ordering};
+use spin::Mutex;
 
 pub mod executor;
 pub mod keyboard;
@@ -17,9 +18,80 @@ impl TaskId {
     }
 }
 
+/// What a spawned task is doing, as shown in `/proc/tasks`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u8)]
+pub enum TaskState {
+    /// Woken and waiting in the executor queue
+    Ready,
+    /// Being polled right now
+    Running,
+    /// Returned `Pending` and nobody woke it yet
+    Waiting,
+}
+
+impl TaskState {
+    fn from_u8(value: u8) -> TaskState {
+        match value {
+            0 => TaskState::Ready,
+            1 => TaskState::Running,
+            _ => TaskState::Waiting,
+        }
+    }
+}
+
+/// State shared between a task, its waker and the registry.
+/// Wakers run in interrupt handlers, so it is an atomic instead of a lock.
+struct TaskStatus(AtomicU8);
+
+impl TaskStatus {
+    fn set(&self, state: TaskState) {
+        self.0.store(state as u8, Ordering::Relaxed);
+    }
+
+    fn get(&self) -> TaskState {
+        TaskState::from_u8(self.0.load(Ordering::Relaxed))
+    }
+
+    /// Switches to `new` only if nothing changed the state since `current`
+    fn transition(&self, current: TaskState, new: TaskState) {
+        let _ = self
+            .0
+            .compare_exchange(current as u8, new as u8, Ordering::Relaxed, Ordering::Relaxed);
+    }
+}
+
+/// Tasks spawned on an executor and not finished yet.
+///
+/// The executor locks it around its bookkeeping between polls, `tasks`
+/// locks it from inside a poll (`/proc/tasks` is read by the shell task).
+/// Neither holds it across a poll and interrupt handlers never take it, so
+/// with the single executor on one CPU it is always free when locked.
+static TASKS: Mutex<BTreeMap<TaskId, Arc<TaskStatus>>> = Mutex::new(BTreeMap::new());
+
+/// Entry of the task list as reported by `task::tasks`
+#[derive(Debug, Clone, Copy)]
+pub struct TaskInfo {
+    pub id: u64,
+    pub state: TaskState,
+}
+
+/// List the live tasks, ordered by id
+pub fn tasks() -> Vec<TaskInfo> {
+    TASKS
+        .lock()
+        .iter()
+        .map(|(id, status)| TaskInfo {
+            id: id.0,
+            state: status.get(),
+        })
+        .collect()
+}
+
 pub struct Task {
     id: TaskId,
     future: Pin<Box<dyn Future<Output = ()>>>,
+    status: Arc<TaskStatus>,
 }
 
 impl Task {
@@ -27,6 +99,7 @@ impl Task {
         Task {
             id: TaskId::new(),
             future: Box::pin(future),
+            status: Arc::new(TaskStatus(AtomicU8::new(TaskState::Ready as u8))),
         }
     }
 
@@ -1,7 +1,9 @@
-use alloc::string::{String, ToString};
+use alloc::{boxed::Box, collections::{BTreeMap, VecDeque}, string::{String, ToString}};
+use core::{future::Future, pin::Pin, task::Poll};
+use futures_util::{future::poll_fn, task::AtomicWaker};
 use pc_keyboard::{DecodedKey};
 use spin::Mutex;
-use crate::{print, vga_buffer};
+use crate::{fs::{self, FsError, Path, PathBuf}, print, vga_buffer};
 use super::keyboard;
 pub mod hello;
 pub mod clear;
@@ -9,51 +11,196 @@ pub mod cat;
 pub mod mkdir;
 pub mod touch;
 pub mod ls;
+pub mod stat;
+pub mod mount;
+pub mod umount;
+pub mod rm;
+pub mod rmdir;
+pub mod mv;
+pub mod ln;
+pub mod df;
+pub mod fs_save;
+pub mod fs_load;
+pub mod snapshot;
+pub mod blk;
+pub mod sync;
+pub mod lsblk;
+pub mod cd;
+pub mod pwd;
+pub mod env;
+pub mod lexer;
 pub mod test;
 
 // NOTE: Each command module must have this signature:
 // pub const CMD: &str = "command_name";
 //pub static USAGE: &str = "command_usage"; (only for help command)
 //pub static DES: &str = "command_description"; (only for help command)
-// pub fn main(args: &[&str]) { ... }
+// pub fn main(shell: &mut Shell, args: &[&str]) { ... }
+// or, for commands that await (listed with `Handler::Async`):
+// pub fn main<'a>(shell: &'a mut Shell, args: &'a [&'a str]) -> CommandFuture<'a> { ... }
 
 static TYPED_KEYS: Mutex<String> = Mutex::new(String::new());
 
-/////TODO: somehow allow cd to change PWD without causing other errors
-pub static PWD: &str = "/";
+/// Lines entered at the console that the shell task has yet to run
+static LINES: Mutex<VecDeque<String>> = Mutex::new(VecDeque::new());
+static LINES_WAKER: AtomicWaker = AtomicWaker::new();
+
+/// Exit status of a command that printed its usage
+pub const STATUS_USAGE: u8 = 2;
+/// Exit status when no command has the typed name
+pub const STATUS_NOT_FOUND: u8 = 127;
+
+/// State of the session at the console, handed to every command
+pub struct Shell {
+    cwd: PathBuf,
+    /// Of the last command, 0 if it succeeded
+    status: u8,
+    env: BTreeMap<String, String>,
+}
+
+impl Default for Shell {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Shell {
+    pub fn new() -> Self {
+        let mut shell = Shell {
+            cwd: PathBuf::from("/"),
+            status: 0,
+            env: BTreeMap::new(),
+        };
+        shell.set_var("PWD", "/");
+        shell
+    }
+
+    pub fn cwd(&self) -> &Path {
+        &self.cwd
+    }
+
+    /// Makes `path` the working directory if it is a directory, keeping
+    /// `PWD` and `OLDPWD` up to date
+    pub fn set_cwd(&mut self, path: &str) -> Result<(), FsError> {
+        let target = self.full_path(path);
+        if !fs::stat(target.as_str())?.is_dir() {
+            return Err(FsError::NotADirectory);
+        }
+        self.set_var("PWD", target.as_str());
+        let old = core::mem::replace(&mut self.cwd, target);
+        self.set_var("OLDPWD", old.as_str());
+        Ok(())
+    }
+
+    /// Turns a path typed by the user into an absolute one, relative to the
+    /// working directory
+    pub fn full_path(&self, path: &str) -> PathBuf {
+        Path::new(path).canonicalize(&self.cwd)
+    }
+
+    pub fn status(&self) -> u8 {
+        self.status
+    }
+
+    /// Sets the exit status of the running command, it starts out as 0
+    pub fn set_status(&mut self, status: u8) {
+        self.status = status;
+    }
+
+    /// Prints the usage of the running command and fails it
+    pub fn usage(&mut self, usage: &str) {
+        print!("\nUSAGE: {}\n", usage);
+        self.status = STATUS_USAGE;
+    }
+
+    pub fn var(&self, name: &str) -> Option<&str> {
+        self.env.get(name).map(String::as_str)
+    }
+
+    pub fn set_var(&mut self, name: &str, value: &str) {
+        self.env.insert(name.to_string(), value.to_string());
+    }
+
+    pub fn remove_var(&mut self, name: &str) {
+        self.env.remove(name);
+    }
+
+    /// Every variable, ordered by name
+    pub fn vars(&self) -> impl Iterator<Item = (&str, &str)> {
+        self.env.iter().map(|(name, value)| (name.as_str(), value.as_str()))
+    }
+}
+
+/// The console session, created for the first command
+static SESSION: Mutex<Option<Shell>> = Mutex::new(None);
+
+/// What an async command handler returns, awaited by the shell task
+pub type CommandFuture<'a> = Pin<Box<dyn Future<Output = ()> + 'a>>;
+
+enum Handler {
+    Sync(fn(&mut Shell, &[&str])),
+    /// Awaited by the shell task, so other tasks run while the command waits
+    Async(for<'a> fn(&'a mut Shell, &'a [&'a str]) -> CommandFuture<'a>),
+}
 
 // Define command structure
 struct Command {
     name: &'static str,
     usage: &'static str,
     des: &'static str,
-    handler: fn(&[&str]),
+    handler: Handler,
 }
 
 // List of all available commands
 const COMMANDS: &[Command] = &[
-    Command { name: hello::CMD, handler: hello::main, usage: hello::USAGE, des: hello::DES },
-    Command { name: clear::CMD, handler: clear::main, usage: clear::USAGE, des: clear::DES },
-    Command { name: cat::CMD, handler: cat::main, usage: cat::USAGE, des: cat::DES },
-    Command { name: mkdir::CMD, handler: mkdir::main, usage: mkdir::USAGE, des: mkdir::DES},
-    Command { name: touch::CMD, handler: touch::main, usage: touch::USAGE, des: touch::DES},
-    Command { name: ls::CMD, handler: ls::main, usage: ls::USAGE, des: ls::DES},
-    Command { name: test::CMD, handler: test::main, usage: test::USAGE, des: test::DES}
+    Command { name: hello::CMD, handler: Handler::Sync(hello::main), usage: hello::USAGE, des: hello::DES },
+    Command { name: clear::CMD, handler: Handler::Sync(clear::main), usage: clear::USAGE, des: clear::DES },
+    Command { name: cat::CMD, handler: Handler::Async(cat::main), usage: cat::USAGE, des: cat::DES },
+    Command { name: mkdir::CMD, handler: Handler::Sync(mkdir::main), usage: mkdir::USAGE, des: mkdir::DES},
+    Command { name: touch::CMD, handler: Handler::Sync(touch::main), usage: touch::USAGE, des: touch::DES},
+    Command { name: ls::CMD, handler: Handler::Sync(ls::main), usage: ls::USAGE, des: ls::DES},
+    Command { name: stat::CMD, handler: Handler::Sync(stat::main), usage: stat::USAGE, des: stat::DES},
+    Command { name: mount::CMD, handler: Handler::Sync(mount::main), usage: mount::USAGE, des: mount::DES},
+    Command { name: umount::CMD, handler: Handler::Sync(umount::main), usage: umount::USAGE, des: umount::DES},
+    Command { name: rm::CMD, handler: Handler::Sync(rm::main), usage: rm::USAGE, des: rm::DES},
+    Command { name: rmdir::CMD, handler: Handler::Sync(rmdir::main), usage: rmdir::USAGE, des: rmdir::DES},
+    Command { name: mv::CMD, handler: Handler::Sync(mv::main), usage: mv::USAGE, des: mv::DES},
+    Command { name: ln::CMD, handler: Handler::Sync(ln::main), usage: ln::USAGE, des: ln::DES},
+    Command { name: df::CMD, handler: Handler::Sync(df::main), usage: df::USAGE, des: df::DES},
+    Command { name: fs_save::CMD, handler: Handler::Sync(fs_save::main), usage: fs_save::USAGE, des: fs_save::DES},
+    Command { name: fs_load::CMD, handler: Handler::Sync(fs_load::main), usage: fs_load::USAGE, des: fs_load::DES},
+    Command { name: snapshot::CMD, handler: Handler::Sync(snapshot::main), usage: snapshot::USAGE, des: snapshot::DES},
+    Command { name: blk::CMD, handler: Handler::Sync(blk::main), usage: blk::USAGE, des: blk::DES},
+    Command { name: sync::CMD, handler: Handler::Sync(sync::main), usage: sync::USAGE, des: sync::DES},
+    Command { name: lsblk::CMD, handler: Handler::Sync(lsblk::main), usage: lsblk::USAGE, des: lsblk::DES},
+    Command { name: cd::CMD, handler: Handler::Sync(cd::main), usage: cd::USAGE, des: cd::DES},
+    Command { name: pwd::CMD, handler: Handler::Sync(pwd::main), usage: pwd::USAGE, des: pwd::DES},
+    Command { name: env::CMD, handler: Handler::Sync(env::main), usage: env::USAGE, des: env::DES},
+    Command { name: test::CMD, handler: Handler::Sync(test::main), usage: test::USAGE, des: test::DES}
 ];
 
-fn handle_cmd() {
-    let binding = TYPED_KEYS.lock();
-    let input = binding.as_str().trim();
-    
+async fn handle_cmd(line: &str) {
+    let mut session = SESSION.lock();
+    let shell = session.get_or_insert_with(Shell::new);
+
     // Split input into command and arguments
-    let parts: alloc::vec::Vec<&str> = input.split_whitespace().collect();
-    
+    let words = match lexer::tokenize(line) {
+        Ok(words) => words,
+        Err(e) => {
+            print!("\nsyntax error: {}", e);
+            shell.set_status(STATUS_USAGE);
+            return;
+        }
+    };
+    let parts: alloc::vec::Vec<&str> = words.iter().map(String::as_str).collect();
+
     if parts.is_empty() {
         return;
     }
-    
+
     let cmd = parts[0];
     let args = &parts[1..];
+    shell.set_status(0);
     
     if cmd == "help" {
         print!("\n\n-- help list --\n\n");
@@ -68,24 +215,25 @@ fn handle_cmd() {
     // Loop through all commands to find a match
     for command in COMMANDS {
         if cmd == command.name {
-            (command.handler)(args);
+            match command.handler {
+                Handler::Sync(handler) => handler(shell, args),
+                Handler::Async(handler) => handler(shell, args).await,
+            }
             return;
         }
     }
     
     // If no command matched, optionally print an error
     print!("\nUnknown command: {}", cmd);
+    shell.set_status(STATUS_NOT_FOUND);
 }
 
 fn handle_unicode(c: char) {
     let key = c.to_string();
     if key == "\n" {
-        handle_cmd();
-        print!("\n$ ");
-        vga_buffer::WRITER.lock().set_column_position(2);
-        
-        let mut ____s____ = TYPED_KEYS.lock();
-        ____s____.clear();
+        let line = core::mem::take(&mut *TYPED_KEYS.lock());
+        LINES.lock().push_back(line);
+        LINES_WAKER.wake();
     }
     else if key == "\x08" || key == "\x7f" {
         let binding = TYPED_KEYS.lock();
@@ -111,8 +259,34 @@ fn key_pressed(key: DecodedKey) {
     }
 }
 
+/// Waits for the next line entered at the console
+async fn next_line() -> String {
+    poll_fn(|cx| {
+        if let Some(line) = LINES.lock().pop_front() {
+            return Poll::Ready(line);
+        }
+        LINES_WAKER.register(cx.waker());
+        match LINES.lock().pop_front() {
+            Some(line) => {
+                LINES_WAKER.take();
+                Poll::Ready(line)
+            }
+            None => Poll::Pending,
+        }
+    })
+    .await
+}
+
+/// Runs the lines typed at the console one after the other, for as long
+/// as the kernel runs
 pub async fn init() {
     vga_buffer::WRITER.lock().clear_buffer(); // So the cursor gets shown even before using clean
     keyboard::register_key_callback(key_pressed);
     print!("$ ");
+    loop {
+        let line = next_line().await;
+        handle_cmd(&line).await;
+        print!("\n$ ");
+        vga_buffer::WRITER.lock().set_column_position(2);
+    }
 }
\ No newline at end of file
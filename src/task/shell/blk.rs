@@ -0,0 +1,108 @@
+use alloc::string::String;
+use crate::{block::{self, cache, SECTOR_SIZE}, print};
+use super::Shell;
+
+pub static CMD: &str = "blk";
+pub static USAGE: &str = "blk [read <dev> <lba> | write <dev> <lba> <text> | cache [<size>[K|M]]]";
+pub static DES: &str = "lists block devices, dumps or overwrites one sector, or shows or sizes the block cache";
+
+pub fn main(shell: &mut Shell, args: &[&str]) {
+    match args {
+        [] => {
+            print!("\n{:<6} {:>10} {:>8}  MODEL", "NAME", "SECTORS", "SIZE");
+            for device in block::devices() {
+                let mib = device.sector_count() * SECTOR_SIZE as u64 / (1024 * 1024);
+                print!("\n{:<6} {:>10} {:>6}M  {}", device.name(), device.sector_count(), mib, device.model());
+            }
+        }
+        ["read", name, lba] => read(shell, name, lba),
+        ["write", name, lba, text @ ..] if !text.is_empty() => write(shell, name, lba, text),
+        ["cache"] => cache_stats(),
+        ["cache", size] => match super::mount::parse_size(size) {
+            Some(bytes) if bytes <= cache::MAX_BUDGET as u64 => cache::CACHE.set_budget(bytes as usize),
+            Some(_) => {
+                print!("\nblk: cache size is limited to {} bytes", cache::MAX_BUDGET);
+                shell.set_status(1);
+            }
+            None => {
+                print!("\nblk: invalid size '{}'", size);
+                shell.set_status(1);
+            }
+        },
+        _ => shell.usage(USAGE),
+    }
+}
+
+fn cache_stats() {
+    let stats = cache::CACHE.stats();
+    print!("\nbudget {} B, used {} B, dirty {} B", stats.budget, stats.used, stats.dirty);
+    print!("\n{} hits, {} misses, {} sectors written back", stats.hits, stats.misses, stats.writebacks);
+}
+
+fn parse_lba(lba: &str) -> Option<u64> {
+    let lba = lba.parse().ok();
+    if lba.is_none() {
+        print!("\nblk: invalid sector number");
+    }
+    lba
+}
+
+fn find(name: &str) -> Option<alloc::sync::Arc<dyn block::BlockDevice>> {
+    let device = block::get(name);
+    if device.is_none() {
+        print!("\nblk: {}: no such device", name);
+    }
+    device
+}
+
+fn read(shell: &mut Shell, name: &str, lba: &str) {
+    let (Some(device), Some(lba)) = (find(name), parse_lba(lba)) else {
+        shell.set_status(1);
+        return;
+    };
+    let mut sector = [0u8; SECTOR_SIZE];
+    if let Err(e) = block::block_on(device.read_sectors(lba, &mut sector)) {
+        print!("\nblk: {}: {}", name, e);
+        shell.set_status(1);
+        return;
+    }
+    for (i, row) in sector.chunks(16).enumerate() {
+        print!("\n{:03x}:", i * 16);
+        for byte in row {
+            print!(" {:02x}", byte);
+        }
+        print!("  ");
+        for &byte in row {
+            print!("{}", if byte.is_ascii_graphic() { byte as char } else { '.' });
+        }
+    }
+}
+
+fn write(shell: &mut Shell, name: &str, lba: &str, words: &[&str]) {
+    let (Some(device), Some(lba)) = (find(name), parse_lba(lba)) else {
+        shell.set_status(1);
+        return;
+    };
+    let mut text = String::new();
+    for (i, word) in words.iter().enumerate() {
+        if i > 0 {
+            text.push(' ');
+        }
+        text.push_str(word);
+    }
+    let mut sector = [0u8; SECTOR_SIZE];
+    let len = text.len().min(SECTOR_SIZE);
+    sector[..len].copy_from_slice(&text.as_bytes()[..len]);
+
+    let result = block::block_on(async {
+        device.write_sectors(lba, &sector).await?;
+        device.flush().await
+    });
+    match result {
+        Ok(()) => print!("\nwrote sector {} of {}", lba, name),
+        Err(e) => {
+            print!("\nblk: {}: {}", name, e);
+            shell.set_status(1);
+        }
+    }
+}
@@ -1,34 +1,32 @@
+use alloc::boxed::Box;
 use alloc::format;
 use alloc::string::ToString;
-use alloc::vec::Vec;
 use crate::print;
-use crate::fs::read;
-use super::PWD;
+use crate::fs::read_async;
+use super::{CommandFuture, Shell};
 
 pub static CMD: &str = "cat";
 pub static USAGE: &str = "cat (path)";
 pub static DES: &str = "displays the content of a file";
 
-fn read_file(path: &str) -> Result<alloc::string::String, alloc::string::String> {
-    let data = read(path)?;
+async fn read_file(path: &str) -> Result<alloc::string::String, alloc::string::String> {
+    let data = read_async(path).await.map_err(|e| e.to_string())?;
     alloc::string::String::from_utf8(data)
         .map_err(|e| format!("Invalid UTF-8: {}", e))
 }
 
-pub fn main(args: &[&str]) {
-    if args.is_empty() || args.len() > 1 {
-        print!("\nUSAGE: cat (path)");
-    } else {
-        let chars: Vec<char> = args[0].chars().collect();
-        let path = if chars[0] == '/' {
-            args[0].to_string()
+pub fn main<'a>(shell: &'a mut Shell, args: &'a [&'a str]) -> CommandFuture<'a> {
+    Box::pin(async move {
+        if args.is_empty() || args.len() > 1 {
+            shell.usage(USAGE);
         } else {
-            format!("{}/{}", PWD, args[0])
-        };
-
-        match read_file(&path) {
-            Ok(content) => print!("\n{}", content),
-            Err(e) => print!("\ncat: {}", e),
+            match read_file(shell.full_path(args[0]).as_str()).await {
+                Ok(content) => print!("\n{}", content),
+                Err(e) => {
+                    print!("\ncat: {}", e);
+                    shell.set_status(1);
+                }
+            }
         }
-    }
-}
\ No newline at end of file
+    })
+}
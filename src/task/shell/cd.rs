@@ -1,102 +1,32 @@
-use alloc::{format, string::{String, ToString}, vec::Vec};
-use crate::{fs, print};
-use super::PWD;
+use alloc::string::String;
+use crate::print;
+use super::Shell;
 
 pub static CMD: &str = "cd";
-pub static USAGE: &str = "cd <path>";
-pub static DES: &str = "Changes the current working directory";
+pub static USAGE: &str = "cd [path | -]";
+pub static DES: &str = "changes the working directory, to / without a path or back with -";
 
-pub fn main(args: &[&str]) {
-    let target = if args.is_empty() {
-        // cd with no args goes to root (or could go to home directory)
-        "/"
-    } else {
-        args[0]
-    };
-    
-    // Build the target path
-    let target_path = resolve_path(target);
-    
-    // Verify the directory exists
-    match fs::list_dir(&target_path) {
-        Ok(_) => {
-            let normalized = normalize_path(&target_path);
-            unsafe { PWD = normalized.leak(); }
-        }
-        Err(_) => {
-            print!("cd: {}: No such directory\n", target);
-        }
-    }
-}
-
-fn resolve_path(path: &str) -> String {
-    if path.starts_with('/') {
-        // Absolute path
-        path.to_string()
-    } else if path == "." {
-        // Current directory
-        unsafe { PWD.to_string() }
-    } else if path == ".." {
-        // Parent directory
-        unsafe {get_parent_dir(PWD)}
-    } else if path.starts_with("..") {
-        // Path starting with ..
-        let remaining = &path[2..].trim_start_matches('/');
-        let parent = unsafe{get_parent_dir(PWD)};
-        if remaining.is_empty() {
-            parent
-        } else {
-            format!("{}/{}", parent, remaining)
-        }
-    } else {
-        // Relative path
-        if unsafe {PWD} == "/" {
-            format!("/{}", path)
-        } else {
-            format!("{}/{}", unsafe{PWD}, path)
+pub fn main(shell: &mut Shell, args: &[&str]) {
+    let target = match args {
+        [] => "/",
+        ["-"] => match shell.var("OLDPWD") {
+            Some(old) => old,
+            None => {
+                print!("\ncd: OLDPWD not set");
+                shell.set_status(1);
+                return;
+            }
+        },
+        [path] => *path,
+        _ => {
+            shell.usage(USAGE);
+            return;
         }
-    }
-}
+    };
 
-fn get_parent_dir(path: &str) -> String {
-    if path == "/" {
-        return "/".to_string();
-    }
-    
-    let parts: Vec<&str> = path.split('/').filter(|s| !s.is_empty()).collect();
-    
-    if parts.is_empty() {
-        return "/".to_string();
-    }
-    
-    if parts.len() == 1 {
-        return "/".to_string();
+    let target = String::from(target);
+    if let Err(e) = shell.set_cwd(&target) {
+        print!("\ncd: {}: {}", target, e);
+        shell.set_status(1);
     }
-    
-    format!("/{}", parts[..parts.len()-1].join("/"))
 }
-
-fn normalize_path(path: &str) -> String {
-    if path == "/" {
-        return "/".to_string();
-    }
-    
-    let parts: Vec<&str> = path.split('/').filter(|s| !s.is_empty()).collect();
-    let mut normalized = Vec::new();
-    
-    for part in parts {
-        match part {
-            "." => continue,
-            ".." => {
-                normalized.pop();
-            }
-            _ => normalized.push(part),
-        }
-    }
-    
-    if normalized.is_empty() {
-        "/".to_string()
-    } else {
-        format!("/{}", normalized.join("/"))
-    }
-}
\ No newline at end of file
@@ -1,9 +1,10 @@
 use crate::vga_buffer;
+use super::Shell;
 
 pub static CMD: &str = "clear";
 pub static USAGE: &str = "clear";
 pub static DES: &str = "Clears the shell";
 
-pub fn main(_args: &[&str]) {
+pub fn main(_shell: &mut Shell, _args: &[&str]) {
     vga_buffer::WRITER.lock().clear_buffer();    
 }
\ No newline at end of file
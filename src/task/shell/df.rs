@@ -0,0 +1,40 @@
+use alloc::format;
+use crate::{fs::{self, FsError}, print};
+use super::Shell;
+
+pub static CMD: &str = "df";
+pub static USAGE: &str = "df";
+pub static DES: &str = "shows size, usage and free space of every mounted filesystem";
+
+pub fn main(shell: &mut Shell, _args: &[&str]) {
+    let mounts = match fs::mounts() {
+        Ok(mounts) => mounts,
+        Err(e) => {
+            print!("\ndf: {}", e);
+            shell.set_status(1);
+            return;
+        }
+    };
+
+    print!("\n{:<10} {:>8} {:>8} {:>8} {:>5} {:>9}  Mounted on", "Filesystem", "Bytes", "Used", "Avail", "Use%", "Inodes");
+    for mount in mounts {
+        match fs::statfs(&mount.path) {
+            Ok(stats) => {
+                let percent = (stats.used_bytes * 100).checked_div(stats.total_bytes).unwrap_or(0);
+                let inodes = format!("{}/{}", stats.used_inodes, stats.total_inodes);
+                print!(
+                    "\n{:<10} {:>8} {:>8} {:>8} {:>4}% {:>9}  {}",
+                    mount.fs_name, stats.total_bytes, stats.used_bytes, stats.free_bytes(), percent, inodes, mount.path
+                );
+            }
+            // Pseudo filesystems hold no data of their own
+            Err(FsError::Unsupported) => {
+                print!("\n{:<10} {:>8} {:>8} {:>8} {:>5} {:>9}  {}", mount.fs_name, "-", "-", "-", "-", "-", mount.path);
+            }
+            Err(e) => {
+                print!("\ndf: {}: {}", mount.path, e);
+                shell.set_status(1);
+            }
+        }
+    }
+}
@@ -0,0 +1,28 @@
+use crate::print;
+use super::Shell;
+
+pub static CMD: &str = "env";
+pub static USAGE: &str = "env [-u <name>] [<name>=<value>...]";
+pub static DES: &str = "lists the session's environment variables, sets them or removes one with -u";
+
+pub fn main(shell: &mut Shell, args: &[&str]) {
+    match args {
+        [] => {
+            for (name, value) in shell.vars() {
+                print!("\n{}={}", name, value);
+            }
+        }
+        ["-u", name] => shell.remove_var(name),
+        assignments => {
+            for assignment in assignments {
+                match assignment.split_once('=') {
+                    Some((name, value)) if !name.is_empty() => shell.set_var(name, value),
+                    _ => {
+                        shell.usage(USAGE);
+                        return;
+                    }
+                }
+            }
+        }
+    }
+}
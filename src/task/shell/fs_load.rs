@@ -0,0 +1,28 @@
+use crate::{fs, interrupts::TIMER_HZ, print};
+use super::Shell;
+
+pub static CMD: &str = "fs-load";
+pub static USAGE: &str = "fs-load [path]";
+pub static DES: &str = "unpacks the next image from the serial port into a directory (default /) in the background";
+
+/// How long the serial line may stay quiet before giving up
+const TIMEOUT_SECS: u64 = 30;
+
+pub fn main(shell: &mut Shell, args: &[&str]) {
+    let path = match args {
+        [] => shell.full_path("/"),
+        [path] => shell.full_path(path),
+        _ => {
+            shell.usage(USAGE);
+            return;
+        }
+    };
+
+    match fs::request_image_load(path.as_str(), TIMEOUT_SECS * TIMER_HZ) {
+        Ok(()) => print!("\nwaiting for an image on serial..."),
+        Err(e) => {
+            print!("\nfs-load: {}: {}", path, e);
+            shell.set_status(1);
+        }
+    }
+}
@@ -0,0 +1,25 @@
+use crate::{fs, print};
+use super::Shell;
+
+pub static CMD: &str = "fs-save";
+pub static USAGE: &str = "fs-save [path]";
+pub static DES: &str = "sends the files below a directory (default /) to the serial port as an image";
+
+pub fn main(shell: &mut Shell, args: &[&str]) {
+    let path = match args {
+        [] => shell.full_path("/"),
+        [path] => shell.full_path(path),
+        _ => {
+            shell.usage(USAGE);
+            return;
+        }
+    };
+
+    match fs::save_image(path.as_str()) {
+        Ok(count) => print!("\nsaved {} entries of {} to serial", count, path),
+        Err(e) => {
+            print!("\nfs-save: {}: {}", path, e);
+            shell.set_status(1);
+        }
+    }
+}
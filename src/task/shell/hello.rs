@@ -1,10 +1,11 @@
 use crate::println;
+use super::Shell;
 
 pub static CMD: &str = "hello";
 pub static USAGE: &str = "hello [name]";
 pub static DES: &str = "displays a hello message for testing";
 
-pub fn main(args: &[&str]) {
+pub fn main(_shell: &mut Shell, args: &[&str]) {
     if args.is_empty() {
         println!("\nHi :)");
     } else {
@@ -0,0 +1,124 @@
+use alloc::{string::String, vec::Vec};
+use core::fmt;
+
+/// Why a command line could not be split into words
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SyntaxError {
+    /// A quote, `'` or `"`, that is never closed
+    UnterminatedQuote(char),
+    /// A backslash with nothing after it to escape
+    TrailingBackslash,
+}
+
+impl fmt::Display for SyntaxError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            SyntaxError::UnterminatedQuote('\'') => f.write_str("unterminated single quote"),
+            SyntaxError::UnterminatedQuote(_) => f.write_str("unterminated double quote"),
+            SyntaxError::TrailingBackslash => f.write_str("backslash at end of line"),
+        }
+    }
+}
+
+/// Splits a command line into words the way a POSIX shell does, without
+/// any expansion
+///
+/// - Spaces and tabs separate words.
+/// - `'...'` keeps everything literally, `"..."` too except that `\"` and
+///   `\\` stand for `"` and `\`.
+/// - A backslash outside quotes takes the next character literally.
+/// - `''` and `""` give an empty word.
+/// - `#` at the start of a word comments out the rest of the line.
+pub fn tokenize(line: &str) -> Result<Vec<String>, SyntaxError> {
+    let mut words = Vec::new();
+    let mut word = String::new();
+    // Quotes can make a word that has no characters, so track it apart
+    let mut in_word = false;
+    let mut chars = line.chars();
+
+    while let Some(c) = chars.next() {
+        match c {
+            ' ' | '\t' => {
+                if in_word {
+                    words.push(core::mem::take(&mut word));
+                    in_word = false;
+                }
+            }
+            '#' if !in_word => break,
+            '\\' => {
+                word.push(chars.next().ok_or(SyntaxError::TrailingBackslash)?);
+                in_word = true;
+            }
+            '\'' => {
+                loop {
+                    match chars.next() {
+                        Some('\'') => break,
+                        Some(c) => word.push(c),
+                        None => return Err(SyntaxError::UnterminatedQuote('\'')),
+                    }
+                }
+                in_word = true;
+            }
+            '"' => {
+                loop {
+                    match chars.next() {
+                        Some('"') => break,
+                        Some('\\') => match chars.next() {
+                            Some(c @ ('"' | '\\')) => word.push(c),
+                            Some(c) => {
+                                word.push('\\');
+                                word.push(c);
+                            }
+                            None => return Err(SyntaxError::UnterminatedQuote('"')),
+                        },
+                        Some(c) => word.push(c),
+                        None => return Err(SyntaxError::UnterminatedQuote('"')),
+                    }
+                }
+                in_word = true;
+            }
+            c => {
+                word.push(c);
+                in_word = true;
+            }
+        }
+    }
+    if in_word {
+        words.push(word);
+    }
+    Ok(words)
+}
+
+#[test_case]
+fn test_tokenize() {
+    let ok = |line: &str| tokenize(line).unwrap();
+
+    assert_eq!(ok("  ls   -r\t/tmp  "), ["ls", "-r", "/tmp"]);
+    assert!(ok("").is_empty());
+    assert!(ok("   ").is_empty());
+
+    // Quotes keep spacing and join with what touches them
+    assert_eq!(ok("touch f \"a  b\""), ["touch", "f", "a  b"]);
+    assert_eq!(ok("echo 'it''s'"), ["echo", "its"]);
+    assert_eq!(ok("a\"b c\"d 'e f'g"), ["ab cd", "e fg"]);
+    assert_eq!(ok("'$x \\n \"'"), ["$x \\n \""]);
+    assert_eq!(ok("\"say \\\"hi\\\" \\\\ \\n\""), ["say \"hi\" \\ \\n"]);
+
+    // Empty arguments survive
+    assert_eq!(ok("a '' \"\" b"), ["a", "", "", "b"]);
+    assert_eq!(ok("''"), [""]);
+
+    // Backslashes outside quotes
+    assert_eq!(ok("a\\ b \\'c\\\\"), ["a b", "'c\\"]);
+    assert_eq!(ok("\\#x"), ["#x"]);
+
+    // Comments only start a word
+    assert_eq!(ok("ls # the root"), ["ls"]);
+    assert_eq!(ok("# nothing"), Vec::<String>::new());
+    assert_eq!(ok("a#b '#c'"), ["a#b", "#c"]);
+
+    assert_eq!(tokenize("touch 'open"), Err(SyntaxError::UnterminatedQuote('\'')));
+    assert_eq!(tokenize("touch \"open"), Err(SyntaxError::UnterminatedQuote('"')));
+    assert_eq!(tokenize("touch \"open\\"), Err(SyntaxError::UnterminatedQuote('"')));
+    assert_eq!(tokenize("ls \\"), Err(SyntaxError::TrailingBackslash));
+}
@@ -0,0 +1,29 @@
+use crate::{fs, print};
+use super::Shell;
+
+pub static CMD: &str = "ln";
+pub static USAGE: &str = "ln [-s] <target> <link>";
+pub static DES: &str = "creates a hard link, or a symbolic link with -s";
+
+pub fn main(shell: &mut Shell, args: &[&str]) {
+    let symbolic = args.first() == Some(&"-s");
+    let args = if symbolic { &args[1..] } else { args };
+
+    if args.len() != 2 {
+        shell.usage(USAGE);
+        return;
+    }
+
+    let link = shell.full_path(args[1]);
+    let result = if symbolic {
+        // The target is stored as typed, relative targets stay relative
+        fs::symlink(args[0], link.as_str())
+    } else {
+        fs::link(shell.full_path(args[0]).as_str(), link.as_str())
+    };
+
+    if let Err(e) = result {
+        print!("\nln: {} -> {}: {}", args[1], args[0], e);
+        shell.set_status(1);
+    }
+}
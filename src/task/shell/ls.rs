@@ -1,31 +1,21 @@
-use alloc::{format, string::ToString};
 use crate::{fs, print};
-use super::PWD;
+use super::Shell;
 
 pub static CMD: &str = "ls";
 pub static USAGE: &str = "ls [path]";
 pub static DES: &str = "Lists directory contents";
 
-pub fn main(args: &[&str]) {
-    // Use PWD if no path provided
+pub fn main(shell: &mut Shell, args: &[&str]) {
+    // Use the working directory if no path provided
     let path = if args.is_empty() {
-        PWD
+        "."
     } else {
         args[0]
     };
     
-    // Resolve to full path if relative
-    let full_path = if path.starts_with('/') {
-        path.to_string()
-    } else {
-        if PWD == "/" {
-            format!("/{}", path)
-        } else {
-            format!("{}/{}", PWD, path)
-        }
-    };
+    let full_path = shell.full_path(path);
     
-    match fs::list_dir(&full_path) {
+    match fs::list_dir(full_path.as_str()) {
         Ok(contents) => {
             if contents.is_empty() {
                 print!("\n(empty directory)");
@@ -37,6 +27,7 @@ pub fn main(args: &[&str]) {
         }
         Err(e) => {
             print!("\nError listing '{}': {}", full_path, e);
+            shell.set_status(1);
         }
     }
 }
\ No newline at end of file
@@ -0,0 +1,42 @@
+use alloc::{format, string::String};
+use crate::{block::{self, BlockDevice, SECTOR_SIZE}, print};
+use super::Shell;
+
+pub static CMD: &str = "lsblk";
+pub static USAGE: &str = "lsblk";
+pub static DES: &str = "lists disks with their partitions, sizes and partition types";
+
+pub fn main(_shell: &mut Shell, _args: &[&str]) {
+    let partitions = block::partitions();
+    print!("\n{:<10} {:>7} {:>10}  TYPE  PARTTYPE", "NAME", "SIZE", "START");
+    for disk in block::devices() {
+        if partitions.iter().any(|p| p.name() == disk.name()) {
+            continue;
+        }
+        print!("\n{:<10} {:>7} {:>10}  disk  {}", disk.name(), size(disk.sector_count()), "", disk.model());
+        for part in partitions.iter().filter(|p| p.disk() == disk.name()) {
+            let name = format!("`-{}", part.name());
+            print!("\n{:<10} {:>7} {:>10}  part  {}", name, size(part.sector_count()), part.start(), part.kind());
+            if !part.label().is_empty() {
+                print!(" {}", part.label());
+            }
+        }
+    }
+}
+
+/// Sector count as "512B", "16K" or "1.5M"
+fn size(sectors: u64) -> String {
+    let bytes = sectors * SECTOR_SIZE as u64;
+    let mut unit = 0;
+    let mut scaled = bytes * 10;
+    while scaled >= 10 * 1024 && unit < 3 {
+        scaled /= 1024;
+        unit += 1;
+    }
+    let suffix = ["B", "K", "M", "G"][unit];
+    if scaled.is_multiple_of(10) || scaled >= 100 {
+        format!("{}{}", scaled / 10, suffix)
+    } else {
+        format!("{}.{}{}", scaled / 10, scaled % 10, suffix)
+    }
+}
@@ -1,55 +1,45 @@
-use alloc::{format, string::{String, ToString}, vec::Vec};
-use crate::{fs, print};
-use super::PWD;
+use alloc::vec::Vec;
+use crate::{fs::{self, FsError, PathBuf}, print};
+use super::Shell;
 
 pub static CMD: &str = "mkdir";
 pub static USAGE: &str = "mkdir <path>";
 pub static DES: &str = "creates one or multiple new directories";
 
-pub fn main(args: &[&str]) {
+pub fn main(shell: &mut Shell, args: &[&str]) {
     if args.is_empty() {
-        print!("\nUSAGE: {}\n", USAGE);
+        shell.usage(USAGE);
         return;
     }
     
     for arg in args {
-        if let Err(e) = create_directory(arg) {
+        if let Err(e) = create_directory(shell, arg) {
             print!("Error creating '{}': {}\n", arg, e);
+            shell.set_status(1);
         }
     }
 }
 
-fn create_directory(path: &str) -> Result<(), &'static str> {
-    if path.is_empty() {
-        return Err("path cannot be empty");
-    }
-    
-    let is_absolute = path.starts_with('/');
-    let parts: Vec<&str> = path.split('/').filter(|s| !s.is_empty()).collect();
+fn create_directory(shell: &Shell, path: &str) -> Result<(), FsError> {
+    let full_path = shell.full_path(path);
+    // Skip the root, the rest of a canonical path are plain names
+    let parts: Vec<&str> = full_path.components().skip(1).map(|c| c.as_str()).collect();
     
     if parts.is_empty() {
-        return Err("invalid path");
+        return Err(FsError::InvalidPath);
     }
-    
-    // Build the full path
-    let mut current_dir = if is_absolute {
-        String::from("")
-    } else {
-        PWD.to_string()
-    };
+    let last = parts.len() - 1;
     
     // Create each directory in the path
-    for part in parts {
-        current_dir = if current_dir.is_empty() || current_dir == "/" {
-            format!("/{}", part)
-        } else {
-            format!("{}/{}", current_dir, part)
-        };
+    let mut current_dir = PathBuf::from("/");
+    for (i, part) in parts.into_iter().enumerate() {
+        current_dir.push(part);
         
-        // Attempt to create the directory
-        if let Err(_) = fs::create_dir(&current_dir) {
-            // Directory might already exist, which is typically fine
-            // or there was an actual error - depends on your fs implementation
+        // Intermediate directories may already exist, the last one may not
+        match fs::create_dir(current_dir.as_str()) {
+            Ok(()) => {}
+            Err(FsError::AlreadyExists) if i != last => {}
+            Err(e) => return Err(e),
         }
     }
     
@@ -0,0 +1,97 @@
+use crate::{block, fs::{self, RamFsLimits}, print};
+use super::Shell;
+
+pub static CMD: &str = "mount";
+pub static USAGE: &str = "mount [-t ramfs [-s <size>[K|M]] <path> | -t fat|ext2 <device> <path>]";
+pub static DES: &str = "lists mounted filesystems or mounts a new one on a directory";
+
+pub fn main(shell: &mut Shell, args: &[&str]) {
+    if args.is_empty() {
+        match fs::mounts() {
+            Ok(mounts) => {
+                for mount in mounts {
+                    print!("\n{} on {}", mount.fs_name, mount.path);
+                }
+            }
+            Err(e) => {
+                print!("\nmount: {}", e);
+                shell.set_status(1);
+            }
+        }
+        return;
+    }
+
+    if let ["-t", kind @ ("fat" | "ext2"), device, path] = args {
+        mount_device(shell, kind, device, path);
+        return;
+    }
+
+    let (size, path) = match args {
+        ["-t", _, path] => (None, *path),
+        ["-t", _, "-s", size, path] => match parse_size(size) {
+            Some(size) => (Some(size), *path),
+            None => {
+                print!("\nmount: invalid size '{}'", size);
+                shell.set_status(1);
+                return;
+            }
+        },
+        _ => {
+            shell.usage(USAGE);
+            return;
+        }
+    };
+
+    let new_fs = match (args[1], size) {
+        ("ramfs", None) => fs::new_ramfs(),
+        ("ramfs", Some(max_bytes)) => fs::new_ramfs_with_limits(RamFsLimits {
+            max_bytes,
+            ..RamFsLimits::default()
+        }),
+        (other, _) => {
+            print!("\nmount: unknown filesystem type '{}'", other);
+            shell.set_status(1);
+            return;
+        }
+    };
+
+    if let Err(e) = fs::mount(shell.full_path(path).as_str(), new_fs) {
+        print!("\nmount: {}: {}", path, e);
+        shell.set_status(1);
+    }
+}
+
+/// Mounts the volume on the block device `name`, e.g. "hdb"
+fn mount_device(shell: &mut Shell, kind: &str, name: &str, path: &str) {
+    let Some(device) = block::get(name) else {
+        print!("\nmount: {}: no such device", name);
+        shell.set_status(1);
+        return;
+    };
+    let opened = match kind {
+        "fat" => fs::new_fat(device),
+        _ => fs::new_ext2(device),
+    };
+    let volume = match opened {
+        Ok(volume) => volume,
+        Err(e) => {
+            print!("\nmount: {}: {}", name, e);
+            shell.set_status(1);
+            return;
+        }
+    };
+    if let Err(e) = fs::mount(shell.full_path(path).as_str(), volume) {
+        print!("\nmount: {}: {}", path, e);
+        shell.set_status(1);
+    }
+}
+
+/// Parses "512", "16K" or "1M" into bytes
+pub(super) fn parse_size(arg: &str) -> Option<u64> {
+    let (digits, unit) = match arg.as_bytes().last()? {
+        b'K' | b'k' => (&arg[..arg.len() - 1], 1024),
+        b'M' | b'm' => (&arg[..arg.len() - 1], 1024 * 1024),
+        _ => (arg, 1),
+    };
+    digits.parse::<u64>().ok()?.checked_mul(unit)
+}
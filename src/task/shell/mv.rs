@@ -0,0 +1,29 @@
+use crate::{fs, print};
+use super::Shell;
+
+pub static CMD: &str = "mv";
+pub static USAGE: &str = "mv <source> <target>";
+pub static DES: &str = "renames a file or directory, or moves it into a directory";
+
+pub fn main(shell: &mut Shell, args: &[&str]) {
+    if args.len() != 2 {
+        shell.usage(USAGE);
+        return;
+    }
+
+    let from = shell.full_path(args[0]);
+    let mut to = shell.full_path(args[1]);
+
+    // Moving onto a directory puts the source inside it
+    if let Ok(meta) = fs::stat(to.as_str())
+        && meta.is_dir()
+        && let Some(name) = from.file_name()
+    {
+        to.push(name);
+    }
+
+    if let Err(e) = fs::rename(from.as_str(), to.as_str()) {
+        print!("\nmv: {} -> {}: {}", args[0], args[1], e);
+        shell.set_status(1);
+    }
+}
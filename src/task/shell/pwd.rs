@@ -0,0 +1,10 @@
+use crate::print;
+use super::Shell;
+
+pub static CMD: &str = "pwd";
+pub static USAGE: &str = "pwd";
+pub static DES: &str = "prints the working directory";
+
+pub fn main(shell: &mut Shell, _args: &[&str]) {
+    print!("\n{}", shell.cwd());
+}
@@ -0,0 +1,29 @@
+use crate::{fs, print};
+use super::Shell;
+
+pub static CMD: &str = "rm";
+pub static USAGE: &str = "rm [-r] <path>...";
+pub static DES: &str = "removes files, or whole directories with -r";
+
+pub fn main(shell: &mut Shell, args: &[&str]) {
+    let recursive = args.first() == Some(&"-r");
+    let paths = if recursive { &args[1..] } else { args };
+
+    if paths.is_empty() {
+        shell.usage(USAGE);
+        return;
+    }
+
+    for path in paths {
+        let full = shell.full_path(path);
+        let result = match fs::symlink_metadata(full.as_str()) {
+            Ok(meta) if meta.is_dir() && recursive => fs::remove_dir_all(full.as_str()),
+            Ok(_) => fs::remove_file(full.as_str()),
+            Err(e) => Err(e),
+        };
+        if let Err(e) = result {
+            print!("\nrm: {}: {}", path, e);
+            shell.set_status(1);
+        }
+    }
+}
@@ -0,0 +1,20 @@
+use crate::{fs, print};
+use super::Shell;
+
+pub static CMD: &str = "rmdir";
+pub static USAGE: &str = "rmdir <path>...";
+pub static DES: &str = "removes empty directories";
+
+pub fn main(shell: &mut Shell, args: &[&str]) {
+    if args.is_empty() {
+        shell.usage(USAGE);
+        return;
+    }
+
+    for path in args {
+        if let Err(e) = fs::remove_dir(shell.full_path(path).as_str()) {
+            print!("\nrmdir: {}: {}", path, e);
+            shell.set_status(1);
+        }
+    }
+}
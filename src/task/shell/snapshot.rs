@@ -0,0 +1,32 @@
+use crate::{fs, print};
+use super::Shell;
+
+pub static CMD: &str = "snapshot";
+pub static USAGE: &str = "snapshot [-r|-d] [name]";
+pub static DES: &str = "lists, takes (no flag), restores (-r) or deletes (-d) snapshots of the root filesystem";
+
+pub fn main(shell: &mut Shell, args: &[&str]) {
+    let result = match args {
+        [] => match fs::snapshots() {
+            Ok(names) => {
+                for name in names {
+                    print!("\n{}", name);
+                }
+                return;
+            }
+            Err(e) => Err(e),
+        },
+        [name] => fs::snapshot(name),
+        ["-r", name] => fs::restore(name),
+        ["-d", name] => fs::remove_snapshot(name),
+        _ => {
+            shell.usage(USAGE);
+            return;
+        }
+    };
+
+    if let Err(e) = result {
+        print!("\nsnapshot: {}", e);
+        shell.set_status(1);
+    }
+}
@@ -0,0 +1,39 @@
+use crate::{fs::{self, FileType}, print};
+use super::Shell;
+
+pub static CMD: &str = "stat";
+pub static USAGE: &str = "stat <path>";
+pub static DES: &str = "shows size, inode, links, mode and timestamps of a file";
+
+pub fn main(shell: &mut Shell, args: &[&str]) {
+    if args.is_empty() {
+        shell.usage(USAGE);
+        return;
+    }
+
+    for path in args {
+        let full_path = shell.full_path(path);
+
+        match fs::symlink_metadata(full_path.as_str()) {
+            Ok(meta) => {
+                let kind = match meta.kind {
+                    FileType::File => "file",
+                    FileType::Directory => "directory",
+                    FileType::Symlink => "symbolic link",
+                    FileType::CharDevice => "character device",
+                };
+                print!("\n  File: {}", full_path);
+                if let Ok(target) = fs::read_link(full_path.as_str()) {
+                    print!(" -> {}", target);
+                }
+                print!("\n  Type: {}  Inode: {}  Links: {}", kind, meta.ino, meta.nlink);
+                print!("\n  Size: {}  Mode: {:o}", meta.size, meta.mode);
+                print!("\nAccess: {}  Modify: {}  Create: {}", meta.accessed, meta.modified, meta.created);
+            }
+            Err(e) => {
+                print!("\nstat: {}: {}", path, e);
+                shell.set_status(1);
+            }
+        }
+    }
+}
@@ -0,0 +1,13 @@
+use crate::{block, print};
+use super::Shell;
+
+pub static CMD: &str = "sync";
+pub static USAGE: &str = "sync";
+pub static DES: &str = "writes cached disk sectors back to their devices";
+
+pub fn main(shell: &mut Shell, _args: &[&str]) {
+    if let Err(e) = block::block_on(block::cache::CACHE.sync()) {
+        print!("\nsync: {}", e);
+        shell.set_status(1);
+    }
+}
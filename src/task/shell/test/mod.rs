@@ -1,4 +1,4 @@
-use crate::print;
+use super::Shell;
 
 pub static CMD: &str = "test";
 pub static USAGE: &str = "test [test]";
@@ -7,9 +7,9 @@ pub static DES: &str = "tests some features like: VGA graphics mode";
 mod anim;
 mod graphics;
 
-pub fn main(args: &[&str]) {
+pub fn main(shell: &mut Shell, args: &[&str]) {
     if args.is_empty() {
-        print!("Usage: {}", USAGE);
+        shell.usage(USAGE);
         return;
     }
 
@@ -1,95 +1,37 @@
-use alloc::{format, string::{String, ToString}};
-use crate::{fs, print};
-use super::PWD;
+use crate::{fs::{self, FsError}, print};
+use super::Shell;
 
 pub static CMD: &str = "touch";
 pub static USAGE: &str = "touch <path> [\"content\"]";
 pub static DES: &str = "creates a new file or updates timestamp, optionally with content";
 
-pub fn main(args: &[&str]) {
-    if args.is_empty() {
-        print!("\nUSAGE: {}\n", USAGE);
-        return;
-    }
-    
-    // Parse arguments: first is path, rest might be content in quotes
-    let path = args[0];
-    let content = if args.len() > 1 {
-        parse_content(&args[1..])
-    } else {
-        String::new()
+pub fn main(shell: &mut Shell, args: &[&str]) {
+    // Content with spaces has to be quoted, `""` empties the file
+    let (path, content) = match args {
+        [path] => (*path, None),
+        [path, content] => (*path, Some(content.as_bytes())),
+        _ => {
+            shell.usage(USAGE);
+            return;
+        }
     };
-    
-    if let Err(e) = create_file(path, content.as_bytes()) {
-        print!("Error creating '{}': {}\n", path, e);
-    }
-}
 
-fn parse_content(args: &[&str]) -> String {
-    // Join all remaining arguments and handle quotes
-    let joined = args.join(" ");
-    
-    // Remove surrounding quotes if present
-    if (joined.starts_with('"') && joined.ends_with('"')) 
-        || (joined.starts_with('\'') && joined.ends_with('\'')) {
-        if joined.len() >= 2 {
-            return joined[1..joined.len()-1].to_string();
-        }
+    if let Err(e) = create_file(shell, path, content) {
+        print!("Error creating '{}': {}\n", path, e);
+        shell.set_status(1);
     }
-    
-    joined
 }
 
-fn create_file(path: &str, content: &[u8]) -> Result<(), String> {
+fn create_file(shell: &Shell, path: &str, content: Option<&[u8]>) -> Result<(), FsError> {
     if path.is_empty() {
-        return Err("path cannot be empty".to_string());
+        return Err(FsError::InvalidPath);
     }
     
-    let is_absolute = path.starts_with('/');
+    let full_path = shell.full_path(path);
     
-    // Build the full path
-    let full_path = if is_absolute {
-        path.to_string()
-    } else {
-        if PWD == "/" {
-            format!("/{}", path)
-        } else {
-            format!("{}/{}", PWD, path)
-        }
-    };
-    
-    // Write the file (creates it if it doesn't exist)
-    fs::write(&full_path, content)
-}
-
-// Alternative version if you want to create multiple files at once
-pub fn main_multi(args: &[&str]) {
-    if args.is_empty() {
-        print!("\nUSAGE: {}\n", USAGE);
-        return;
-    }
-    
-    // Find where content starts (look for quoted string)
-    let mut content_start = None;
-    for (i, arg) in args.iter().enumerate() {
-        if arg.starts_with('"') || arg.starts_with('\'') {
-            content_start = Some(i);
-            break;
-        }
+    // Without content only the timestamps of an existing file change
+    match content {
+        Some(content) => fs::write(full_path.as_str(), content),
+        None => fs::touch(full_path.as_str()),
     }
-    
-    let (paths, content) = if let Some(idx) = content_start {
-        let paths = &args[..idx];
-        let content = parse_content(&args[idx..]);
-        (paths, content)
-    } else {
-        (args, String::new())
-    };
-    
-    // Create each file with the same content
-    for path in paths {
-        if let Err(e) = create_file(path, content.as_bytes()) {
-            print!("Error creating '{}': {}\n", path, e);
-        }
-    }
-}
\ No newline at end of file
+}
@@ -0,0 +1,18 @@
+use crate::{fs, print};
+use super::Shell;
+
+pub static CMD: &str = "umount";
+pub static USAGE: &str = "umount <path>";
+pub static DES: &str = "detaches the filesystem mounted on a directory";
+
+pub fn main(shell: &mut Shell, args: &[&str]) {
+    if args.len() != 1 {
+        shell.usage(USAGE);
+        return;
+    }
+
+    if let Err(e) = fs::umount(shell.full_path(args[0]).as_str()) {
+        print!("\numount: {}: {}", args[0], e);
+        shell.set_status(1);
+    }
+}